@@ -15,9 +15,15 @@ fn impl_piped_command(ast: &syn::DeriveInput) -> TokenStream {
     let gen = quote! {
         #[async_trait::async_trait]
         impl StreamablePlugin for #name {
+            async fn init(&self, profile: &bot_rs_core::profile::Profile)
+            -> Result<(), bot_rs_core::plugin::PluginError> {
+                Plugin::init(self, profile).await?;
+                Ok(())
+            }
+
             async fn stream(
                 &self,
-                mut input: futures::channel::mpsc::UnboundedReceiver<Message>,
+                mut input: futures::channel::mpsc::Receiver<Message>,
                 mut output: futures::channel::mpsc::UnboundedSender<Vec<Message>>)
             -> Result<(), bot_rs_core::plugin::PluginError> {
                     // Read next message from input channel
@@ -30,6 +36,22 @@ fn impl_piped_command(ast: &syn::DeriveInput) -> TokenStream {
                     Ok(())
             }
 
+            async fn shutdown(&self, deadline: std::time::Instant)
+            -> Result<(), bot_rs_core::plugin::PluginError> {
+                Plugin::shutdown(self, deadline).await?;
+                Ok(())
+            }
+
+            async fn health(&self) -> bot_rs_core::plugin::PluginHealth {
+                Plugin::health(self).await
+            }
+
+            fn validate_config(&self, config: Option<&bot_rs_core::plugin::PluginConfig>)
+            -> Result<(), bot_rs_core::plugin::PluginError> {
+                Plugin::validate_config(self, config)?;
+                Ok(())
+            }
+
             fn info(&self) -> PluginInfo {
                 Plugin::info(self)
             }
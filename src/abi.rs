@@ -0,0 +1,659 @@
+//! Stable C ABI for plugins.
+//!
+//! Plugins exported with [export_command!] share Rust types like trait objects, futures and
+//! channels with the plugin-loader. As their layout isn't stable, those plugins only load into
+//! plugin-loaders built with the exact same rustc and core version.
+//!
+//! Plugins exported with [export_plugin!] only exchange `#[repr(C)]` types with the
+//! plugin-loader. Messages and results are passed as JSON through a [PluginVTable]. These
+//! plugins load into every plugin-loader with the same [ABI_VERSION] and a semver-compatible
+//! [CORE_VERSION](crate::CORE_VERSION), independent of the rustc version used to build them.
+//!
+//! Only [Plugin] implementations can be exported this way as asynchronous streams can't be
+//! shared over the C ABI. Every call of [Plugin::call] and the other lifecycle methods blocks a
+//! thread of the plugin-loader until the call is done.
+//!
+//! The function creating the plugin gets a copy of the [Profile] owning the plugin or `None` if
+//! the plugins aren't loaded for a profile (see [crate::plugins::Plugins::for_profile]). As the
+//! [crate::context::Context] can't be shared over the C ABI, later changes of the profile are
+//! only passed to [Plugin::init].
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! // Exports the plugin created by `HelloPlugin::new(profile: Option<Profile>)` through the
+//! // stable ABI
+//! bot_rs_core::export_plugin!(HelloPlugin::new);
+//! ```
+
+use crate::plugin::{Plugin, PluginConfig};
+use crate::profile::Profile;
+use crate::Message;
+use core::fmt;
+use serde::Serialize;
+use std::error::Error;
+use std::ffi::c_void;
+use std::fmt::{Display, Formatter};
+use std::mem::ManuallyDrop;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::time::{Duration, Instant};
+use std::{slice, str};
+
+/// Version of the plugin ABI. Increased on every change to the `#[repr(C)]` types of this
+/// module.
+///
+/// - `1`: `info`, `call`, `free_buffer` and `drop` entries of the [PluginVTable].
+/// - `2`: `init`, `shutdown`, `health` and `validate_config` entries of the [PluginVTable] and
+///   the profile passed to [PluginDeclaration::create].
+pub const ABI_VERSION: u32 = 2;
+
+/// Name of the symbol [export_plugin!] exports the [PluginDeclaration] as.
+pub const DECLARATION_SYMBOL: &[u8] = b"bot_rs_plugin_declaration\0";
+
+/// Returns if a plugin built against core version `plugin` can be loaded by a plugin-loader
+/// built against core version `loader`.
+///
+/// Versions are compatible if their major versions match. For versions `0.x` their minor
+/// versions have to match too. Pre-release and build metadata are ignored.
+pub fn is_compatible(plugin: &str, loader: &str) -> bool {
+    fn parse(version: &str) -> Option<(u64, u64)> {
+        let version = version.split(&['-', '+'][..]).next()?;
+        let mut parts = version.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        Some((major, minor))
+    }
+
+    match (parse(plugin), parse(loader)) {
+        (Some((0, plugin_minor)), Some((0, loader_minor))) => plugin_minor == loader_minor,
+        (Some((plugin_major, _)), Some((loader_major, _))) => plugin_major == loader_major,
+        _ => false,
+    }
+}
+
+/// Error returned by a plugin through the stable ABI.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AbiError(pub String);
+
+impl Display for AbiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for AbiError {}
+
+/// Borrowed UTF-8 string passed over the C ABI.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct FfiStr {
+    ptr: *const u8,
+    len: usize,
+}
+
+impl FfiStr {
+    pub const fn from_static(s: &'static str) -> Self {
+        FfiStr {
+            ptr: s.as_ptr(),
+            len: s.len(),
+        }
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        FfiStr {
+            ptr: bytes.as_ptr(),
+            len: bytes.len(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The string has to be valid for the returned lifetime.
+    pub unsafe fn as_bytes<'a>(&self) -> &'a [u8] {
+        slice::from_raw_parts(self.ptr, self.len)
+    }
+
+    /// # Safety
+    ///
+    /// The string has to be valid for the returned lifetime.
+    pub unsafe fn as_str<'a>(&self) -> Result<&'a str, str::Utf8Error> {
+        str::from_utf8(self.as_bytes())
+    }
+}
+
+/// Owned byte buffer passed over the C ABI. Has to be freed by the side which allocated it.
+#[repr(C)]
+#[derive(Debug)]
+pub struct FfiBuffer {
+    ptr: *mut u8,
+    len: usize,
+    capacity: usize,
+}
+
+impl FfiBuffer {
+    fn from_vec(vec: Vec<u8>) -> Self {
+        let mut vec = ManuallyDrop::new(vec);
+        FfiBuffer {
+            ptr: vec.as_mut_ptr(),
+            len: vec.len(),
+            capacity: vec.capacity(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Has to be called on the side which allocated the buffer.
+    unsafe fn into_vec(self) -> Vec<u8> {
+        Vec::from_raw_parts(self.ptr, self.len, self.capacity)
+    }
+
+    /// # Safety
+    ///
+    /// The buffer must not be freed yet.
+    pub unsafe fn as_bytes(&self) -> &[u8] {
+        slice::from_raw_parts(self.ptr, self.len)
+    }
+}
+
+/// Functions to interact with a plugin instance.
+///
+/// - `info` returns the [crate::plugin::PluginInfo] as JSON,
+/// - `call` takes a [Message] as JSON and returns `Result<Vec<Message>, String>` as JSON,
+/// - `free_buffer` frees buffers returned by the other functions,
+/// - `drop` drops the plugin instance,
+/// - `init` takes the [Profile] as JSON and returns `Result<(), String>` as JSON,
+/// - `shutdown` takes the milliseconds until the shutdown deadline and returns
+///   `Result<(), String>` as JSON,
+/// - `health` returns `Result<PluginHealth, String>` as JSON and
+/// - `validate_config` takes the `Option<PluginConfig>` as JSON and returns
+///   `Result<(), String>` as JSON.
+///
+/// Entries are only appended and documented with the [ABI_VERSION] adding them.
+#[repr(C)]
+#[derive(Debug)]
+pub struct PluginVTable {
+    pub instance: *mut c_void,
+    pub info: unsafe extern "C" fn(instance: *const c_void) -> FfiBuffer,
+    pub call: unsafe extern "C" fn(instance: *const c_void, message: FfiStr) -> FfiBuffer,
+    pub free_buffer: unsafe extern "C" fn(buffer: FfiBuffer),
+    pub drop: unsafe extern "C" fn(instance: *mut c_void),
+    pub init: unsafe extern "C" fn(instance: *const c_void, profile: FfiStr) -> FfiBuffer,
+    pub shutdown: unsafe extern "C" fn(instance: *const c_void, timeout_ms: u64) -> FfiBuffer,
+    pub health: unsafe extern "C" fn(instance: *const c_void) -> FfiBuffer,
+    pub validate_config: unsafe extern "C" fn(instance: *const c_void, config: FfiStr) -> FfiBuffer,
+}
+
+impl PluginVTable {
+    /// Creates the vtable for a plugin instance.
+    pub fn new<P>(plugin: P) -> Self
+    where
+        P: Plugin + 'static,
+        P::Error: Display,
+    {
+        PluginVTable {
+            instance: Box::into_raw(Box::new(plugin)) as *mut c_void,
+            info: plugin_info::<P>,
+            call: plugin_call::<P>,
+            free_buffer: plugin_free_buffer,
+            drop: plugin_drop::<P>,
+            init: plugin_init::<P>,
+            shutdown: plugin_shutdown::<P>,
+            health: plugin_health::<P>,
+            validate_config: plugin_validate_config::<P>,
+        }
+    }
+
+    /// Creates the vtable for the plugin instance returned by `create` for the profile passed
+    /// by the plugin-loader as JSON. Used by [export_plugin!].
+    ///
+    /// Aborts the process if `create` panics as panics must not unwind into the plugin-loader.
+    ///
+    /// # Safety
+    ///
+    /// `profile` has to be valid for the duration of the call.
+    pub unsafe fn create<P, F>(profile: FfiStr, create: F) -> Self
+    where
+        P: Plugin + 'static,
+        P::Error: Display,
+        F: FnOnce(Option<Profile>) -> P,
+    {
+        // The plugin-loader serializes the profile with a compatible core version
+        let profile = serde_json::from_slice(profile.as_bytes()).unwrap_or(None);
+        catch_unwind(AssertUnwindSafe(|| PluginVTable::new(create(profile))))
+            .unwrap_or_else(|_| std::process::abort())
+    }
+}
+
+/// Declaration exported by plugins through [export_plugin!].
+///
+/// `create` takes the `Option<Profile>` owning the plugin as JSON.
+#[repr(C)]
+#[derive(Debug)]
+pub struct PluginDeclaration {
+    pub abi_version: u32,
+    pub core_version: FfiStr,
+    pub create: unsafe extern "C" fn(profile: FfiStr) -> PluginVTable,
+}
+
+// Only contains a pointer to a static string
+unsafe impl Sync for PluginDeclaration {}
+
+unsafe extern "C" fn plugin_info<P: Plugin>(instance: *const c_void) -> FfiBuffer {
+    let plugin = &*(instance as *const P);
+    let json = catch_unwind(AssertUnwindSafe(|| serde_json::to_vec(&plugin.info())))
+        .ok()
+        .and_then(Result::ok)
+        .unwrap_or_default();
+    FfiBuffer::from_vec(json)
+}
+
+/// Returns the result of `f` as JSON. Panics are returned as errors.
+fn respond<T, F>(f: F) -> FfiBuffer
+where
+    T: Serialize,
+    F: FnOnce() -> Result<T, String>,
+{
+    let result =
+        catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| Err("plugin panicked".to_string()));
+    let json = serde_json::to_vec(&result).unwrap_or_default();
+    FfiBuffer::from_vec(json)
+}
+
+unsafe extern "C" fn plugin_call<P>(instance: *const c_void, message: FfiStr) -> FfiBuffer
+where
+    P: Plugin,
+    P::Error: Display,
+{
+    let plugin = &*(instance as *const P);
+    respond(|| {
+        let message: Message =
+            serde_json::from_slice(message.as_bytes()).map_err(|why| why.to_string())?;
+        futures::executor::block_on(plugin.call(message)).map_err(|why| why.to_string())
+    })
+}
+
+unsafe extern "C" fn plugin_init<P>(instance: *const c_void, profile: FfiStr) -> FfiBuffer
+where
+    P: Plugin,
+    P::Error: Display,
+{
+    let plugin = &*(instance as *const P);
+    respond(|| {
+        let profile: Profile =
+            serde_json::from_slice(profile.as_bytes()).map_err(|why| why.to_string())?;
+        futures::executor::block_on(plugin.init(&profile)).map_err(|why| why.to_string())
+    })
+}
+
+unsafe extern "C" fn plugin_shutdown<P>(instance: *const c_void, timeout_ms: u64) -> FfiBuffer
+where
+    P: Plugin,
+    P::Error: Display,
+{
+    let plugin = &*(instance as *const P);
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+    respond(|| {
+        futures::executor::block_on(plugin.shutdown(deadline)).map_err(|why| why.to_string())
+    })
+}
+
+unsafe extern "C" fn plugin_health<P: Plugin>(instance: *const c_void) -> FfiBuffer {
+    let plugin = &*(instance as *const P);
+    respond(|| Ok(futures::executor::block_on(plugin.health())))
+}
+
+unsafe extern "C" fn plugin_validate_config<P>(instance: *const c_void, config: FfiStr) -> FfiBuffer
+where
+    P: Plugin,
+    P::Error: Display,
+{
+    let plugin = &*(instance as *const P);
+    respond(|| {
+        let config: Option<PluginConfig> =
+            serde_json::from_slice(config.as_bytes()).map_err(|why| why.to_string())?;
+        plugin
+            .validate_config(config.as_ref())
+            .map_err(|why| why.to_string())
+    })
+}
+
+unsafe extern "C" fn plugin_free_buffer(buffer: FfiBuffer) {
+    drop(buffer.into_vec());
+}
+
+unsafe extern "C" fn plugin_drop<P>(instance: *mut c_void) {
+    // Panics must not unwind into the plugin-loader
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        drop(Box::from_raw(instance as *mut P));
+    }));
+}
+
+/// Exports a [Plugin] through the stable ABI. Takes a function creating the plugin instance
+/// from the `Option<Profile>` owning it.
+///
+/// Initializing loggers and other dependencies should be done in this function.
+#[macro_export]
+macro_rules! export_plugin {
+    ($create:expr) => {
+        #[doc(hidden)]
+        #[no_mangle]
+        #[allow(non_upper_case_globals)]
+        pub static bot_rs_plugin_declaration: $crate::abi::PluginDeclaration =
+            $crate::abi::PluginDeclaration {
+                abi_version: $crate::abi::ABI_VERSION,
+                core_version: $crate::abi::FfiStr::from_static($crate::CORE_VERSION),
+                create: {
+                    unsafe extern "C" fn create(
+                        profile: $crate::abi::FfiStr,
+                    ) -> $crate::abi::PluginVTable {
+                        $crate::abi::PluginVTable::create(profile, $create)
+                    }
+                    create
+                },
+            };
+    };
+}
+
+#[cfg(feature = "plugin-loader")]
+pub(crate) use loader::AbiPlugin;
+
+#[cfg(feature = "plugin-loader")]
+mod loader {
+    use crate::abi::{AbiError, FfiBuffer, FfiStr, PluginVTable};
+    use crate::plugin::{PluginConfig, PluginError, PluginHealth, PluginInfo, StreamablePlugin};
+    use crate::profile::Profile;
+    use crate::Message;
+    use futures::channel::mpsc::{Receiver, UnboundedSender};
+    use futures::{SinkExt, StreamExt};
+    use libloading::Library;
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    /// Plugin instance created through the stable ABI. Dropped before its library.
+    #[derive(Debug)]
+    struct AbiInstance {
+        vtable: PluginVTable,
+        _lib: Arc<Option<Library>>,
+    }
+
+    // Plugins exported through the stable ABI are Send + Sync
+    unsafe impl Send for AbiInstance {}
+    unsafe impl Sync for AbiInstance {}
+
+    impl AbiInstance {
+        fn info(&self) -> Result<PluginInfo, AbiError> {
+            let buffer = unsafe { (self.vtable.info)(self.vtable.instance) };
+            self.read(buffer)
+        }
+
+        fn call(&self, message: &[u8]) -> Result<Vec<Message>, AbiError> {
+            let buffer =
+                unsafe { (self.vtable.call)(self.vtable.instance, FfiStr::from_bytes(message)) };
+            self.result(buffer)
+        }
+
+        fn init(&self, profile: &[u8]) -> Result<(), AbiError> {
+            let buffer =
+                unsafe { (self.vtable.init)(self.vtable.instance, FfiStr::from_bytes(profile)) };
+            self.result(buffer)
+        }
+
+        fn shutdown(&self, timeout_ms: u64) -> Result<(), AbiError> {
+            let buffer = unsafe { (self.vtable.shutdown)(self.vtable.instance, timeout_ms) };
+            self.result(buffer)
+        }
+
+        fn health(&self) -> Result<PluginHealth, AbiError> {
+            let buffer = unsafe { (self.vtable.health)(self.vtable.instance) };
+            self.result(buffer)
+        }
+
+        fn validate_config(&self, config: &[u8]) -> Result<(), AbiError> {
+            let buffer = unsafe {
+                (self.vtable.validate_config)(self.vtable.instance, FfiStr::from_bytes(config))
+            };
+            self.result(buffer)
+        }
+
+        /// Reads a `Result<T, String>` returned by the plugin.
+        fn result<T: DeserializeOwned>(&self, buffer: FfiBuffer) -> Result<T, AbiError> {
+            let result: Result<T, String> = self.read(buffer)?;
+            result.map_err(AbiError)
+        }
+
+        fn read<T: DeserializeOwned>(&self, buffer: FfiBuffer) -> Result<T, AbiError> {
+            let result = serde_json::from_slice(unsafe { buffer.as_bytes() });
+            unsafe { (self.vtable.free_buffer)(buffer) };
+            result.map_err(|why| AbiError(format!("invalid response from plugin: {}", why)))
+        }
+    }
+
+    impl Drop for AbiInstance {
+        fn drop(&mut self) {
+            unsafe { (self.vtable.drop)(self.vtable.instance) };
+        }
+    }
+
+    /// [StreamablePlugin] calling a plugin exported through the stable ABI.
+    #[derive(Debug)]
+    pub(crate) struct AbiPlugin {
+        instance: Arc<AbiInstance>,
+        info: PluginInfo,
+    }
+
+    impl AbiPlugin {
+        /// Takes ownership of the plugin instance of `vtable`. `lib` has to be the library
+        /// which created the instance.
+        pub(crate) fn new(
+            vtable: PluginVTable,
+            lib: Arc<Option<Library>>,
+        ) -> Result<Self, AbiError> {
+            let instance = Arc::new(AbiInstance { vtable, _lib: lib });
+            let info = instance.info()?;
+            Ok(AbiPlugin { instance, info })
+        }
+
+        /// Runs `call` on a blocking thread as calls block until the plugin is done.
+        async fn blocking<T, F>(&self, call: F) -> Result<T, AbiError>
+        where
+            T: Send + 'static,
+            F: FnOnce(&AbiInstance) -> Result<T, AbiError> + Send + 'static,
+        {
+            let instance = Arc::clone(&self.instance);
+            tokio::task::spawn_blocking(move || call(&instance))
+                .await
+                .map_err(|why| AbiError(format!("plugin call failed: {}", why)))?
+        }
+    }
+
+    fn to_json<T: Serialize>(value: &T) -> Result<Vec<u8>, AbiError> {
+        serde_json::to_vec(value).map_err(|why| AbiError(format!("failed to serialize: {}", why)))
+    }
+
+    #[async_trait]
+    impl StreamablePlugin for AbiPlugin {
+        async fn init(&self, profile: &Profile) -> Result<(), PluginError> {
+            let profile = to_json(profile)?;
+            Ok(self
+                .blocking(move |instance| instance.init(&profile))
+                .await?)
+        }
+
+        async fn stream(
+            &self,
+            mut input: Receiver<Message>,
+            mut output: UnboundedSender<Vec<Message>>,
+        ) -> Result<(), PluginError> {
+            while let Some(msg) = input.next().await {
+                let message = to_json(&msg)?;
+                let results = self
+                    .blocking(move |instance| instance.call(&message))
+                    .await?;
+                output.send(results).await?;
+            }
+            Ok(())
+        }
+
+        async fn shutdown(&self, deadline: Instant) -> Result<(), PluginError> {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let timeout_ms = timeout.as_millis() as u64;
+            Ok(self
+                .blocking(move |instance| instance.shutdown(timeout_ms))
+                .await?)
+        }
+
+        async fn health(&self) -> PluginHealth {
+            self.blocking(AbiInstance::health)
+                .await
+                .unwrap_or_else(|why| PluginHealth::Unhealthy(why.to_string()))
+        }
+
+        fn validate_config(&self, config: Option<&PluginConfig>) -> Result<(), PluginError> {
+            Ok(self.instance.validate_config(&to_json(&config)?)?)
+        }
+
+        fn info(&self) -> PluginInfo {
+            self.info.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::abi::{is_compatible, FfiBuffer, FfiStr, PluginVTable};
+    use crate::plugin::{
+        parse_config, Plugin, PluginConfig, PluginError, PluginHealth, PluginInfo,
+    };
+    use crate::profile::Profile;
+    use crate::Message;
+    use async_trait::async_trait;
+    use serde::de::DeserializeOwned;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    struct EchoPlugin(Mutex<Option<Profile>>);
+
+    #[async_trait]
+    impl Plugin for EchoPlugin {
+        type Error = PluginError;
+
+        async fn init(&self, profile: &Profile) -> Result<(), PluginError> {
+            *self.0.lock().unwrap() = Some(profile.clone());
+            Ok(())
+        }
+
+        async fn call(&self, message: Message) -> Result<Vec<Message>, PluginError> {
+            Ok(vec![message])
+        }
+
+        async fn health(&self) -> PluginHealth {
+            match self.0.lock().unwrap().as_ref() {
+                Some(_) => PluginHealth::Healthy,
+                None => PluginHealth::Unhealthy("not initialized".to_string()),
+            }
+        }
+
+        fn validate_config(&self, config: Option<&PluginConfig>) -> Result<(), PluginError> {
+            parse_config::<HashMap<String, String>>(config)?;
+            Ok(())
+        }
+
+        fn info(&self) -> PluginInfo {
+            PluginInfo {
+                name: "Echo".to_string(),
+                version: "".to_string(),
+                authors: "".to_string(),
+                repo: None,
+                commands: vec![],
+                required_rights: Default::default(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_compatible() {
+        assert!(is_compatible("0.4.1", "0.4.4"));
+        assert!(is_compatible("0.4.9", "0.4.4"));
+        assert!(is_compatible("0.4.4-alpha", "0.4.4"));
+        assert!(!is_compatible("0.3.4", "0.4.4"));
+        assert!(is_compatible("1.2.0", "1.0.3"));
+        assert!(!is_compatible("2.0.0", "1.0.3"));
+        assert!(!is_compatible("invalid", "0.4.4"));
+    }
+
+    fn read<T: DeserializeOwned>(vtable: &PluginVTable, buffer: FfiBuffer) -> T {
+        let parsed = serde_json::from_slice(unsafe { buffer.as_bytes() }).unwrap();
+        unsafe { (vtable.free_buffer)(buffer) };
+        parsed
+    }
+
+    #[test]
+    fn test_vtable() {
+        let vtable = PluginVTable::new(EchoPlugin(Mutex::new(None)));
+
+        let info = unsafe { (vtable.info)(vtable.instance) };
+        let parsed: PluginInfo = serde_json::from_slice(unsafe { info.as_bytes() }).unwrap();
+        unsafe { (vtable.free_buffer)(info) };
+        assert_eq!(parsed.name, "Echo");
+
+        let message = Message::Irc(irc_rust::Message::from("PRIVMSG #channel :hello"));
+        let json = serde_json::to_vec(&message).unwrap();
+        let result = unsafe { (vtable.call)(vtable.instance, FfiStr::from_bytes(&json)) };
+        let parsed: Result<Vec<Message>, String> =
+            serde_json::from_slice(unsafe { result.as_bytes() }).unwrap();
+        unsafe { (vtable.free_buffer)(result) };
+        assert_eq!(parsed, Ok(vec![message]));
+
+        let result = unsafe { (vtable.call)(vtable.instance, FfiStr::from_static("invalid")) };
+        let parsed: Result<Vec<Message>, String> =
+            serde_json::from_slice(unsafe { result.as_bytes() }).unwrap();
+        unsafe { (vtable.free_buffer)(result) };
+        assert!(parsed.is_err());
+
+        unsafe { (vtable.drop)(vtable.instance) };
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let profile = Profile::new(
+            "foo".to_string(),
+            Vec::new(),
+            String::new(),
+            Default::default(),
+            None,
+        );
+        let json = serde_json::to_vec(&Some(&profile)).unwrap();
+        let vtable = unsafe {
+            PluginVTable::create(FfiStr::from_bytes(&json), |created| {
+                assert_eq!(created.as_ref(), Some(&profile));
+                EchoPlugin(Mutex::new(None))
+            })
+        };
+
+        let health: Result<PluginHealth, String> =
+            read(&vtable, unsafe { (vtable.health)(vtable.instance) });
+        assert!(!health.unwrap().is_healthy());
+        let json = serde_json::to_vec(&profile).unwrap();
+        let result: Result<(), String> = read(&vtable, unsafe {
+            (vtable.init)(vtable.instance, FfiStr::from_bytes(&json))
+        });
+        assert_eq!(result, Ok(()));
+        let health: Result<PluginHealth, String> =
+            read(&vtable, unsafe { (vtable.health)(vtable.instance) });
+        assert_eq!(health, Ok(PluginHealth::Healthy));
+
+        for (config, valid) in &[("null", true), (r#"{"a":"b"}"#, true), ("[1]", false)] {
+            let result: Result<(), String> = read(&vtable, unsafe {
+                (vtable.validate_config)(vtable.instance, FfiStr::from_static(config))
+            });
+            assert_eq!(result.is_ok(), *valid, "{}", config);
+        }
+
+        let result: Result<(), String> =
+            read(&vtable, unsafe { (vtable.shutdown)(vtable.instance, 100) });
+        assert_eq!(result, Ok(()));
+        unsafe { (vtable.drop)(vtable.instance) };
+    }
+}
@@ -1,5 +1,6 @@
 use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
 
 #[derive(PartialEq, Eq, Debug, Hash, Clone, Serialize, Deserialize)]
 pub enum Platform {
@@ -147,6 +148,23 @@ where
     }
 }
 
+/// Parses credentials in the format of [Credentials::fmt].
+impl FromStr for Credentials {
+    type Err = ValidationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(token) = s.strip_prefix("oauth:") {
+            Ok(Credentials::OAuthToken {
+                token: token.to_string(),
+            })
+        } else if s == "NONE" {
+            Ok(Credentials::None)
+        } else {
+            Err(ValidationError::Invalid)
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ValidationError {
     Invalid,
@@ -178,6 +196,8 @@ mod tests {
                 token: "thisisatoken".to_string()
             }
         );
+        assert_eq!("NONE".parse::<Credentials>().ok(), Some(Credentials::None));
+        assert!("thisisatoken".parse::<Credentials>().is_err());
     }
 
     mod userinfo {
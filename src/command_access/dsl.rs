@@ -0,0 +1,462 @@
+//! Textual language to write [AccessFilter]s, e.g. in the `config.json` of a profile:
+//!
+//! ```text
+//! badge:moderator/* OR (badge:vip/* AND trailing:^!song)
+//! ```
+//!
+//! Filters are written as `kind:value`:
+//!
+//! - `badge:<regex>`: [AccessFilter::Badge]
+//! - `trailing:<regex>`: [AccessFilter::Trailing]
+//! - `user-id:<id>`: [AccessFilter::UserId]
+//! - `user:<login>`: [AccessFilter::Username]
+//! - `channel:<channel>`: [AccessFilter::Channel]
+//! - `command:<command>`: [AccessFilter::Command]
+//! - `tag:<name>=<regex>`: [AccessFilter::Tag]
+//!
+//! Values containing whitespace, parentheses, commas or quotes have to be quoted
+//! (`trailing:"^hello, world!$"`). Inside quotes `\"` and `\\` are escaped.
+//!
+//! Filters are combined with `NOT`, `AND` and `OR` (in order of precedence) and grouped with
+//! parentheses. `ALL(a, b)` and `ANY(a, b)` are alternative forms of `a AND b` and `a OR b`.
+//! Keywords are case-insensitive.
+
+use crate::command_access::{AccessFilter, FilterRegex};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+/// Position of a part of the parsed text. Lines and columns start at 1.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    /// Byte offset of the start.
+    pub offset: usize,
+    /// Length in bytes.
+    pub len: usize,
+}
+
+impl Span {
+    fn new(input: &str, offset: usize, len: usize) -> Self {
+        let before = &input[..offset];
+        let line_start = before.rfind('\n').map(|index| index + 1).unwrap_or(0);
+        Span {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            offset,
+            len,
+        }
+    }
+}
+
+/// Error raised if a text isn't a valid filter.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.message, self.span.line, self.span.column
+        )
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token<'a> {
+    LParen,
+    RParen,
+    Comma,
+    And,
+    Or,
+    Not,
+    All,
+    Any,
+    Filter { kind: &'a str, value: String },
+    End,
+}
+
+impl Display for Token<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+            Token::And => write!(f, "AND"),
+            Token::Or => write!(f, "OR"),
+            Token::Not => write!(f, "NOT"),
+            Token::All => write!(f, "ALL"),
+            Token::Any => write!(f, "ANY"),
+            Token::Filter { kind, .. } => write!(f, "filter '{}'", kind),
+            Token::End => write!(f, "end of input"),
+        }
+    }
+}
+
+/// Characters ending an unquoted value.
+fn ends_value(c: char) -> bool {
+    c.is_whitespace() || c == '(' || c == ')' || c == ',' || c == '"'
+}
+
+/// Writes `value` so it is read as a single value. Quotes it if necessary.
+pub(crate) fn write_value(f: &mut Formatter<'_>, value: &str) -> fmt::Result {
+    if !value.is_empty() && !value.contains(ends_value) {
+        return write!(f, "{}", value);
+    }
+    write!(f, "\"")?;
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            write!(f, "\\")?;
+        }
+        write!(f, "{}", c)?;
+    }
+    write!(f, "\"")
+}
+
+struct Lexer<'a> {
+    input: &'a str,
+    offset: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn error<S: Into<String>>(&self, message: S, offset: usize, len: usize) -> ParseError {
+        ParseError {
+            message: message.into(),
+            span: Span::new(self.input, offset, len),
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.offset..]
+    }
+
+    fn tokens(mut self) -> Result<Vec<(Token<'a>, Span)>, ParseError> {
+        let mut tokens = Vec::new();
+        loop {
+            let trimmed = self.rest().trim_start();
+            self.offset = self.input.len() - trimmed.len();
+            let start = self.offset;
+            let token = match trimmed.chars().next() {
+                None => {
+                    tokens.push((Token::End, Span::new(self.input, start, 0)));
+                    return Ok(tokens);
+                }
+                Some('(') => self.symbol(Token::LParen),
+                Some(')') => self.symbol(Token::RParen),
+                Some(',') => self.symbol(Token::Comma),
+                Some(c) if c.is_alphanumeric() => self.word()?,
+                Some(c) => {
+                    return Err(self.error(
+                        format!("unexpected character '{}'", c),
+                        start,
+                        c.len_utf8(),
+                    ))
+                }
+            };
+            tokens.push((token, Span::new(self.input, start, self.offset - start)));
+        }
+    }
+
+    fn symbol(&mut self, token: Token<'a>) -> Token<'a> {
+        self.offset += 1;
+        token
+    }
+
+    fn word(&mut self) -> Result<Token<'a>, ParseError> {
+        let start = self.offset;
+        let len = self
+            .rest()
+            .find(|c: char| !c.is_alphanumeric() && c != '-' && c != '_')
+            .unwrap_or_else(|| self.rest().len());
+        let word = &self.input[start..start + len];
+        self.offset += len;
+        if self.rest().starts_with(':') {
+            self.offset += 1;
+            let value = self.value()?;
+            return Ok(Token::Filter { kind: word, value });
+        }
+        match word.to_uppercase().as_str() {
+            "AND" => Ok(Token::And),
+            "OR" => Ok(Token::Or),
+            "NOT" => Ok(Token::Not),
+            "ALL" => Ok(Token::All),
+            "ANY" => Ok(Token::Any),
+            _ => Err(self.error(
+                format!(
+                    "unknown keyword '{}', expected a filter like 'badge:moderator/*'",
+                    word
+                ),
+                start,
+                len,
+            )),
+        }
+    }
+
+    fn value(&mut self) -> Result<String, ParseError> {
+        let start = self.offset;
+        if !self.rest().starts_with('"') {
+            let len = self
+                .rest()
+                .find(ends_value)
+                .unwrap_or_else(|| self.rest().len());
+            if len == 0 {
+                return Err(self.error("missing value", start, 0));
+            }
+            self.offset += len;
+            return Ok(self.input[start..self.offset].to_string());
+        }
+
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices().skip(1);
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.offset += index + 1;
+                    return Ok(value);
+                }
+                '\\' => match chars.next() {
+                    Some((_, escaped)) if escaped == '"' || escaped == '\\' => value.push(escaped),
+                    Some((_, other)) => {
+                        value.push('\\');
+                        value.push(other);
+                    }
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        Err(self.error("unterminated quoted value", start, self.rest().len()))
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<(Token<'a>, Span)>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Token<'a> {
+        &self.tokens[self.position].0
+    }
+
+    fn next(&mut self) -> (Token<'a>, Span) {
+        let token = self.tokens[self.position].clone();
+        if token.0 != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, expected: Token<'a>) -> Result<(), ParseError> {
+        let (token, span) = self.next();
+        if token == expected {
+            Ok(())
+        } else {
+            Err(unexpected(&token, span, &format!("{}", expected)))
+        }
+    }
+
+    fn or(&mut self) -> Result<AccessFilter, ParseError> {
+        let mut filters = vec![self.and()?];
+        while *self.peek() == Token::Or {
+            self.next();
+            filters.push(self.and()?);
+        }
+        Ok(if filters.len() == 1 {
+            filters.remove(0)
+        } else {
+            AccessFilter::Any(filters)
+        })
+    }
+
+    fn and(&mut self) -> Result<AccessFilter, ParseError> {
+        let mut filters = vec![self.unary()?];
+        while *self.peek() == Token::And {
+            self.next();
+            filters.push(self.unary()?);
+        }
+        Ok(if filters.len() == 1 {
+            filters.remove(0)
+        } else {
+            AccessFilter::All(filters)
+        })
+    }
+
+    fn unary(&mut self) -> Result<AccessFilter, ParseError> {
+        let (token, span) = self.next();
+        match token {
+            Token::Not => Ok(AccessFilter::negate(self.unary()?)),
+            Token::LParen => {
+                let filter = self.or()?;
+                self.expect(Token::RParen)?;
+                Ok(filter)
+            }
+            Token::All => Ok(AccessFilter::All(self.list()?)),
+            Token::Any => Ok(AccessFilter::Any(self.list()?)),
+            Token::Filter { kind, value } => self.filter(kind, value, span),
+            token => Err(unexpected(&token, span, "a filter")),
+        }
+    }
+
+    /// Parses the comma separated filters of `ALL(...)` and `ANY(...)`.
+    fn list(&mut self) -> Result<Vec<AccessFilter>, ParseError> {
+        self.expect(Token::LParen)?;
+        let mut filters = Vec::new();
+        if *self.peek() == Token::RParen {
+            self.next();
+            return Ok(filters);
+        }
+        loop {
+            filters.push(self.or()?);
+            match self.next() {
+                (Token::Comma, _) => (),
+                (Token::RParen, _) => return Ok(filters),
+                (token, span) => return Err(unexpected(&token, span, "',' or ')'")),
+            }
+        }
+    }
+
+    fn filter(&self, kind: &str, value: String, span: Span) -> Result<AccessFilter, ParseError> {
+        let regex = |pattern: &str| {
+            FilterRegex::new(pattern).map_err(|why| ParseError {
+                message: why.to_string(),
+                span,
+            })
+        };
+        match kind.to_lowercase().as_str() {
+            "badge" => regex(&value).map(AccessFilter::Badge),
+            "trailing" => regex(&value).map(AccessFilter::Trailing),
+            "user-id" => Ok(AccessFilter::UserId(value)),
+            "user" => Ok(AccessFilter::Username(value)),
+            "channel" => Ok(AccessFilter::Channel(value)),
+            "command" => Ok(AccessFilter::Command(value)),
+            "tag" => match value.find('=') {
+                Some(index) => Ok(AccessFilter::Tag {
+                    name: value[..index].to_string(),
+                    regex: regex(&value[index + 1..])?,
+                }),
+                None => Err(ParseError {
+                    message: format!("tag filter '{}' has to be written as 'tag:<name>=<regex>'", value),
+                    span,
+                }),
+            },
+            _ => Err(ParseError {
+                message: format!(
+                    "unknown filter '{}', expected one of badge, trailing, user-id, user, channel, command or tag",
+                    kind
+                ),
+                span: Span::new(self.input, span.offset, kind.len()),
+            }),
+        }
+    }
+}
+
+fn unexpected(token: &Token<'_>, span: Span, expected: &str) -> ParseError {
+    ParseError {
+        message: format!("expected {}, found {}", expected, token),
+        span,
+    }
+}
+
+/// Parses `input` into a filter.
+pub fn parse(input: &str) -> Result<AccessFilter, ParseError> {
+    let tokens = Lexer { input, offset: 0 }.tokens()?;
+    let mut parser = Parser {
+        input,
+        tokens,
+        position: 0,
+    };
+    let filter = parser.or()?;
+    match parser.next() {
+        (Token::End, _) => Ok(filter),
+        (token, span) => Err(unexpected(&token, span, "AND, OR or end of input")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command_access::dsl::parse;
+    use crate::command_access::AccessFilter;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("badge:moderator/* OR (badge:vip/* AND trailing:^!song)").unwrap(),
+            AccessFilter::Any(vec![
+                AccessFilter::badge("moderator/*").unwrap(),
+                AccessFilter::All(vec![
+                    AccessFilter::badge("vip/*").unwrap(),
+                    AccessFilter::trailing("^!song").unwrap(),
+                ]),
+            ])
+        );
+        assert_eq!(
+            parse("not user:someone and tag:subscriber=^1$ AND channel:#foo").unwrap(),
+            AccessFilter::All(vec![
+                AccessFilter::negate(AccessFilter::Username("someone".to_string())),
+                AccessFilter::tag("subscriber", "^1$").unwrap(),
+                AccessFilter::Channel("#foo".to_string()),
+            ])
+        );
+        assert_eq!(
+            parse(r#"ANY(trailing:"^hello, \"world\"$", user-id:1234) OR ALL()"#).unwrap(),
+            AccessFilter::Any(vec![
+                AccessFilter::Any(vec![
+                    AccessFilter::trailing(r#"^hello, "world"$"#).unwrap(),
+                    AccessFilter::UserId("1234".to_string()),
+                ]),
+                AccessFilter::All(vec![]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = parse("badge:moderator/* OR\n  (badge:vip/* AND)").unwrap_err();
+        assert_eq!(error.message, "expected a filter, found ')'");
+        assert_eq!((error.span.line, error.span.column), (2, 19));
+
+        let error = parse("badge:moderator/* XOR user:someone").unwrap_err();
+        assert_eq!((error.span.line, error.span.column), (1, 19));
+        assert_eq!(
+            error.to_string(),
+            "unknown keyword 'XOR', expected a filter like 'badge:moderator/*' at line 1, column 19"
+        );
+
+        let error = parse("user:someone OR bagde:vip/*").unwrap_err();
+        assert_eq!((error.span.column, error.span.len), (17, 5));
+
+        assert!(parse("badge:(").is_err());
+        assert!(parse("trailing:\"unterminated").is_err());
+        assert!(parse("tag:subscriber").is_err());
+        assert!(parse("(user:someone").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let filters = vec![
+            AccessFilter::default_command_start(),
+            AccessFilter::Any(vec![
+                AccessFilter::broadcaster(),
+                AccessFilter::All(vec![
+                    AccessFilter::negate(AccessFilter::Command("WHISPER".to_string())),
+                    AccessFilter::trailing("^hello, (world|kevin)!$").unwrap(),
+                ]),
+            ]),
+            AccessFilter::All(vec![AccessFilter::Username("someone".to_string())]),
+            AccessFilter::Any(vec![]),
+            AccessFilter::tag("display-name", r#"^"quoted\\"$"#).unwrap(),
+        ];
+        for filter in filters {
+            assert_eq!(parse(&filter.to_string()).unwrap(), filter);
+        }
+    }
+}
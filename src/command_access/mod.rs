@@ -0,0 +1,1156 @@
+use regex::Regex;
+
+pub mod dsl;
+
+use crate::command_access::dsl::ParseError;
+use crate::plugin::PluginInfo;
+use crate::router;
+use crate::Message;
+use core::fmt;
+use serde::export::Formatter;
+use serde::{de, Deserializer, Serializer};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// Manages filters for command invocations. Should be used to manage the access of users
+/// to commands based on the messages.
+///
+/// Commands can have own filters replacing the default filters (see
+/// [AccessRights::set_command]). [AccessRights::decide] returns which filter decided if a
+/// message is allowed, e.g. to explain refusals in chat.
+///
+/// The plugin-loader only sends messages to a plugin which aren't denied by the
+/// [AccessRights] of the active profile or the rights the plugin requires for the invoked
+/// command ([PluginInfo::required_rights]).
+///
+/// # Example
+///
+/// Plugins can check the rights themselves, e.g. to answer denied invocations.
+///
+/// ```rust,no_run
+/// use bot_rs_core::plugin::{Plugin, PluginInfo, PluginError};
+/// use bot_rs_core::Message;
+/// use async_trait::async_trait;
+/// use bot_rs_core::profile::{Profiles, Profile};
+/// use bot_rs_core::command_access::AccessVerdict;
+///
+/// // Profile of the PluginRegistrar::context can be stored on Plugin creation as it doesn't change at runtime
+/// struct TestPlugin(Profile);
+///
+/// #[async_trait]
+/// impl Plugin for TestPlugin{
+///     type Error = PluginError;
+///
+///     async fn call(&self, message: Message) -> Result<Vec<Message>, PluginError> {
+///         match self.0.rights().verdict_for(&self.info(), &message) {
+///             AccessVerdict::Allowed => (), // Message was checked by a AccessFilter and is allowed,
+///             AccessVerdict::Denied => (), // Message was checked by a AccessFilter and is not allowed
+///             AccessVerdict::Unchecked => (), // Message has no handling AccessFilters
+///         }
+///         Ok(Vec::with_capacity(0))
+///     }
+///
+///     fn info(&self) -> PluginInfo {
+///         unimplemented!()
+///     }
+/// }
+/// ```
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct AccessRights {
+    // Filters of commands without own filters and messages not invoking a command.
+    filters: Vec<AccessFilter>,
+    // Maps commands (e.g. `!ban`) to their filters.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    commands: BTreeMap<String, Vec<AccessFilter>>,
+}
+
+impl AccessRights {
+    /// Creates default access right which allows messages of the broadcaster and non-command invokations.
+    pub fn new() -> Self {
+        AccessRights {
+            filters: vec![AccessFilter::Any(vec![
+                AccessFilter::broadcaster(),
+                AccessFilter::default_command_start(),
+            ])],
+            commands: BTreeMap::new(),
+        }
+    }
+
+    pub const fn empty() -> Self {
+        AccessRights {
+            filters: Vec::new(),
+            commands: BTreeMap::new(),
+        }
+    }
+
+    /// Returns if there are no filters.
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty() && self.commands.values().all(Vec::is_empty)
+    }
+
+    /// Returns an Iterator over all default filters.
+    pub fn iter(&self) -> impl Iterator<Item = &AccessFilter> {
+        self.filters.iter()
+    }
+
+    /// Returns an Iterator over all commands with own filters and their filters.
+    pub fn commands(&self) -> impl Iterator<Item = (&str, &[AccessFilter])> {
+        self.commands
+            .iter()
+            .map(|(command, filters)| (command.as_str(), filters.as_slice()))
+    }
+
+    /// Sets the filters of `command` (e.g. `!ban`). They replace the default filters for
+    /// messages invoking the command. Commands are compared case-insensitively.
+    pub fn set_command<S: Into<String>>(&mut self, command: S, filters: Vec<AccessFilter>) {
+        self.commands.insert(command.into().to_lowercase(), filters);
+    }
+
+    /// Removes the filters of `command`. Messages invoking it are checked by the default
+    /// filters again.
+    pub fn remove_command(&mut self, command: &str) -> Option<Vec<AccessFilter>> {
+        let key = self.command_key(command)?.to_string();
+        self.commands.remove(&key)
+    }
+
+    /// Returns the filters of `command` if it has own filters.
+    pub fn command(&self, command: &str) -> Option<&[AccessFilter]> {
+        let key = self.command_key(command)?;
+        self.commands.get(key).map(Vec::as_slice)
+    }
+
+    fn command_key(&self, command: &str) -> Option<&str> {
+        self.commands
+            .keys()
+            .find(|key| key.eq_ignore_ascii_case(command))
+            .map(String::as_str)
+    }
+
+    /// Checks the message against the filters of the invoked command or the default filters
+    /// if the command has no own filters. Returns the verdict and the rule deciding it.
+    pub fn decide(&self, mssg: &Message) -> AccessDecision<'_> {
+        let (rules, filters) = self.rules(mssg);
+        let mut handling = filters
+            .iter()
+            .filter(|filter| filter.handles(mssg))
+            .peekable();
+        let (verdict, filter) = match handling.peek().copied() {
+            None => (AccessVerdict::Unchecked, None),
+            Some(first) => match handling.find(|filter| filter.matches(mssg)) {
+                Some(filter) => (AccessVerdict::Allowed, Some(filter)),
+                None => (AccessVerdict::Denied, Some(first)),
+            },
+        };
+        AccessDecision {
+            verdict,
+            rules,
+            filter,
+        }
+    }
+
+    /// Checks the message like [AccessRights::decide] and records the results of all filters
+    /// it was checked against.
+    pub fn explain(&self, mssg: &Message) -> AccessTrace<'_> {
+        let (_, filters) = self.rules(mssg);
+        AccessTrace {
+            decision: self.decide(mssg),
+            filters: filters.iter().map(|filter| filter.explain(mssg)).collect(),
+        }
+    }
+
+    /// Returns the filters to check the message against.
+    fn rules(&self, mssg: &Message) -> (Rules<'_>, &[AccessFilter]) {
+        let command = router::command(mssg);
+        match command
+            .as_deref()
+            .and_then(|command| self.command_key(command))
+        {
+            Some(command) => (Rules::Command(command), self.commands[command].as_slice()),
+            None => (Rules::Default, self.filters.as_slice()),
+        }
+    }
+
+    /// Checks if any filter allows the invocation of a command.
+    ///
+    /// Returns:
+    ///
+    /// - `Some(true)` if filters were present for the message and any allowed it,
+    /// - `Some(false)` if filters were present for the message and none allowed it and
+    /// - `None` if no filters were present for the message.
+    pub fn allowed(&self, mssg: &Message) -> Option<bool> {
+        match self.verdict(mssg) {
+            AccessVerdict::Allowed => Some(true),
+            AccessVerdict::Denied => Some(false),
+            AccessVerdict::Unchecked => None,
+        }
+    }
+
+    /// Checks if any filter allows the invocation of a command. See [AccessRights::decide].
+    pub fn verdict(&self, mssg: &Message) -> AccessVerdict {
+        self.decide(mssg).verdict
+    }
+
+    /// Checks if the message is allowed to be sent to the plugin with the given info. The
+    /// message has to be allowed by these rights and the rights the plugin requires for the
+    /// invoked command.
+    pub fn verdict_for(&self, info: &PluginInfo, mssg: &Message) -> AccessVerdict {
+        self.verdict(mssg)
+            .for_plugin(info, router::command(mssg).as_deref(), mssg)
+    }
+}
+
+/// Set of filters of [AccessRights] a message was checked against.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum Rules<'a> {
+    /// The default filters.
+    Default,
+    /// The filters of the command.
+    Command(&'a str),
+}
+
+/// Result of [AccessRights::decide] containing the rule deciding the verdict.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub struct AccessDecision<'a> {
+    pub verdict: AccessVerdict,
+    /// Filters the message was checked against.
+    pub rules: Rules<'a>,
+    /// The first filter allowing the message if allowed or the first filter handling the
+    /// message if denied. `None` if unchecked.
+    pub filter: Option<&'a AccessFilter>,
+}
+
+impl Display for AccessDecision<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.verdict {
+            AccessVerdict::Allowed => write!(f, "Allowed")?,
+            AccessVerdict::Denied => write!(f, "Denied")?,
+            AccessVerdict::Unchecked => write!(f, "Unchecked")?,
+        }
+        if let Some(filter) = self.filter {
+            write!(f, " by {}", filter)?;
+        }
+        match self.rules {
+            Rules::Default => Ok(()),
+            Rules::Command(command) => write!(f, " for {}", command),
+        }
+    }
+}
+
+/// Result of [AccessRights::explain].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct AccessTrace<'a> {
+    pub decision: AccessDecision<'a>,
+    /// Results of the filters the message was checked against.
+    pub filters: Vec<FilterTrace<'a>>,
+}
+
+impl Display for AccessTrace<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.decision)?;
+        for filter in self.filters.iter() {
+            writeln!(f)?;
+            filter.write(f, 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Result of checking a message against a filter. Mirrors the structure of the filter.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct FilterTrace<'a> {
+    pub filter: &'a AccessFilter,
+    pub handles: bool,
+    pub matches: bool,
+    /// Part of the message the filter looked at, e.g. the badges or the trailing. `None` for
+    /// [AccessFilter::All], [AccessFilter::Any] and [AccessFilter::Not] or if missing.
+    pub input: Option<String>,
+    /// Traces of the nested filters.
+    pub children: Vec<FilterTrace<'a>>,
+}
+
+impl FilterTrace<'_> {
+    fn write(&self, f: &mut Formatter<'_>, depth: usize) -> fmt::Result {
+        write!(f, "{:width$}", "", width = depth * 2)?;
+        match self.filter {
+            AccessFilter::All(_) => write!(f, "ALL")?,
+            AccessFilter::Any(_) => write!(f, "ANY")?,
+            AccessFilter::Not(_) => write!(f, "NOT")?,
+            filter => write!(f, "{}", filter)?,
+        }
+        let result = match (self.handles, self.matches) {
+            (false, _) => "not handled",
+            (true, true) => "matched",
+            (true, false) => "not matched",
+        };
+        write!(f, " -> {}", result)?;
+        if let Some(ref input) = self.input {
+            write!(f, " ({:?})", input)?;
+        }
+        for child in self.children.iter() {
+            writeln!(f)?;
+            child.write(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for FilterTrace<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+/// Result of checking a message against access rights.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum AccessVerdict {
+    /// Filters handling the message allowed it.
+    Allowed,
+    /// Filters handled the message but didn't allow it.
+    Denied,
+    /// No filter handled the message.
+    Unchecked,
+}
+
+impl AccessVerdict {
+    /// Checks the message against rights required by a plugin. Messages not handled by
+    /// `required` are denied.
+    pub fn required(required: &AccessFilter, mssg: &Message) -> Self {
+        if required.matches(mssg) {
+            AccessVerdict::Allowed
+        } else {
+            AccessVerdict::Denied
+        }
+    }
+
+    /// Combines the verdict of the profile rights with the rights `info` requires to invoke
+    /// `command`.
+    pub(crate) fn for_plugin(
+        self,
+        info: &PluginInfo,
+        command: Option<&str>,
+        mssg: &Message,
+    ) -> Self {
+        match command.and_then(|command| info.rights_for(command)) {
+            Some(required) if !self.is_denied() => {
+                self.and(AccessVerdict::required(required, mssg))
+            }
+            _ => self,
+        }
+    }
+
+    /// Combines two verdicts. Denied if any is denied, allowed if any is allowed and
+    /// unchecked otherwise.
+    pub fn and(self, other: AccessVerdict) -> Self {
+        match (self, other) {
+            (AccessVerdict::Denied, _) | (_, AccessVerdict::Denied) => AccessVerdict::Denied,
+            (AccessVerdict::Allowed, _) | (_, AccessVerdict::Allowed) => AccessVerdict::Allowed,
+            _ => AccessVerdict::Unchecked,
+        }
+    }
+
+    pub fn is_denied(&self) -> bool {
+        *self == AccessVerdict::Denied
+    }
+}
+
+impl From<Option<bool>> for AccessVerdict {
+    fn from(allowed: Option<bool>) -> Self {
+        match allowed {
+            Some(true) => AccessVerdict::Allowed,
+            Some(false) => AccessVerdict::Denied,
+            None => AccessVerdict::Unchecked,
+        }
+    }
+}
+
+impl From<Vec<AccessFilter>> for AccessRights {
+    fn from(filters: Vec<AccessFilter>) -> Self {
+        AccessRights {
+            filters,
+            commands: BTreeMap::new(),
+        }
+    }
+}
+
+/// Error raised if a pattern of an [AccessFilter] isn't a valid regex.
+#[derive(Debug, Clone)]
+pub struct FilterError {
+    pub pattern: String,
+    pub error: regex::Error,
+}
+
+impl Display for FilterError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid filter regex '{}': {}", self.pattern, self.error)
+    }
+}
+
+impl Error for FilterError {}
+
+/// Regex of an [AccessFilter]. Compiled once on creation and (de-)serialized as its pattern.
+#[derive(Clone, Debug)]
+pub struct FilterRegex(Regex);
+
+impl FilterRegex {
+    pub fn new(pattern: &str) -> Result<Self, FilterError> {
+        Regex::new(pattern)
+            .map(FilterRegex)
+            .map_err(|error| FilterError {
+                pattern: pattern.to_string(),
+                error,
+            })
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.0.is_match(text)
+    }
+}
+
+impl PartialEq for FilterRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for FilterRegex {}
+
+impl PartialOrd for FilterRegex {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FilterRegex {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Hash for FilterRegex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl Display for FilterRegex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl serde::Serialize for FilterRegex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for FilterRegex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = <String as serde::Deserialize>::deserialize(deserializer)?;
+        FilterRegex::new(&pattern).map_err(de::Error::custom)
+    }
+}
+
+/// Filter deciding if a message is allowed.
+///
+/// Filters are (de-)serialized as enum or parsed from their textual form (see [dsl]), e.g.
+/// `"badge:moderator/* OR user:someone"`.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+#[serde(remote = "Self")]
+pub enum AccessFilter {
+    /// Checks if a badge matches the given regex.
+    Badge(FilterRegex),
+    /// Checks if the user message (IRC = Trailing parameter) matches the given regex string.
+    Trailing(FilterRegex),
+    /// Checks if all [AccessFilter]s match a given [Message]. Equivalent to logical `AND`.
+    All(Vec<AccessFilter>),
+    /// Checks if any [AccessFilter] matches a given [Message]. Equivalent to logical `OR`.
+    Any(Vec<AccessFilter>),
+    /// Checks if the `user-id` tag of a message equals the given id.
+    UserId(String),
+    /// Checks if the login name of the sender (IRC = Prefix name) equals the given name. Names
+    /// are compared case-insensitively.
+    Username(String),
+    /// Checks if a message was sent to the given channel (IRC = first parameter). Channels are
+    /// compared case-insensitively with or without leading `#`.
+    Channel(String),
+    /// Checks if the command of a message (e.g. `PRIVMSG` or `WHISPER`) equals the given one.
+    Command(String),
+    /// Checks if the tag `name` matches the given regex.
+    Tag { name: String, regex: FilterRegex },
+    /// Checks if the [AccessFilter] doesn't match a message it handles. Equivalent to
+    /// logical `NOT`.
+    Not(Box<AccessFilter>),
+}
+
+impl serde::Serialize for AccessFilter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        AccessFilter::serialize(self, serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for AccessFilter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Deserialized into a value first to keep the errors of both forms
+        match serde_json::Value::deserialize(deserializer)? {
+            serde_json::Value::String(text) => dsl::parse(&text).map_err(de::Error::custom),
+            value => AccessFilter::deserialize(value).map_err(de::Error::custom),
+        }
+    }
+}
+
+fn tag<'a>(mssg: &'a irc_rust::Message, name: &str) -> Option<&'a str> {
+    mssg.tags()
+        .ok()
+        .unwrap_or(None)?
+        .iter()
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+fn username(mssg: &irc_rust::Message) -> Option<&str> {
+    let (name, _, _) = mssg.prefix().ok().unwrap_or(None)?.into_parts();
+    Some(name)
+}
+
+fn channel(mssg: &irc_rust::Message) -> Option<&str> {
+    let (mut params, _) = mssg.params()?.into_parts();
+    params.next().filter(|channel| channel.starts_with('#'))
+}
+
+fn trailing(mssg: &irc_rust::Message) -> Option<&str> {
+    let (_, trailing) = mssg.params()?.into_parts();
+    trailing
+}
+
+impl AccessFilter {
+    /// Creates a [AccessFilter::Badge] filter. Fails if `pattern` isn't a valid regex.
+    pub fn badge(pattern: &str) -> Result<Self, FilterError> {
+        FilterRegex::new(pattern).map(AccessFilter::Badge)
+    }
+
+    /// Creates a [AccessFilter::Trailing] filter. Fails if `pattern` isn't a valid regex.
+    pub fn trailing(pattern: &str) -> Result<Self, FilterError> {
+        FilterRegex::new(pattern).map(AccessFilter::Trailing)
+    }
+
+    pub fn broadcaster() -> AccessFilter {
+        AccessFilter::badge("broadcaster/*").expect("invalid broadcaster regex")
+    }
+
+    /// Creates a [AccessFilter::Tag] filter. Fails if `pattern` isn't a valid regex.
+    pub fn tag(name: &str, pattern: &str) -> Result<Self, FilterError> {
+        FilterRegex::new(pattern).map(|regex| AccessFilter::Tag {
+            name: name.to_string(),
+            regex,
+        })
+    }
+
+    /// Creates a [AccessFilter::Not] filter.
+    pub fn negate(filter: AccessFilter) -> Self {
+        AccessFilter::Not(Box::new(filter))
+    }
+
+    /// Only allows messages not invoking any commands.
+    pub fn default_command_start() -> Self {
+        AccessFilter::trailing(r"^\s*[^!\?¡¿]").expect("invalid command start regex")
+    }
+
+    /// Checks the message against the filter and records the results of it and all nested
+    /// filters.
+    pub fn explain(&self, mssg: &Message) -> FilterTrace<'_> {
+        let children = match self {
+            AccessFilter::All(filters) | AccessFilter::Any(filters) => {
+                filters.iter().map(|filter| filter.explain(mssg)).collect()
+            }
+            AccessFilter::Not(filter) => vec![filter.explain(mssg)],
+            _ => Vec::new(),
+        };
+        FilterTrace {
+            filter: self,
+            handles: self.handles(mssg),
+            matches: self.matches(mssg),
+            input: self.input(mssg).map(str::to_string),
+            children,
+        }
+    }
+
+    /// Returns the part of the message the filter looks at.
+    fn input<'a>(&self, mssg: &'a Message) -> Option<&'a str> {
+        match (self, mssg) {
+            (AccessFilter::Badge(_), Message::Irc(mssg)) => tag(mssg, "badges"),
+            (AccessFilter::Trailing(_), Message::Irc(mssg)) => trailing(mssg),
+            (AccessFilter::UserId(_), Message::Irc(mssg)) => tag(mssg, "user-id"),
+            (AccessFilter::Username(_), Message::Irc(mssg)) => username(mssg),
+            (AccessFilter::Channel(_), Message::Irc(mssg)) => channel(mssg),
+            (AccessFilter::Command(_), Message::Irc(mssg)) => Some(mssg.command()),
+            (AccessFilter::Tag { name, .. }, Message::Irc(mssg)) => tag(mssg, name),
+            (AccessFilter::All(_), _) | (AccessFilter::Any(_), _) | (AccessFilter::Not(_), _) => {
+                None
+            }
+        }
+    }
+
+    /// Returns if the filter is handling the message. This can mean multiple things based
+    /// on the type of filter and message:
+    ///
+    /// - [AccessFilter::Badge] and [Message::Irc] : If the Irc-Message has tags and
+    /// - [AccessFilter::UserId] and [AccessFilter::Tag] : If the message has the tag,
+    /// - [AccessFilter::Username] : If the message has a prefix,
+    /// - [AccessFilter::Channel] : If the first parameter is a channel,
+    /// - [AccessFilter::Command] : Always,
+    /// - [AccessFilter::Not] : If the inner filter handles the message.
+    pub fn handles(&self, mssg: &Message) -> bool {
+        match (self, mssg) {
+            (AccessFilter::Badge(_), Message::Irc(mssg)) => tag(mssg, "badges").is_some(),
+            (AccessFilter::Trailing(_), Message::Irc(mssg)) => trailing(mssg).is_some(),
+            (AccessFilter::All(filters), mssg) => filters.iter().all(|filter| filter.handles(mssg)),
+            (AccessFilter::Any(filters), mssg) => filters.iter().any(|filter| filter.handles(mssg)),
+            (AccessFilter::UserId(_), Message::Irc(mssg)) => tag(mssg, "user-id").is_some(),
+            (AccessFilter::Username(_), Message::Irc(mssg)) => username(mssg).is_some(),
+            (AccessFilter::Channel(_), Message::Irc(mssg)) => channel(mssg).is_some(),
+            (AccessFilter::Command(_), Message::Irc(_)) => true,
+            (AccessFilter::Tag { name, .. }, Message::Irc(mssg)) => tag(mssg, name).is_some(),
+            (AccessFilter::Not(filter), mssg) => filter.handles(mssg),
+        }
+    }
+
+    pub fn matches(&self, mssg: &Message) -> bool {
+        match (self, mssg) {
+            (AccessFilter::Badge(regex), Message::Irc(mssg)) => {
+                if let Err(why) = mssg.tags() {
+                    error!("failed to parse tags from irc message: {:?}", why);
+                    return false;
+                }
+
+                tag(mssg, "badges")
+                    .map(|badges| badges.split(',').any(|badge| regex.is_match(badge)))
+                    .unwrap_or(false)
+            }
+            (AccessFilter::Trailing(regex), Message::Irc(mssg)) => trailing(mssg)
+                .map(|trailing| regex.is_match(trailing))
+                .unwrap_or(false),
+            (AccessFilter::All(filters), mssg) => filters.iter().all(|filter| filter.matches(mssg)),
+            (AccessFilter::Any(filters), mssg) => filters.iter().any(|filter| filter.matches(mssg)),
+            (AccessFilter::UserId(id), Message::Irc(mssg)) => {
+                tag(mssg, "user-id") == Some(id.as_str())
+            }
+            (AccessFilter::Username(name), Message::Irc(mssg)) => username(mssg)
+                .map(|username| username.eq_ignore_ascii_case(name))
+                .unwrap_or(false),
+            (AccessFilter::Channel(expected), Message::Irc(mssg)) => channel(mssg)
+                .map(|channel| {
+                    channel
+                        .trim_start_matches('#')
+                        .eq_ignore_ascii_case(expected.trim_start_matches('#'))
+                })
+                .unwrap_or(false),
+            (AccessFilter::Command(command), Message::Irc(mssg)) => {
+                mssg.command().eq_ignore_ascii_case(command)
+            }
+            (AccessFilter::Tag { name, regex }, Message::Irc(mssg)) => tag(mssg, name)
+                .map(|value| regex.is_match(value))
+                .unwrap_or(false),
+            (AccessFilter::Not(filter), mssg) => filter.handles(mssg) && !filter.matches(mssg),
+        }
+    }
+}
+
+impl Display for AccessFilter {
+    /// Writes the filter in the language parsed by [dsl::parse].
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (kind, value) = match self {
+            AccessFilter::Badge(regex) => ("badge", regex.to_string()),
+            AccessFilter::Trailing(regex) => ("trailing", regex.to_string()),
+            AccessFilter::UserId(id) => ("user-id", id.clone()),
+            AccessFilter::Username(name) => ("user", name.clone()),
+            AccessFilter::Channel(channel) => ("channel", channel.clone()),
+            AccessFilter::Command(command) => ("command", command.clone()),
+            AccessFilter::Tag { name, regex } => ("tag", format!("{}={}", name, regex)),
+            AccessFilter::Not(filter) => return write!(f, "NOT {}", filter),
+            AccessFilter::All(filters) => return write_group(f, "ALL", " AND ", filters),
+            AccessFilter::Any(filters) => return write_group(f, "ANY", " OR ", filters),
+        };
+        write!(f, "{}:", kind)?;
+        dsl::write_value(f, &value)
+    }
+}
+
+/// Writes `filters` joined by `operator`. Groups with less than two filters are written as
+/// `ALL(...)` or `ANY(...)` to be parsed into the same filter.
+fn write_group(
+    f: &mut Formatter<'_>,
+    keyword: &str,
+    operator: &str,
+    filters: &[AccessFilter],
+) -> fmt::Result {
+    if filters.len() < 2 {
+        write!(f, "{}(", keyword)?;
+        if let Some(filter) = filters.first() {
+            filter.fmt(f)?;
+        }
+        return write!(f, ")");
+    }
+    write!(f, "( ")?;
+    let mut iter = filters.iter();
+    if let Some(first) = iter.next() {
+        first.fmt(f)?;
+    }
+    for filter in iter {
+        write!(f, "{}", operator)?;
+        filter.fmt(f)?;
+    }
+    write!(f, " )")
+}
+
+impl FromStr for AccessFilter {
+    type Err = ParseError;
+
+    /// Parses a filter written in the language of [dsl].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        dsl::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command_access::{AccessFilter, AccessRights, AccessVerdict, Rules};
+    use crate::plugin::PluginInfo;
+    use crate::Message;
+
+    #[test]
+    fn test_badge_filter() {
+        let badge_filter = AccessFilter::badge("moderator/*").unwrap();
+
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .tag("badges", "moderator/1")
+                .build(),
+        );
+        assert!(badge_filter.handles(&message));
+        assert!(badge_filter.matches(&message));
+
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .tag("badges", "subscriber/1")
+                .build(),
+        );
+        assert!(badge_filter.handles(&message));
+        assert!(!badge_filter.matches(&message));
+
+        let message = Message::Irc(irc_rust::Message::builder("PRIVMSG").build());
+        assert!(!badge_filter.handles(&message));
+        assert!(!badge_filter.matches(&message));
+    }
+
+    #[test]
+    fn test_trailing_filter() {
+        let trailing_filter = AccessFilter::trailing("^!command$").unwrap();
+
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .trailing("!command")
+                .build(),
+        );
+        assert!(trailing_filter.handles(&message));
+        assert!(trailing_filter.matches(&message));
+
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .trailing("Invalid")
+                .build(),
+        );
+        assert!(trailing_filter.handles(&message));
+        assert!(!trailing_filter.matches(&message));
+
+        let message = Message::Irc(irc_rust::Message::builder("PRIVMSG").build());
+        assert!(!trailing_filter.handles(&message));
+    }
+
+    #[test]
+    fn test_all_filter() {
+        let all_filter = AccessFilter::All(vec![
+            AccessFilter::badge("moderator/*").unwrap(),
+            AccessFilter::trailing("^hello, world!$").unwrap(),
+        ]);
+
+        // Everything as expected
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .trailing("hello, world!")
+                .tag("badges", "moderator/1")
+                .build(),
+        );
+        assert!(all_filter.handles(&message));
+        assert!(all_filter.matches(&message));
+
+        // Handles but badge doesn't match
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .trailing("hello, world!")
+                .tag("badges", "broadcaster/1")
+                .build(),
+        );
+        assert!(all_filter.handles(&message));
+        assert!(!all_filter.matches(&message));
+
+        // Handles but trailing doesn't match
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .trailing("hello, kevin!")
+                .tag("badges", "moderator/1")
+                .build(),
+        );
+        assert!(all_filter.handles(&message));
+        assert!(!all_filter.matches(&message));
+
+        // Missing tag isn't handled
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .trailing("hello, world!")
+                .build(),
+        );
+        assert!(!all_filter.handles(&message));
+
+        // Missing trailing isn't handled
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .tag("badges", "moderator/1")
+                .build(),
+        );
+        assert!(!all_filter.handles(&message));
+
+        let message = Message::Irc(irc_rust::Message::builder("PRIVMSG").build());
+        assert!(!all_filter.handles(&message));
+    }
+
+    #[test]
+    fn test_any_filter() {
+        let any_filter = AccessFilter::Any(vec![
+            AccessFilter::badge("moderator/*").unwrap(),
+            AccessFilter::trailing("^hello, world!$").unwrap(),
+        ]);
+
+        // Everything as expected
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .trailing("hello, world!")
+                .tag("badges", "moderator/1")
+                .build(),
+        );
+        assert!(any_filter.handles(&message));
+        assert!(any_filter.matches(&message));
+
+        // Matches if any of the filters matches
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .trailing("hello, world!")
+                .tag("badges", "broadcaster/1")
+                .build(),
+        );
+        assert!(any_filter.handles(&message));
+        assert!(any_filter.matches(&message));
+
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .trailing("hello, kevin!")
+                .tag("badges", "moderator/1")
+                .build(),
+        );
+        assert!(any_filter.handles(&message));
+        assert!(any_filter.matches(&message));
+
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .trailing("hello, world!")
+                .build(),
+        );
+        assert!(any_filter.handles(&message));
+        assert!(any_filter.matches(&message));
+
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .tag("badges", "moderator/1")
+                .build(),
+        );
+        assert!(any_filter.handles(&message));
+        assert!(any_filter.matches(&message));
+
+        let message = Message::Irc(irc_rust::Message::builder("PRIVMSG").build());
+        assert!(!any_filter.handles(&message));
+    }
+
+    #[test]
+    fn test_user_filters() {
+        let message = Message::Irc(irc_rust::Message::from(
+            "@user-id=1234;subscriber=1 :Someone!someone@someone.tmi.twitch.tv PRIVMSG #Foo :hello",
+        ));
+
+        assert!(AccessFilter::UserId("1234".to_string()).matches(&message));
+        assert!(!AccessFilter::UserId("123".to_string()).matches(&message));
+        assert!(AccessFilter::Username("someone".to_string()).matches(&message));
+        assert!(!AccessFilter::Username("other".to_string()).matches(&message));
+        assert!(AccessFilter::Channel("#foo".to_string()).matches(&message));
+        assert!(AccessFilter::Channel("foo".to_string()).matches(&message));
+        assert!(!AccessFilter::Channel("#bar".to_string()).matches(&message));
+        assert!(AccessFilter::Command("privmsg".to_string()).matches(&message));
+        assert!(!AccessFilter::Command("WHISPER".to_string()).matches(&message));
+        assert!(AccessFilter::tag("subscriber", "^1$")
+            .unwrap()
+            .matches(&message));
+        assert!(!AccessFilter::tag("subscriber", "^0$")
+            .unwrap()
+            .matches(&message));
+
+        let message = Message::Irc(irc_rust::Message::from("PING :tmi.twitch.tv"));
+        assert!(!AccessFilter::UserId("1234".to_string()).handles(&message));
+        assert!(!AccessFilter::Username("someone".to_string()).handles(&message));
+        assert!(!AccessFilter::Channel("#foo".to_string()).handles(&message));
+        assert!(AccessFilter::Command("PING".to_string()).handles(&message));
+        assert!(!AccessFilter::tag("subscriber", "^1$")
+            .unwrap()
+            .handles(&message));
+    }
+
+    #[test]
+    fn test_not_filter() {
+        let not_filter = AccessFilter::negate(AccessFilter::badge("moderator/*").unwrap());
+
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .tag("badges", "subscriber/1")
+                .build(),
+        );
+        assert!(not_filter.handles(&message));
+        assert!(not_filter.matches(&message));
+
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .tag("badges", "moderator/1")
+                .build(),
+        );
+        assert!(!not_filter.matches(&message));
+
+        let message = Message::Irc(irc_rust::Message::builder("PRIVMSG").build());
+        assert!(!not_filter.handles(&message));
+        assert!(!not_filter.matches(&message));
+        assert_eq!(not_filter.to_string(), "NOT badge:moderator/*");
+    }
+
+    #[test]
+    fn test_command_rights() {
+        let mut rights = AccessRights::from(vec![AccessFilter::Any(vec![])]);
+        rights.set_command("!Ban", vec![AccessFilter::badge("moderator/*").unwrap()]);
+        rights.set_command(
+            "!hello",
+            vec![
+                AccessFilter::badge("subscriber/*").unwrap(),
+                AccessFilter::badge("vip/*").unwrap(),
+            ],
+        );
+        let message = |trailing: &str, badges: &str| {
+            Message::Irc(
+                irc_rust::Message::builder("PRIVMSG")
+                    .tag("badges", badges)
+                    .trailing(trailing)
+                    .build(),
+            )
+        };
+
+        let decision = rights.decide(&message("!ban someone", "moderator/1"));
+        assert_eq!(decision.verdict, AccessVerdict::Allowed);
+        assert_eq!(decision.rules, Rules::Command("!ban"));
+        assert_eq!(
+            decision.to_string(),
+            "Allowed by badge:moderator/* for !ban"
+        );
+
+        let decision = rights.decide(&message("!BAN someone", "vip/1"));
+        assert_eq!(decision.verdict, AccessVerdict::Denied);
+        assert_eq!(decision.to_string(), "Denied by badge:moderator/* for !ban");
+
+        let decision = rights.decide(&message("!hello", "vip/1"));
+        assert_eq!(decision.verdict, AccessVerdict::Allowed);
+        assert_eq!(
+            decision.filter,
+            Some(&AccessFilter::badge("vip/*").unwrap())
+        );
+
+        let decision = rights.decide(&message("!other", "vip/1"));
+        assert_eq!(decision.verdict, AccessVerdict::Unchecked);
+        assert_eq!(decision.rules, Rules::Default);
+        assert_eq!(decision.to_string(), "Unchecked");
+
+        assert_eq!(rights.command("!BAN").map(<[_]>::len), Some(1));
+        assert!(rights.remove_command("!ban").is_some());
+        assert_eq!(
+            rights.verdict(&message("!ban someone", "vip/1")),
+            AccessVerdict::Unchecked
+        );
+
+        let json = serde_json::to_string(&rights).unwrap();
+        assert_eq!(serde_json::from_str::<AccessRights>(&json).unwrap(), rights);
+    }
+
+    #[test]
+    fn test_explain() {
+        let mut rights = AccessRights::new();
+        rights.set_command(
+            "!ban",
+            vec![AccessFilter::All(vec![
+                AccessFilter::badge("moderator/*").unwrap(),
+                AccessFilter::negate(AccessFilter::UserId("1234".to_string())),
+            ])],
+        );
+        let message = Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .tag("badges", "moderator/1")
+                .tag("user-id", "1234")
+                .param("#channel")
+                .trailing("!ban someone")
+                .build(),
+        );
+
+        let trace = rights.explain(&message);
+        assert_eq!(trace.decision.verdict, AccessVerdict::Denied);
+        assert_eq!(trace.filters.len(), 1);
+        let all = &trace.filters[0];
+        assert!(all.handles && !all.matches);
+        assert_eq!(all.input, None);
+        assert_eq!(all.children[0].input, Some("moderator/1".to_string()));
+        assert!(all.children[0].matches);
+        assert!(!all.children[1].matches);
+        assert_eq!(all.children[1].children[0].input, Some("1234".to_string()));
+        assert_eq!(
+            trace.to_string(),
+            r#"Denied by ( badge:moderator/* AND NOT user-id:1234 ) for !ban
+  ALL -> not matched
+    badge:moderator/* -> matched ("moderator/1")
+    NOT -> not matched
+      user-id:1234 -> matched ("1234")"#
+        );
+
+        let trace = rights.explain(&Message::Irc(
+            irc_rust::Message::builder("PRIVMSG")
+                .tag("badges", "subscriber/1")
+                .trailing("!hello")
+                .build(),
+        ));
+        assert_eq!(trace.decision.verdict, AccessVerdict::Denied);
+        assert_eq!(
+            trace.filters[0].to_string(),
+            r#"ANY -> not matched
+  badge:broadcaster/* -> not matched ("subscriber/1")
+  trailing:^\s*[^!\?¡¿] -> not matched ("!hello")"#
+        );
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let error = AccessFilter::badge("moderator/(").unwrap_err();
+        assert_eq!(error.pattern, "moderator/(");
+
+        let rights = AccessRights::from(vec![AccessFilter::broadcaster()]);
+        let json = serde_json::to_string(&rights).unwrap();
+        assert_eq!(json, r#"{"filters":[{"Badge":"broadcaster/*"}]}"#);
+        assert_eq!(serde_json::from_str::<AccessRights>(&json).unwrap(), rights);
+        assert!(serde_json::from_str::<AccessRights>(r#"{"filters":[{"Trailing":"("}]}"#).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_text() {
+        let rights = serde_json::from_str::<AccessRights>(
+            r#"{"filters":["badge:moderator/* OR user:someone",{"Badge":"vip/*"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            rights,
+            AccessRights::from(vec![
+                AccessFilter::Any(vec![
+                    AccessFilter::badge("moderator/*").unwrap(),
+                    AccessFilter::Username("someone".to_string()),
+                ]),
+                AccessFilter::badge("vip/*").unwrap(),
+            ])
+        );
+
+        let error =
+            serde_json::from_str::<AccessRights>(r#"{"filters":["badge:vip/* AND"]}"#).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("expected a filter, found end of input at line 1, column 16"));
+        // Errors of nested filters are kept
+        let error = serde_json::from_str::<AccessRights>(
+            r#"{"filters":[{"Not":{"Any":["user:a","badge:vip/* AND"]}}]}"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("expected a filter"), "{}", error);
+        let error =
+            serde_json::from_str::<AccessRights>(r#"{"filters":[{"Trailing":"("}]}"#).unwrap_err();
+        assert!(error.to_string().contains("regex"), "{}", error);
+    }
+
+    #[test]
+    fn test_verdict_for() {
+        let mut info = PluginInfo {
+            name: "".to_string(),
+            version: "".to_string(),
+            authors: "".to_string(),
+            repo: None,
+            commands: vec!["!ban".to_string()],
+            required_rights: Default::default(),
+        };
+        info.required_rights.insert(
+            "!BAN".to_string(),
+            AccessFilter::badge("moderator/*").unwrap(),
+        );
+        // Commands without prefix are invoked with the default prefix
+        info.required_rights
+            .insert("kick".to_string(), AccessFilter::broadcaster());
+        assert_eq!(info.rights_for("!Kick"), Some(&AccessFilter::broadcaster()));
+        let message = |trailing: &str, badges: &str| {
+            Message::Irc(
+                irc_rust::Message::builder("PRIVMSG")
+                    .tag("badges", badges)
+                    .trailing(trailing)
+                    .build(),
+            )
+        };
+
+        let rights = AccessRights::empty();
+        assert_eq!(
+            rights.verdict_for(&info, &message("hello", "")),
+            AccessVerdict::Unchecked
+        );
+        assert_eq!(
+            rights.verdict_for(&info, &message("!ban someone", "moderator/1")),
+            AccessVerdict::Allowed
+        );
+        assert_eq!(
+            rights.verdict_for(&info, &message("!ban someone", "subscriber/1")),
+            AccessVerdict::Denied
+        );
+
+        let rights = AccessRights::new();
+        assert_eq!(
+            rights.verdict_for(&info, &message("!ban someone", "broadcaster/1")),
+            AccessVerdict::Denied
+        );
+        assert_eq!(
+            rights.verdict_for(&info, &message("!ban someone", "broadcaster/1,moderator/1")),
+            AccessVerdict::Allowed
+        );
+        assert_eq!(
+            rights.verdict_for(&info, &message("!hello", "moderator/1")),
+            AccessVerdict::Denied
+        );
+    }
+}
@@ -0,0 +1,51 @@
+//! Runtime context of the plugins of a profile.
+//!
+//! A plugin-loader creates one [crate::plugins::Plugins] per profile with
+//! [crate::plugins::Plugins::for_profile]. Every plugin registered by it gets the [Context] of
+//! its profile through [crate::plugin::PluginRegistrar::context], so one process can run the
+//! bots of several profiles concurrently. Plugins should keep their state in the instances
+//! created on registration to keep the profiles isolated.
+
+use crate::profile::watch::{ProfileEvent, ProfileEvents};
+use crate::profile::Profile;
+use futures::channel::mpsc::UnboundedReceiver;
+use std::sync::Arc;
+
+/// Context of the plugins of one profile. Cloning is cheap.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Context {
+    profile: Arc<Profile>,
+    events: ProfileEvents,
+}
+
+impl Context {
+    pub fn new(profile: Profile) -> Self {
+        Context {
+            profile: Arc::new(profile),
+            events: ProfileEvents::default(),
+        }
+    }
+
+    /// Returns the profile owning the plugins.
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Returns the changes of the profile published by a [crate::profile::watch::ProfileWatcher]
+    /// publishing to [Context::events]. [Context::profile] keeps the profile the plugins were
+    /// loaded with.
+    pub fn profile_events(&self) -> UnboundedReceiver<ProfileEvent> {
+        self.events.subscribe()
+    }
+
+    /// Returns the subscribers of the changes of the profile.
+    pub fn events(&self) -> &ProfileEvents {
+        &self.events
+    }
+}
+
+impl From<Profile> for Context {
+    fn from(profile: Profile) -> Self {
+        Context::new(profile)
+    }
+}
@@ -0,0 +1,306 @@
+//! Cooldowns of commands limiting how often they can be invoked.
+//!
+//! Cooldowns are configured per command in the [crate::profile::Profile] and apply globally,
+//! per channel and per user (keyed on [UserInfo::to_global_id]). Messages matching any of the
+//! exemptions (e.g. [AccessFilter::broadcaster]) aren't limited.
+//!
+//! The plugin-loader drops commands on cooldown before sending them to the plugins. Plugins can
+//! check cooldowns themselves through a [CooldownTracker].
+
+use crate::auth::UserInfo;
+use crate::command_access::AccessFilter;
+use crate::router;
+use crate::Message;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Number of tracked invocations after which expired ones are removed.
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Cooldowns of a single command. Durations are configured in seconds.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Cooldown {
+    /// Time between two invocations of the command.
+    #[serde(default, with = "secs", skip_serializing_if = "Option::is_none")]
+    pub global: Option<Duration>,
+    /// Time between two invocations of the command in the same channel.
+    #[serde(default, with = "secs", skip_serializing_if = "Option::is_none")]
+    pub channel: Option<Duration>,
+    /// Time between two invocations of the command by the same user.
+    #[serde(default, with = "secs", skip_serializing_if = "Option::is_none")]
+    pub user: Option<Duration>,
+}
+
+impl Display for Cooldown {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let scopes = [
+            ("Global", self.global),
+            ("Channel", self.channel),
+            ("User", self.user),
+        ];
+        let mut first = true;
+        for (scope, duration) in scopes.iter() {
+            if let Some(duration) = duration {
+                if !first {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{} {:?}", scope, duration)?;
+                first = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Cooldowns of all commands of a profile.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Cooldowns {
+    /// Maps commands (e.g. `!hello`) to their cooldowns.
+    #[serde(default)]
+    pub commands: BTreeMap<String, Cooldown>,
+    /// Messages matching any of these filters aren't limited.
+    #[serde(default)]
+    pub exempt: Vec<AccessFilter>,
+}
+
+impl Cooldowns {
+    /// Returns if no command has a cooldown.
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Returns the cooldown of `command`. Commands are compared case-insensitively.
+    pub fn get(&self, command: &str) -> Option<&Cooldown> {
+        self.commands
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(command))
+            .map(|(_, cooldown)| cooldown)
+    }
+
+    /// Sets the cooldown of `command`.
+    pub fn set<S: Into<String>>(&mut self, command: S, cooldown: Cooldown) {
+        self.commands
+            .insert(command.into().to_lowercase(), cooldown);
+    }
+}
+
+/// Result of checking a message against the cooldowns.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum CooldownVerdict {
+    Allowed,
+    /// The command is on cooldown and can be invoked again after the duration.
+    RetryAfter(Duration),
+}
+
+impl CooldownVerdict {
+    pub fn is_allowed(&self) -> bool {
+        *self == CooldownVerdict::Allowed
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+enum Scope {
+    Global,
+    Channel(String),
+    User(String),
+}
+
+/// Tracks the invocations of commands to check them against [Cooldowns].
+#[derive(Debug, Default)]
+pub struct CooldownTracker {
+    cooldowns: Cooldowns,
+    // Maps commands and scopes to the end of their cooldown
+    until: Mutex<HashMap<(String, Scope), Instant>>,
+}
+
+impl CooldownTracker {
+    pub fn new(cooldowns: Cooldowns) -> Self {
+        CooldownTracker {
+            cooldowns,
+            until: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn cooldowns(&self) -> &Cooldowns {
+        &self.cooldowns
+    }
+
+    /// Checks if the command invoked by `mssg` is on cooldown. Allowed invocations start the
+    /// cooldowns of the command.
+    pub fn check(&self, mssg: &Message) -> CooldownVerdict {
+        self.check_at(mssg, Instant::now())
+    }
+
+    /// Checks like [CooldownTracker::check] at the time `now`.
+    pub fn check_at(&self, mssg: &Message, now: Instant) -> CooldownVerdict {
+        let command = match router::command(mssg) {
+            Some(command) => command,
+            None => return CooldownVerdict::Allowed,
+        };
+        let cooldown = match self.cooldowns.get(&command) {
+            Some(cooldown) => *cooldown,
+            None => return CooldownVerdict::Allowed,
+        };
+        if self
+            .cooldowns
+            .exempt
+            .iter()
+            .any(|filter| filter.matches(mssg))
+        {
+            return CooldownVerdict::Allowed;
+        }
+
+        let Message::Irc(irc) = mssg;
+        let mut scopes = Vec::with_capacity(3);
+        if let Some(duration) = cooldown.global {
+            scopes.push((Scope::Global, duration));
+        }
+        if let Some(duration) = cooldown.channel {
+            if let Some(channel) = irc.params().and_then(|params| params.into_parts().0.next()) {
+                scopes.push((Scope::Channel(channel.to_lowercase()), duration));
+            }
+        }
+        if let Some(duration) = cooldown.user {
+            if let Ok(user) = UserInfo::try_from(irc) {
+                scopes.push((Scope::User(user.to_global_id()), duration));
+            }
+        }
+
+        let mut until = self.until.lock().expect("cooldown lock poisoned");
+        let remaining = scopes
+            .iter()
+            .filter_map(|(scope, _)| until.get(&(command.clone(), scope.clone())))
+            .filter(|until| **until > now)
+            .map(|until| *until - now)
+            .max();
+        if let Some(remaining) = remaining {
+            return CooldownVerdict::RetryAfter(remaining);
+        }
+
+        if until.len() > PRUNE_THRESHOLD {
+            until.retain(|_, until| *until > now);
+        }
+        for (scope, duration) in scopes {
+            until.insert((command.clone(), scope), now + duration);
+        }
+        CooldownVerdict::Allowed
+    }
+}
+
+/// (De-)serializes optional durations as seconds.
+mod secs {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&duration.as_secs_f64()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        match Option::<f64>::deserialize(deserializer)? {
+            // Duration::from_secs_f64 panics on values not representable as Duration
+            Some(secs) if secs.is_finite() && secs >= 0.0 && secs < u64::MAX as f64 => {
+                Ok(Some(Duration::from_secs_f64(secs)))
+            }
+            Some(secs) => Err(D::Error::custom(format!("invalid cooldown: {}", secs))),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command_access::AccessFilter;
+    use crate::cooldown::{Cooldown, CooldownTracker, CooldownVerdict, Cooldowns};
+    use crate::Message;
+    use std::time::{Duration, Instant};
+
+    fn message(user_id: &str, channel: &str, badges: &str) -> Message {
+        Message::Irc(irc_rust::Message::from(format!(
+            "@badges={};user-id={} :user!user@user.tmi.twitch.tv PRIVMSG {} :!hello",
+            badges, user_id, channel
+        )))
+    }
+
+    #[test]
+    fn test_cooldowns() {
+        let mut cooldowns = Cooldowns::default();
+        cooldowns.set(
+            "!Hello",
+            Cooldown {
+                global: Some(Duration::from_secs(1)),
+                channel: Some(Duration::from_secs(5)),
+                user: Some(Duration::from_secs(30)),
+            },
+        );
+        cooldowns.exempt.push(AccessFilter::broadcaster());
+        let tracker = CooldownTracker::new(cooldowns);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(tracker
+            .check_at(&message("1", "#foo", "vip/1"), at(0))
+            .is_allowed());
+        // Global cooldown
+        assert_eq!(
+            tracker.check_at(&message("2", "#bar", "vip/1"), at(0)),
+            CooldownVerdict::RetryAfter(Duration::from_secs(1))
+        );
+        assert!(tracker
+            .check_at(&message("2", "#bar", "vip/1"), at(1))
+            .is_allowed());
+        // Channel cooldown
+        assert_eq!(
+            tracker.check_at(&message("3", "#foo", "vip/1"), at(2)),
+            CooldownVerdict::RetryAfter(Duration::from_secs(3))
+        );
+        // User cooldown
+        assert_eq!(
+            tracker.check_at(&message("1", "#baz", "vip/1"), at(10)),
+            CooldownVerdict::RetryAfter(Duration::from_secs(20))
+        );
+        // Exempt
+        assert!(tracker
+            .check_at(&message("1", "#foo", "broadcaster/1"), at(2))
+            .is_allowed());
+        // Other commands
+        assert!(tracker
+            .check_at(
+                &Message::Irc(irc_rust::Message::from("PRIVMSG #foo :!other")),
+                at(2)
+            )
+            .is_allowed());
+    }
+
+    #[test]
+    fn test_serde() {
+        let cooldowns = serde_json::from_str::<Cooldowns>(
+            r#"{"commands":{"!hello":{"user":1.5}},"exempt":["badge:broadcaster/*"]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            cooldowns.get("!HELLO"),
+            Some(&Cooldown {
+                user: Some(Duration::from_millis(1500)),
+                ..Cooldown::default()
+            })
+        );
+        assert_eq!(cooldowns.exempt, vec![AccessFilter::broadcaster()]);
+        for invalid in &["-1", "1e30", "18446744073709551616"] {
+            let config = format!(r#"{{"commands":{{"!hello":{{"user":{}}}}}}}"#, invalid);
+            assert!(serde_json::from_str::<Cooldowns>(&config).is_err());
+        }
+    }
+}
@@ -56,6 +56,7 @@
 //!     use async_trait::async_trait;
 //!
 //!     use bot_rs_core::Message;
+//!     use bot_rs_core::command_access::AccessFilter;
 //!     use bot_rs_core::plugin::{StreamablePlugin, Plugin, InvocationError, PluginInfo, PluginRegistrar};
 //!     use std::sync::Arc;
 //!     use bot_rs_core::profile::Profile;
@@ -114,14 +115,12 @@
 //!
 //!         // Return information about the plugin for identification.
 //!         fn info(&self) -> PluginInfo {
-//!             PluginInfo {
-//!                 name: "Hello Plugin".to_string(),
-//!                 version: env!("CARGO_PKG_VERSION").to_string(),
-//!                 authors: env!("CARGO_PKG_AUTHORS").to_string(),
-//!                 repo: option_env!("CARGO_PKG_REPOSITORY")
-//!                     .map(|repo| if repo.is_empty() { "No repo".to_string() } else { repo.to_string() }),
-//!                 commands: vec!["!hello".to_string()]
-//!             }
+//!             PluginInfo::new("Hello Plugin", env!("CARGO_PKG_VERSION"), env!("CARGO_PKG_AUTHORS"))
+//!                 .with_repo(env!("CARGO_PKG_REPOSITORY"))
+//!                 .with_command("!hello")
+//!                 // Rights a message has to match to invoke a command. Messages not matching
+//!                 // them are not sent to the plugin if access checks are enabled.
+//!                 .with_required_rights("!hello", AccessFilter::broadcaster())
 //!         }
 //!     }
 //!
@@ -130,9 +129,14 @@
 //!
 //!     // The plugin loading mechanism uses this function for load and register. Initializing loggers and other dependencies has to be done here.
 //!     extern "C" fn register(registrar: &mut PluginRegistrar) {
-//!         env_logger::init();
-//!         // Is set on startup by Bot-RS CLI Tool
-//!         let profile = Profile::active().unwrap();
+//!         // Called once for every profile loading the plugin. Statics like the logger are shared
+//!         // by all of them as the library is only loaded once.
+//!         let _ = env_logger::try_init();
+//!         // Profile owning this instance of the plugin. Is set by the plugin-loader
+//!         let profile = registrar.context()
+//!             .expect("plugin loaded without context")
+//!             .profile()
+//!             .clone();
 //!         registrar.register(Arc::new(HelloPlugin{ profile }))
 //!     }
 //!     ```
@@ -190,7 +194,7 @@
 //!
 //!     use bot_rs_core::Message;
 //!     use bot_rs_core::plugin::{StreamablePlugin, Plugin, InvocationError, PluginInfo, PluginRegistrar};
-//!     use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
+//!     use futures::channel::mpsc::{Receiver, UnboundedSender};
 //!     use std::sync::Arc;
 //!     use futures::{StreamExt, SinkExt};
 //!
@@ -205,7 +209,7 @@
 //!     #[async_trait]
 //!     impl StreamablePlugin for HelloPlugin {
 //!         async fn stream(&self,
-//!             mut input: UnboundedReceiver<Message>,
+//!             mut input: Receiver<Message>,
 //!             mut output: UnboundedSender<Vec<Message>>)
 //!         -> Result<(), InvocationError> {
 //!             // Read next message from input channel
@@ -230,7 +234,7 @@
 //!
 //!     // The plugin loading mechanism uses this function for load and register. Initializing loggers and other dependencies has to be done here.
 //!     extern "C" fn register(registrar: &mut PluginRegistrar) {
-//!         env_logger::init();
+//!         let _ = env_logger::try_init();
 //!         registrar.register(Arc::new(HelloPlugin))
 //!     }
 //!     ```
@@ -244,6 +248,32 @@
 //!     For more infos read this guide on reducing the size of rust binaries/libraries: [](https://github.com/johnthagen/min-sized-rust).
 //! 6. Building the plugin file: `cargo build --release`
 //!
+//! ### Exporting a Plugin through the stable ABI
+//!
+//! Plugins exported with `export_command!` only load into plugin-loaders built with the exact same rustc and `bot-rs-core` version.
+//! **Simple Plugins** can instead be exported with `export_plugin!` which uses a stable C ABI. These plugins load into every plugin-loader with a compatible `bot-rs-core` version (e.g. a plugin built against `0.4.1` loads into a `0.4.4` plugin-loader).
+//! See the [abi] module for details.
+//! ```ignore
+//! // Instead of export_command!(register)
+//! bot_rs_core::export_plugin!(create);
+//!
+//! // Gets the profile owning this instance of the plugin from the plugin-loader
+//! fn create(profile: Option<Profile>) -> HelloPlugin {
+//!     let _ = env_logger::try_init();
+//!     HelloPlugin { profile: profile.expect("plugin loaded without profile") }
+//! }
+//! ```
+//!
+//! ### Running a Plugin as a separate Process
+//!
+//! Plugins can also be written in any language and run as a child process of the plugin-loader. Such plugins are declared by a `<name>.process.json` manifest in the plugins directory and exchange messages as line-delimited JSON over stdin and stdout.
+//! Crashed processes are restarted with an exponential backoff. See the [process] module for details.
+//!
+//! ### Writing a Plugin in WebAssembly
+//!
+//! With the `wasm` feature enabled the plugin-loader also loads `.wasm` files from the plugins directory. WebAssembly plugins run sandboxed without access to the filesystem or network of the host and are platform independent.
+//! See the [wasm] module for the interface a plugin has to export.
+//!
 //! ## Plugin-Loader
 //!
 //! This is currently not publicly documented. The only implementation currently present is the [botrs cli](https://github.com/MoBlaa/bot-rs-cli). To use the plugins this cli tool is required.
@@ -264,22 +294,42 @@ extern crate test;
 #[macro_use]
 extern crate derive_more;
 
+#[cfg(feature = "default")]
+pub mod abi;
 #[cfg(feature = "default")]
 pub mod auth;
 #[cfg(feature = "default")]
 pub mod command_access;
 #[cfg(feature = "default")]
+pub mod context;
+#[cfg(feature = "default")]
+pub mod cooldown;
+#[cfg(feature = "default")]
 pub mod plugin;
 #[cfg(feature = "plugin-loader")]
 pub mod plugins;
+#[cfg(feature = "plugin-loader")]
+pub mod process;
 #[cfg(feature = "default")]
 pub mod profile;
+#[cfg(feature = "plugin-loader")]
+pub mod queue;
+#[cfg(feature = "default")]
+pub mod rate_limit;
+#[cfg(feature = "default")]
+pub mod router;
+#[cfg(feature = "default")]
+pub mod secrets;
+#[cfg(feature = "plugin-loader")]
+pub mod supervisor;
 #[cfg(feature = "twitch-api")]
 pub mod twitch_api;
 #[cfg(feature = "twitch-extensions")]
 pub mod twitch_extensions;
 #[cfg(feature = "twitch-api")]
 mod utils;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 #[cfg(feature = "twitch-extensions")]
 pub use twitch_extensions::irc::*;
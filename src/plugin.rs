@@ -1,22 +1,90 @@
 use core::fmt;
 use std::fmt::{Debug, Display, Formatter};
 use std::sync::Arc;
+use std::time::Instant;
 
 use async_trait::async_trait;
-use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use futures::channel::mpsc::{Receiver, UnboundedSender};
 use libloading::Library;
 
+use crate::command_access::AccessFilter;
+use crate::context::Context;
+use crate::profile::Profile;
+use crate::router;
 use crate::Message;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
 use std::error::Error;
 
 /// Contains information about a plugin to identify the supported commands, author information, etc.
-#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+///
+/// Fields may be added in minor versions, so plugins create their info with [PluginInfo::new]
+/// instead of a struct literal.
+#[derive(Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct PluginInfo {
     pub name: String,
     pub version: String,
     pub authors: String,
     pub repo: Option<String>,
+    /// Commands handled by the plugin (e.g. `!hello`). Used to route messages to the plugin,
+    /// see [crate::router].
     pub commands: Vec<String>,
+    /// Rights a message has to match to invoke a command. Maps the command to the filter.
+    #[serde(default)]
+    pub required_rights: BTreeMap<String, AccessFilter>,
+}
+
+impl PluginInfo {
+    /// Creates the info of a plugin without repository, commands and required rights.
+    pub fn new<N, V, A>(name: N, version: V, authors: A) -> Self
+    where
+        N: Into<String>,
+        V: Into<String>,
+        A: Into<String>,
+    {
+        PluginInfo {
+            name: name.into(),
+            version: version.into(),
+            authors: authors.into(),
+            ..PluginInfo::default()
+        }
+    }
+
+    /// Sets the repository of the plugin.
+    pub fn with_repo<S: Into<String>>(mut self, repo: S) -> Self {
+        self.repo = Some(repo.into());
+        self
+    }
+
+    /// Adds `command` to the [PluginInfo::commands].
+    pub fn with_command<S: Into<String>>(mut self, command: S) -> Self {
+        self.commands.push(command.into());
+        self
+    }
+
+    /// Requires messages invoking `command` to match `filter`. See
+    /// [PluginInfo::required_rights].
+    pub fn with_required_rights<S: Into<String>>(
+        mut self,
+        command: S,
+        filter: AccessFilter,
+    ) -> Self {
+        self.required_rights.insert(command.into(), filter);
+        self
+    }
+
+    /// Returns the rights required to invoke `command`. Commands are compared
+    /// case-insensitively and commands without prefix get the [router::DEFAULT_PREFIX] like
+    /// for routing.
+    pub fn rights_for(&self, command: &str) -> Option<&AccessFilter> {
+        let command = router::normalize(command);
+        self.required_rights
+            .iter()
+            .find(|(required, _)| router::normalize(required) == command)
+            .map(|(_, filter)| filter)
+    }
 }
 
 impl Display for PluginInfo {
@@ -32,36 +100,134 @@ impl Display for PluginInfo {
         } else {
             write!(f, "Commands: [{}]", self.commands.join(", "))?;
         }
+        for (command, filter) in self.required_rights.iter() {
+            write!(f, "\nRequired Rights {}: {}", command, filter)?;
+        }
         Ok(())
     }
 }
 
+/// Health of a plugin reported through [StreamablePlugin::health].
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum PluginHealth {
+    /// Plugin works as expected.
+    Healthy,
+    /// Plugin still processes messages but some functionality isn't available.
+    Degraded(String),
+    /// Plugin isn't able to process messages.
+    Unhealthy(String),
+}
+
+impl PluginHealth {
+    pub fn is_healthy(&self) -> bool {
+        matches!(self, PluginHealth::Healthy)
+    }
+}
+
+impl Display for PluginHealth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginHealth::Healthy => write!(f, "Healthy"),
+            PluginHealth::Degraded(reason) => write!(f, "Degraded: {}", reason),
+            PluginHealth::Unhealthy(reason) => write!(f, "Unhealthy: {}", reason),
+        }
+    }
+}
+
+/// Configuration section of a plugin in a profile (see [Profile::plugin_config_value]).
+pub type PluginConfig = Value;
+
 /// Handles single command invocations returning their result.
+///
+/// The lifecycle methods are called like their counterparts of [StreamablePlugin]. They're
+/// forwarded by the derived [StreamablePlugin] and by plugins exported through the stable ABI
+/// ([crate::abi]).
 #[async_trait]
 pub trait Plugin: Send + Sync {
     type Error;
 
+    /// See [StreamablePlugin::init].
+    async fn init(&self, _profile: &Profile) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     async fn call(&self, message: Message) -> Result<Vec<Message>, Self::Error>;
 
+    /// See [StreamablePlugin::shutdown].
+    async fn shutdown(&self, _deadline: Instant) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// See [StreamablePlugin::health].
+    async fn health(&self) -> PluginHealth {
+        PluginHealth::Healthy
+    }
+
+    /// See [StreamablePlugin::validate_config].
+    fn validate_config(&self, _config: Option<&PluginConfig>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     fn info(&self) -> PluginInfo;
 }
 
 /// Allows users to create an asynchronously running stream. This allows commands
 /// to send messages to the output without the need of a command invocation.
+///
+/// The plugin-loader calls the lifecycle methods in the following order:
+///
+/// 1. [StreamablePlugin::init] once after the plugin was loaded,
+/// 2. [StreamablePlugin::stream] until the input stream was closed and
+/// 3. [StreamablePlugin::shutdown] once after [StreamablePlugin::stream] returned.
+///
+/// [StreamablePlugin::health] can be called at any time after [StreamablePlugin::init].
 #[async_trait]
 pub trait StreamablePlugin: Send + Sync + Debug {
+    /// Initializes the plugin with the active [Profile]. Should be used to open resources
+    /// like database handles which are required by [StreamablePlugin::stream].
+    ///
+    /// Returning an error prevents the plugin from being streamed.
+    async fn init(&self, _profile: &Profile) -> Result<(), PluginError> {
+        Ok(())
+    }
+
     /// Create a new Stream sending messages into **output** and receiving messages to
     /// the returned sender.
     ///
+    /// **input** is bounded so a plugin receives new messages only after it received the
+    /// previous ones. Messages waiting to be received are queued by the plugin-loader.
+    ///
     /// Must return only if the whole stream was processed or an error occurred.
     ///
     /// Should only return [PluginError] if the execution of the plugin can't be continued.w
     async fn stream(
         &self,
-        input: UnboundedReceiver<Message>,
+        input: Receiver<Message>,
         output: UnboundedSender<Vec<Message>>,
     ) -> Result<(), PluginError>;
 
+    /// Gracefully shuts the plugin down. Should be used to flush state and close resources
+    /// opened in [StreamablePlugin::init].
+    ///
+    /// Has to return before `deadline` as the plugin-loader won't wait any longer.
+    async fn shutdown(&self, _deadline: Instant) -> Result<(), PluginError> {
+        Ok(())
+    }
+
+    /// Returns the current health of the plugin.
+    async fn health(&self) -> PluginHealth {
+        PluginHealth::Healthy
+    }
+
+    /// Validates the configuration section of the plugin in the profile (see
+    /// [Profile::plugin_config]) or `None` if the profile has no section for the plugin.
+    ///
+    /// Called when the plugin is loaded for a profile and before [StreamablePlugin::init], so
+    /// invalid configurations are reported before the plugin runs. See [parse_config].
+    fn validate_config(&self, _config: Option<&Value>) -> Result<(), PluginError> {
+        Ok(())
+    }
+
     fn info(&self) -> PluginInfo;
 }
 
@@ -87,6 +253,25 @@ impl Display for PluginError {
     }
 }
 
+/// Invalid configuration section of a plugin.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConfigError(pub String);
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Deserializes the configuration section passed to [StreamablePlugin::validate_config] into
+/// `T`. A missing section is deserialized from an empty map.
+pub fn parse_config<T: DeserializeOwned>(config: Option<&Value>) -> Result<T, ConfigError> {
+    let empty = Value::Object(Map::new());
+    T::deserialize(config.unwrap_or(&empty)).map_err(|why| ConfigError(why.to_string()))
+}
+
 pub struct CommandDeclaration {
     pub rustc_version: &'static str,
     pub core_version: &'static str,
@@ -110,28 +295,54 @@ macro_rules! export_command {
 #[derive(Debug, Clone)]
 pub(crate) struct PluginProxy {
     command: Arc<dyn StreamablePlugin>,
-    _lib: Arc<Option<Library>>,
+    #[cfg_attr(not(feature = "plugin-loader"), allow(dead_code))]
+    lib: Arc<Option<Library>>,
+}
+
+#[cfg(feature = "plugin-loader")]
+impl PluginProxy {
+    /// Returns the library which registered the plugin. The library is kept loaded as long
+    /// as the proxy exists.
+    pub(crate) fn library(&self) -> &Arc<Option<Library>> {
+        &self.lib
+    }
 }
 
 impl<P: StreamablePlugin + 'static> From<Arc<P>> for PluginProxy {
     fn from(plugin: Arc<P>) -> Self {
         PluginProxy {
             command: plugin,
-            _lib: Arc::new(None),
+            lib: Arc::new(None),
         }
     }
 }
 
 #[async_trait]
 impl StreamablePlugin for PluginProxy {
+    async fn init(&self, profile: &Profile) -> Result<(), PluginError> {
+        self.command.init(profile).await
+    }
+
     async fn stream(
         &self,
-        input: UnboundedReceiver<Message>,
+        input: Receiver<Message>,
         output: UnboundedSender<Vec<Message>>,
     ) -> Result<(), PluginError> {
         self.command.stream(input, output).await
     }
 
+    async fn shutdown(&self, deadline: Instant) -> Result<(), PluginError> {
+        self.command.shutdown(deadline).await
+    }
+
+    async fn health(&self) -> PluginHealth {
+        self.command.health().await
+    }
+
+    fn validate_config(&self, config: Option<&Value>) -> Result<(), PluginError> {
+        self.command.validate_config(config)
+    }
+
     fn info(&self) -> PluginInfo {
         self.command.info()
     }
@@ -141,20 +352,32 @@ impl StreamablePlugin for PluginProxy {
 pub struct PluginRegistrar {
     pub(crate) commands: Vec<PluginProxy>,
     lib: Arc<Option<Library>>,
+    context: Option<Context>,
 }
 
 impl PluginRegistrar {
     pub fn new(lib: Arc<Option<Library>>) -> PluginRegistrar {
+        PluginRegistrar::with_context(lib, None)
+    }
+
+    pub fn with_context(lib: Arc<Option<Library>>, context: Option<Context>) -> PluginRegistrar {
         PluginRegistrar {
             lib,
             commands: Vec::new(),
+            context,
         }
     }
 
+    /// Returns the context of the profile the registered plugins belong to. Only present if
+    /// the plugins are loaded for a profile (see [crate::plugins::Plugins::for_profile]).
+    pub fn context(&self) -> Option<&Context> {
+        self.context.as_ref()
+    }
+
     pub fn register(&mut self, command: Arc<dyn StreamablePlugin>) {
         let proxy = PluginProxy {
             command: Arc::clone(&command),
-            _lib: Arc::clone(&self.lib),
+            lib: Arc::clone(&self.lib),
         };
         self.commands.push(proxy);
     }
@@ -190,6 +413,7 @@ mod tests {
                 authors: "".to_string(),
                 repo: None,
                 commands: vec![],
+                required_rights: Default::default(),
             }
         }
     }
@@ -204,7 +428,7 @@ mod tests {
 
     #[bench]
     fn bench_derive_delegation(b: &mut Bencher) {
-        let (mut input_sender, input_receiver) = futures::channel::mpsc::unbounded::<Message>();
+        let (mut input_sender, input_receiver) = futures::channel::mpsc::channel::<Message>(0);
         let (output_sender, mut output_receiver) =
             futures::channel::mpsc::unbounded::<Vec<Message>>();
         let mut runtime = Builder::new().basic_scheduler().build().unwrap();
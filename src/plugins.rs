@@ -1,35 +1,430 @@
+use crate::abi::{
+    is_compatible, AbiPlugin, FfiStr, PluginDeclaration, ABI_VERSION, DECLARATION_SYMBOL,
+};
+use crate::command_access::AccessRights;
+use crate::context::Context;
+use crate::cooldown::{CooldownTracker, CooldownVerdict};
 use crate::plugin::{
-    CommandDeclaration, PluginError, PluginInfo, PluginProxy, PluginRegistrar, StreamablePlugin,
+    CommandDeclaration, ConfigError, PluginError, PluginHealth, PluginInfo, PluginProxy,
+    PluginRegistrar, StreamablePlugin,
 };
+use crate::process::{is_manifest, ProcessPlugin};
+use crate::profile::watch::ProfileChange;
+use crate::profile::Profile;
+use crate::queue::QueueConfig;
+use crate::rate_limit::{InvalidLimit, RateLimiter, RateLimits};
+use crate::router::{self, Router};
+use crate::supervisor::{RestartPolicy, Supervisor, SupervisorEvent};
+#[cfg(feature = "wasm")]
+use crate::wasm::{is_wasm, WasmPlugin};
 use crate::{Message, CORE_VERSION, RUSTC_VERSION};
 use async_trait::async_trait;
-use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
-use futures::future::join_all;
+use futures::channel::mpsc::{
+    channel, unbounded, Receiver, Sender, UnboundedReceiver, UnboundedSender,
+};
+use futures::channel::oneshot;
+use futures::future::{join_all, FutureExt};
 use futures::sink::SinkExt;
 use futures::stream::StreamExt;
 use libloading::Library;
-use std::ffi::OsStr;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
 use std::{fs, io};
+use tokio::task::JoinHandle;
 
-// Contains all loaded Plugins.
+/// Default time plugins have to process their remaining messages and shut down after the
+/// input stream was closed.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default interval in which [PluginReloader::watch] checks the plugins directory for changes.
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Number of native libraries copied by [Plugins::stage]. Makes the names of the copies unique.
+static STAGED_LIBRARIES: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns if the file at `path` is a plugin library file based on its extension or a process
+/// plugin manifest.
+fn is_plugin_file(path: &Path) -> bool {
+    path.is_file()
+        && (is_manifest(path)
+            || is_native(path)
+            || (cfg!(feature = "wasm")
+                && path
+                    .extension()
+                    .map(|extension| extension == "wasm")
+                    .unwrap_or(false)))
+}
+
+/// Returns if the file at `path` is a native library based on its extension.
+fn is_native(path: &Path) -> bool {
+    path.extension()
+        .map(|extension| extension == "so" || extension == "dll")
+        .unwrap_or(false)
+}
+
+/// Validates the configuration section of `plugin` in `profile`.
+fn validate_config(plugin: &PluginProxy, profile: &Profile) -> Result<(), PluginError> {
+    let name = plugin.info().name;
+    plugin
+        .validate_config(profile.plugin_config_value(&name))
+        .map_err(|why| {
+            PluginError::from(ConfigError(format!(
+                "invalid config of plugin '{}': {}",
+                name, why
+            )))
+        })
+}
+
+/// Plugins and libraries currently loaded. Libraries are only referenced here and by the
+/// [PluginProxy]s registered by them.
 #[derive(Default, Debug)]
-pub struct Plugins {
+struct Loaded {
     commands: Vec<PluginProxy>,
-    libraries: Vec<Arc<Option<Library>>>,
+    libraries: HashMap<PathBuf, Arc<Option<Library>>>,
+    profile: Option<Profile>,
+}
+
+impl Loaded {
+    /// Removes the library loaded from `path` and all plugins registered by it.
+    fn remove(&mut self, path: &Path) -> Option<Arc<Option<Library>>> {
+        let library = self.libraries.remove(path)?;
+        self.commands
+            .retain(|cmd| !Arc::ptr_eq(cmd.library(), &library));
+        Some(library)
+    }
+}
+
+#[derive(Debug)]
+enum Reload {
+    /// Replaces the library loaded from the path with its current version on disk.
+    File(PathBuf),
+    /// Unloads the library loaded from the path.
+    Remove(PathBuf),
+}
+
+/// Handle to replace or unload plugin libraries while [Plugins::stream] is running.
+///
+/// Requests are processed in order by the running [Plugins::stream]. Replacing a library
+/// first loads the new version. If it fails to load, the old version keeps running.
+/// Otherwise the inputs of all plugins registered by the old version are closed, their
+/// streams are awaited and they are shut down. The old library is unloaded after all of its
+/// plugins are gone. Only then the new version is initialized and streamed.
+///
+/// Native libraries are loaded from a temporary copy as the dynamic loader would otherwise
+/// return the old library still loaded from the same path.
+///
+/// Plugin files should be replaced atomically (e.g. by moving the new version to the path of
+/// the old one) to not load partially written files.
+#[derive(Clone, Debug)]
+pub struct PluginReloader {
+    sender: UnboundedSender<Reload>,
+}
+
+impl PluginReloader {
+    /// Loads or replaces the plugin library at `path`.
+    pub fn reload(&self, path: PathBuf) -> Result<(), PluginError> {
+        self.send(Reload::File(path))
+    }
+
+    /// Unloads the plugin library loaded from `path`.
+    pub fn unload(&self, path: PathBuf) -> Result<(), PluginError> {
+        self.send(Reload::Remove(path))
+    }
+
+    fn send(&self, reload: Reload) -> Result<(), PluginError> {
+        self.sender
+            .unbounded_send(reload)
+            .map_err(|err| PluginError::from(err.into_send_error()))
+    }
+
+    /// Watches the directory at `dir` for added, changed or removed plugin files and reloads
+    /// them. Checks for changes every `interval`.
+    ///
+    /// Returns if the directory can't be read or [Plugins] was dropped.
+    pub async fn watch(self, dir: PathBuf, interval: Duration) -> io::Result<()> {
+        let mut known = Self::modification_times(&dir)?;
+        loop {
+            tokio::time::delay_for(interval).await;
+            let current = Self::modification_times(&dir)?;
+            for (path, modified) in current.iter() {
+                if known.get(path) != Some(modified) {
+                    info!("Plugin file changed: {}", path.display());
+                    if self.reload(path.clone()).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+            for path in known.keys() {
+                if !current.contains_key(path) {
+                    info!("Plugin file removed: {}", path.display());
+                    if self.unload(path.clone()).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+            known = current;
+        }
+    }
+
+    /// Watches the plugins directory of the profile. See [PluginReloader::watch].
+    pub async fn watch_profile(self, profile: &Profile) -> io::Result<()> {
+        self.watch(profile.plugins_path(), DEFAULT_WATCH_INTERVAL)
+            .await
+    }
+
+    fn modification_times(dir: &Path) -> io::Result<HashMap<PathBuf, SystemTime>> {
+        let mut times = HashMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if is_plugin_file(&path) {
+                match fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+                    Ok(modified) => {
+                        times.insert(path, modified);
+                    }
+                    // Removed since reading the directory, reported by the next check
+                    Err(why) if why.kind() == io::ErrorKind::NotFound => (),
+                    Err(why) => return Err(why),
+                }
+            }
+        }
+        Ok(times)
+    }
+}
+
+/// A plugin currently streamed by [Plugins::stream].
+struct Running {
+    proxy: PluginProxy,
+    info: PluginInfo,
+    input: Sender<Message>,
+    handle: JoinHandle<()>,
+}
+
+impl Running {
+    fn spawn(
+        proxy: PluginProxy,
+        output: UnboundedSender<Vec<Message>>,
+        supervisor: &Supervisor,
+    ) -> Self {
+        let (input, read) = channel(0);
+        let handle = tokio::spawn(supervisor.supervise(proxy.clone(), read, output));
+        Running {
+            info: proxy.info(),
+            proxy,
+            input,
+            handle,
+        }
+    }
+}
+
+// Contains all loaded Plugins.
+#[derive(Debug)]
+pub struct Plugins {
+    loaded: Mutex<Loaded>,
+    shutdown_timeout: Option<Duration>,
+    rate_limiter: Option<RateLimiter>,
+    context: Option<Context>,
+    routing: bool,
+    access_checks: bool,
+    supervisor: Supervisor,
+    reloads: UnboundedSender<Reload>,
+    reload_receiver: Mutex<Option<UnboundedReceiver<Reload>>>,
+}
+
+impl Default for Plugins {
+    fn default() -> Self {
+        Plugins::new()
+    }
 }
 
 impl Plugins {
     pub fn new() -> Plugins {
+        Plugins::with_commands(Vec::new())
+    }
+
+    fn with_commands(commands: Vec<PluginProxy>) -> Plugins {
+        let (reloads, reload_receiver) = unbounded();
         Plugins {
-            commands: Vec::new(),
-            libraries: Vec::new(),
+            loaded: Mutex::new(Loaded {
+                commands,
+                ..Loaded::default()
+            }),
+            shutdown_timeout: None,
+            rate_limiter: None,
+            context: None,
+            routing: false,
+            access_checks: false,
+            supervisor: Supervisor::default(),
+            reloads,
+            reload_receiver: Mutex::new(Some(reload_receiver)),
+        }
+    }
+
+    /// Creates the plugins of `profile`. Plugins loaded by the instance get the [Context] of
+    /// the profile on registration and are initialized with it. Instances for different
+    /// profiles don't share any plugins.
+    ///
+    /// Native libraries loaded by several instances are only opened once per process, so
+    /// their statics (e.g. loggers or lazily initialized globals) are shared between the
+    /// profiles. Plugins have to keep per-profile state in the instances they register.
+    /// Process and WebAssembly plugins are started separately for every profile.
+    pub fn for_profile(profile: Profile) -> Plugins {
+        let mut plugins = Plugins::new();
+        plugins
+            .loaded
+            .get_mut()
+            .expect("plugins lock poisoned")
+            .profile = Some(profile.clone());
+        plugins.context = Some(Context::new(profile));
+        plugins
+    }
+
+    /// Returns the context of the profile the plugins were created for.
+    pub fn context(&self) -> Option<&Context> {
+        self.context.as_ref()
+    }
+
+    fn loaded(&self) -> MutexGuard<'_, Loaded> {
+        self.loaded.lock().expect("plugins lock poisoned")
+    }
+
+    fn commands(&self) -> Vec<PluginProxy> {
+        self.loaded().commands.clone()
+    }
+
+    /// Sets the time plugins have to finish processing and shut down after the input stream
+    /// of [Plugins::stream] was closed. Defaults to [DEFAULT_SHUTDOWN_TIMEOUT].
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+        self.shutdown_timeout = Some(timeout);
+    }
+
+    /// Limits the messages sent by the plugins to `limits`. See [crate::rate_limit]. Messages
+    /// aren't limited by default. Fails if the limits are invalid.
+    pub fn set_rate_limits(&mut self, limits: RateLimits) -> Result<(), InvalidLimit> {
+        self.rate_limiter = Some(RateLimiter::new(limits)?);
+        Ok(())
+    }
+
+    /// Limits the messages sent by the plugins through `limiter`.
+    pub fn set_rate_limiter(&mut self, limiter: RateLimiter) {
+        self.rate_limiter = Some(limiter);
+    }
+
+    fn shutdown_deadline(&self) -> Instant {
+        Instant::now() + self.shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT)
+    }
+
+    /// Sets the [RestartPolicy] of all plugins without a policy set through
+    /// [Plugins::set_plugin_restart_policy]. Defaults to [RestartPolicy::Never].
+    ///
+    /// Only applies to plugins started after the policy was set.
+    pub fn set_restart_policy(&mut self, policy: RestartPolicy) {
+        self.supervisor.set_policy(policy);
+    }
+
+    /// Sets the [RestartPolicy] of the plugin named `name`.
+    ///
+    /// Only applies to plugins started after the policy was set.
+    pub fn set_plugin_restart_policy<S: Into<String>>(&mut self, name: S, policy: RestartPolicy) {
+        self.supervisor.set_plugin_policy(name.into(), policy);
+    }
+
+    /// Enables sending PRIVMSGs only to the plugins which declared the invoked command. See
+    /// [crate::router] for details. Disabled by default.
+    pub fn set_routing(&mut self, enabled: bool) {
+        self.routing = enabled;
+    }
+
+    fn router(&self, running: &[Running]) -> Option<Router> {
+        if self.routing {
+            Some(Router::new(running.iter().map(|run| run.info.clone())))
+        } else {
+            None
+        }
+    }
+
+    /// Enables checking commands against the [AccessRights] of the profile the plugins were
+    /// initialized with or the rights of the channel if it overrides them
+    /// ([crate::profile::channels::ChannelSettings::rights]) and the rights required by the
+    /// plugins ([PluginInfo::required_rights]). Changes of the profile published to the
+    /// [Context] of the plugins apply to all messages received after them.
+    /// Plugins don't receive commands they aren't allowed to handle or which are on cooldown
+    /// (see [crate::cooldown]). Disabled by default, so plugins receive all messages and check
+    /// the rights themselves like before version 0.5.
+    pub fn set_access_checks(&mut self, enabled: bool) {
+        self.access_checks = enabled;
+    }
+
+    fn rights(&self) -> Option<AccessRights> {
+        if self.access_checks {
+            self.loaded()
+                .profile
+                .as_ref()
+                .map(|profile| profile.rights().clone())
+        } else {
+            None
         }
     }
 
-    pub fn iter(&self) -> std::slice::Iter<impl StreamablePlugin> {
-        self.commands.iter()
+    fn cooldowns(&self) -> Option<CooldownTracker> {
+        if self.access_checks {
+            self.loaded()
+                .profile
+                .as_ref()
+                .filter(|profile| !profile.cooldowns().is_empty())
+                .map(|profile| CooldownTracker::new(profile.cooldowns().clone()))
+        } else {
+            None
+        }
+    }
+
+    /// Limits the queues of all plugins without a queue set through [Plugins::set_plugin_queue]
+    /// to `queue`. Queues are unbounded by default.
+    ///
+    /// Only applies to plugins started after the queue was set.
+    pub fn set_queue(&mut self, queue: QueueConfig) {
+        self.supervisor.set_queue(Some(queue));
+    }
+
+    /// Sets the queue of the plugin named `name`. `None` makes the queue unbounded.
+    ///
+    /// Only applies to plugins started after the queue was set.
+    pub fn set_plugin_queue<S: Into<String>>(&mut self, name: S, queue: Option<QueueConfig>) {
+        self.supervisor.set_plugin_queue(name.into(), queue);
+    }
+
+    /// Returns the number of messages dropped because of full queues by plugin name.
+    pub fn dropped_messages(&self) -> HashMap<String, u64> {
+        self.supervisor.dropped()
+    }
+
+    /// Returns a receiver of all [SupervisorEvent]s reported after this call.
+    pub fn supervisor_events(&self) -> UnboundedReceiver<SupervisorEvent> {
+        self.supervisor.subscribe()
+    }
+
+    /// Returns a handle to replace or unload plugin libraries while [Plugins::stream] is
+    /// running.
+    pub fn reloader(&self) -> PluginReloader {
+        PluginReloader {
+            sender: self.reloads.clone(),
+        }
+    }
+
+    /// Returns the health of every loaded plugin in load order.
+    pub async fn health_report(&self) -> Vec<(PluginInfo, PluginHealth)> {
+        let commands = self.commands();
+        let checks = commands
+            .iter()
+            .map(|cmd| async move { (cmd.info(), cmd.health().await) });
+        join_all(checks).await
+    }
+
+    /// Returns an iterator over a snapshot of the currently loaded plugins.
+    pub fn iter(&self) -> std::vec::IntoIter<impl StreamablePlugin> {
+        self.commands().into_iter()
     }
 
     pub fn load_dir(&mut self, libraries_root: PathBuf) -> io::Result<()> {
@@ -44,7 +439,7 @@ impl Plugins {
             self.load_file(entry?.path())?;
         }
 
-        if self.libraries.is_empty() {
+        if self.loaded().libraries.is_empty() {
             warn!("No plugins loaded!");
         }
 
@@ -52,34 +447,80 @@ impl Plugins {
     }
 
     pub fn load_file(&mut self, entry: PathBuf) -> io::Result<()> {
-        if entry.exists() {
-            if entry.is_file() {
-                if let Some(extension) = entry.extension() {
-                    if extension == "so" || extension == "dll" {
-                        debug!("Trying to load plugin-file {}", entry.to_str().unwrap());
-                        unsafe { self.load(entry)? };
-                    }
-                }
-            }
-        } else {
-            panic!("File doesn't exist: '{}'", entry.display());
+        if !entry.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("File doesn't exist: '{}'", entry.display()),
+            ));
+        }
+        if is_plugin_file(&entry) {
+            debug!("Trying to load plugin-file {}", entry.display());
+            let loaded = self.loaded.get_mut().expect("plugins lock poisoned");
+            unsafe { Self::load(loaded, self.context.as_ref(), &entry)? };
         }
         Ok(())
     }
 
+    /// Loads the library at `library_path` and adds all plugins it registers. Returns the
+    /// newly registered plugins.
+    ///
+    /// Process plugin manifests ([crate::process]) and WebAssembly plugins (`crate::wasm`) are
+    /// registered as a library without code. Every kind of plugin gets the profile of
+    /// `context`.
+    /// Plugins exported through the stable ABI ([crate::abi]) are loaded if their core version
+    /// is compatible. Other plugins require the exact same rustc and core version.
+    ///
     /// # Safety
     ///
     /// This function should only be called with a valid path to a library file.
-    unsafe fn load<P: AsRef<OsStr>>(&mut self, library_path: P) -> io::Result<()> {
+    unsafe fn load(
+        loaded: &mut Loaded,
+        context: Option<&Context>,
+        library_path: &Path,
+    ) -> io::Result<Vec<PluginProxy>> {
+        if is_manifest(library_path) {
+            let mut plugin = ProcessPlugin::from_manifest(library_path)?;
+            if let Some(context) = context {
+                plugin = plugin.with_context(context.clone());
+            }
+            return Self::register(loaded, context, library_path, Arc::new(None), |registrar| {
+                registrar.register(Arc::new(plugin))
+            });
+        }
+        #[cfg(feature = "wasm")]
+        {
+            if is_wasm(library_path) {
+                let mut plugin = WasmPlugin::from_file(library_path)?;
+                if let Some(context) = context {
+                    plugin = plugin.with_context(context).map_err(|why| {
+                        io::Error::new(io::ErrorKind::InvalidData, why.to_string())
+                    })?;
+                }
+                return Self::register(
+                    loaded,
+                    context,
+                    library_path,
+                    Arc::new(None),
+                    |registrar| registrar.register(Arc::new(plugin)),
+                );
+            }
+        }
+
         // load the library into memory
-        let library = Library::new(library_path).expect("failed to create new library");
+        let library = Library::new(library_path)
+            .map_err(|why| io::Error::new(io::ErrorKind::Other, why.to_string()))?;
+
+        if let Ok(decl) = library.get::<*mut PluginDeclaration>(DECLARATION_SYMBOL) {
+            let decl = decl.read();
+            return Self::load_abi(loaded, context, library_path, library, decl);
+        }
 
         // get a pointer to the plugin_declaration symbol.
         let decl = match library.get::<*mut CommandDeclaration>(b"command_declaration\0") {
             Ok(decl) => decl.read(),
             Err(err) => {
                 warn!("failed to load command_declaration skipping; {}", err);
-                return Ok(());
+                return Ok(Vec::with_capacity(0));
             }
         };
 
@@ -105,50 +546,436 @@ impl Plugins {
         trace!("RUSTC and CORE versions match!");
 
         let library = Arc::new(Some(library));
+        Self::register(loaded, context, library_path, library, |registrar| {
+            (decl.register)(registrar)
+        })
+    }
 
-        let mut registrar = Box::new(PluginRegistrar::new(Arc::clone(&library)));
+    /// Loads a plugin exported through the stable ABI.
+    ///
+    /// # Safety
+    ///
+    /// `decl` has to be the declaration exported by `library`.
+    unsafe fn load_abi(
+        loaded: &mut Loaded,
+        context: Option<&Context>,
+        library_path: &Path,
+        library: Library,
+        decl: PluginDeclaration,
+    ) -> io::Result<Vec<PluginProxy>> {
+        let core_version = decl.core_version.as_str().unwrap_or("invalid");
+        if decl.abi_version != ABI_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "ABI version mismatch; botrs: {}, plugin: {}",
+                    ABI_VERSION, decl.abi_version
+                ),
+            ));
+        }
+        if !is_compatible(core_version, CORE_VERSION) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "CORE version incompatible; botrs: {}, plugin: {}",
+                    CORE_VERSION, core_version
+                ),
+            ));
+        }
+        trace!("ABI and CORE versions compatible!");
 
-        (decl.register)(&mut registrar);
+        let profile = serde_json::to_vec(&context.map(Context::profile))?;
+        let library = Arc::new(Some(library));
+        let plugin = AbiPlugin::new(
+            (decl.create)(FfiStr::from_bytes(&profile)),
+            Arc::clone(&library),
+        )
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why.to_string()))?;
+        Self::register(loaded, context, library_path, library, |registrar| {
+            registrar.register(Arc::new(plugin))
+        })
+    }
+
+    /// Registers all plugins of `library` through `register` and adds them.
+    ///
+    /// Fails without adding any plugin if the profile of `context` contains an invalid
+    /// configuration for one of them.
+    fn register<F: FnOnce(&mut PluginRegistrar)>(
+        loaded: &mut Loaded,
+        context: Option<&Context>,
+        library_path: &Path,
+        library: Arc<Option<Library>>,
+        register: F,
+    ) -> io::Result<Vec<PluginProxy>> {
+        let mut registrar = Box::new(PluginRegistrar::with_context(
+            Arc::clone(&library),
+            context.cloned(),
+        ));
+
+        register(&mut registrar);
+
+        if let Some(context) = context {
+            for cmd in registrar.commands.iter() {
+                validate_config(cmd, context.profile())
+                    .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why.to_string()))?;
+            }
+        }
 
         // add all loaded plugins to the functions map
-        self.commands.extend(registrar.commands);
+        loaded.commands.extend(registrar.commands.iter().cloned());
         // and make sure Commands keeps a reference to the library
-        self.libraries.push(library);
+        loaded.libraries.insert(library_path.to_path_buf(), library);
 
-        Ok(())
+        Ok(registrar.commands)
+    }
+
+    /// Sends the messages of `input` to the plugins. See [Plugins::stream].
+    async fn dispatch(&self, input: Receiver<Message>, output: UnboundedSender<Vec<Message>>) {
+        let mut running = self
+            .commands()
+            .into_iter()
+            .map(|cmd| Running::spawn(cmd, output.clone(), &self.supervisor))
+            .collect::<Vec<_>>();
+        // Only one stream at a time can process reloads. Others use an already closed channel.
+        let reload_receiver = self
+            .reload_receiver
+            .lock()
+            .expect("plugins lock poisoned")
+            .take();
+        let processes_reloads = reload_receiver.is_some();
+        let mut reloads = reload_receiver.unwrap_or_else(|| unbounded().1);
+        let mut input = input.fuse();
+        let mut router = self.router(&running);
+        // Changes of the profile apply to all messages received after them
+        let mut profile_events = self
+            .context
+            .as_ref()
+            .map(Context::profile_events)
+            .unwrap_or_else(|| unbounded().1);
+        let mut profile = self.loaded().profile.clone();
+        let mut rights = self.rights();
+        let mut cooldowns = self.cooldowns();
+
+        loop {
+            futures::select_biased! {
+                event = profile_events.next() => if let Some(event) = event {
+                    profile = Some(Profile::clone(&event.profile));
+                    self.loaded().profile = profile.clone();
+                    rights = self.rights();
+                    // Keeps running cooldowns if they didn't change
+                    if event.changes.contains(&ProfileChange::CooldownsChanged) {
+                        cooldowns = self.cooldowns();
+                    }
+                },
+                msg = input.next() => match msg {
+                    Some(msg) => {
+                        if let Some(ref limiter) = self.rate_limiter {
+                            limiter.observe(&msg);
+                        }
+                        let settings = match (&profile, router::channel(&msg)) {
+                            (Some(profile), Some(channel)) => profile.channel(channel),
+                            _ => None,
+                        };
+                        let prefix = settings.and_then(|settings| settings.prefix.as_deref());
+                        // Only the prefix of the channel invokes commands in it
+                        let invoked = match prefix {
+                            Some(prefix) => router::command_with_prefix(&msg, prefix),
+                            None => router::command(&msg),
+                        };
+                        let msg = match prefix {
+                            Some(prefix) => router::with_default_prefix(msg, prefix),
+                            None => msg,
+                        };
+                        let targets = router.as_ref().map(|router| router.route(invoked.as_deref()));
+                        // Only commands are checked. Commands with another prefix than the one of
+                        // the channel are still checked as plugins may handle them without routing.
+                        let command = router::command(&msg);
+                        let verdict = match (&rights, &command) {
+                            (Some(rights), Some(_)) => {
+                                let rights = settings
+                                    .and_then(|settings| settings.rights.as_ref())
+                                    .unwrap_or(rights);
+                                Some(rights.verdict(&msg))
+                            }
+                            _ => None,
+                        };
+                        let mut receivers = Vec::with_capacity(running.len());
+                        for (index, run) in running.iter().enumerate() {
+                            if let Some(ref targets) = targets {
+                                if targets.binary_search(&index).is_err() {
+                                    continue;
+                                }
+                            }
+                            if let Some(settings) = settings {
+                                if !settings.is_enabled(&run.info.name) {
+                                    continue;
+                                }
+                            }
+                            if let Some(verdict) = verdict {
+                                let verdict = verdict.for_plugin(&run.info, command.as_deref(), &msg);
+                                if verdict.is_denied() {
+                                    debug!("Access of {} denied to {:?}", run.info.name, msg);
+                                    continue;
+                                }
+                            }
+                            receivers.push(index);
+                        }
+                        // Only invocations reaching a plugin start cooldowns
+                        if let (Some(cooldowns), Some(_), false) = (&cooldowns, &invoked, receivers.is_empty()) {
+                            if let CooldownVerdict::RetryAfter(remaining) = cooldowns.check(&msg) {
+                                debug!("Command on cooldown for {:?}: {:?}", remaining, msg);
+                                receivers.clear();
+                            }
+                        }
+                        let mut sends = Vec::with_capacity(receivers.len());
+                        for (index, run) in running.iter_mut().enumerate() {
+                            if receivers.binary_search(&index).is_ok() {
+                                sends.push(run.input.send(msg.clone()));
+                            }
+                        }
+                        // Actually send to all channels/commands
+                        join_all(sends).await;
+                    }
+                    None => break,
+                },
+                reload = reloads.next() => if let Some(reload) = reload {
+                    self.reload(reload, &mut running, &output).await;
+                    router = self.router(&running);
+                },
+            }
+        }
+
+        // Lets the next stream process the remaining reloads
+        if processes_reloads {
+            *self.reload_receiver.lock().expect("plugins lock poisoned") = Some(reloads);
+        }
+        self.stop(running).await;
+    }
+
+    /// Loads the current version of the library at `path` if still present, stops all
+    /// `running` plugins registered by its old version and unloads it. Keeps the old version if
+    /// the new one fails to load.
+    async fn reload(
+        &self,
+        reload: Reload,
+        running: &mut Vec<Running>,
+        output: &UnboundedSender<Vec<Message>>,
+    ) {
+        let (path, staged) = match reload {
+            Reload::File(path) if is_plugin_file(&path) => match self.stage(&path) {
+                Ok(staged) => (path, Some(staged)),
+                Err(why) => {
+                    error!("Failed to load plugin file {}: {}", path.display(), why);
+                    return;
+                }
+            },
+            Reload::File(path) => {
+                // Keeps the plugins loaded from the path
+                warn!("Not a plugin file: {}", path.display());
+                return;
+            }
+            Reload::Remove(path) => (path, None),
+        };
+
+        let removed = self.loaded().remove(&path);
+        if let Some(library) = removed {
+            let (stopped, kept) = running
+                .drain(..)
+                .partition::<Vec<_>, _>(|run| Arc::ptr_eq(run.proxy.library(), &library));
+            *running = kept;
+            self.stop(stopped).await;
+            // Unloads the library as soon as the last plugin registered by it is dropped
+            drop(library);
+            info!("Unloaded plugin library {}", path.display());
+        }
+
+        if let Some(staged) = staged {
+            let (commands, profile) = {
+                let mut loaded = self.loaded();
+                loaded.commands.extend(staged.commands.iter().cloned());
+                loaded.libraries.extend(
+                    staged
+                        .libraries
+                        .values()
+                        .map(|library| (path.clone(), Arc::clone(library))),
+                );
+                (staged.commands, loaded.profile.clone())
+            };
+            for cmd in commands {
+                if let Some(ref profile) = profile {
+                    if let Err(why) = cmd.init(profile).await {
+                        error!("Failed to initialize plugin {}: {:?}", cmd.info().name, why);
+                        continue;
+                    }
+                }
+                info!("Loaded plugin {}", cmd.info().name);
+                running.push(Running::spawn(cmd, output.clone(), &self.supervisor));
+            }
+        }
+    }
+
+    /// Loads the library at `path` without adding its plugins. Native libraries are loaded from
+    /// a temporary copy. See [PluginReloader].
+    fn stage(&self, path: &Path) -> io::Result<Loaded> {
+        let mut staged = Loaded::default();
+        if !is_native(path) {
+            unsafe { Self::load(&mut staged, self.context.as_ref(), path)? };
+            return Ok(staged);
+        }
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let copy = std::env::temp_dir().join(format!(
+            "botrs-{}-{}-{}",
+            std::process::id(),
+            STAGED_LIBRARIES.fetch_add(1, Ordering::Relaxed),
+            file_name
+        ));
+        fs::copy(path, &copy)?;
+        let result = unsafe { Self::load(&mut staged, self.context.as_ref(), &copy) };
+        // Loaded libraries stay mapped after their file was removed. Fails on Windows.
+        if let Err(why) = fs::remove_file(&copy) {
+            debug!("Failed to remove library copy {}: {}", copy.display(), why);
+        }
+        result.map(|_| staged)
+    }
+
+    /// Closes the inputs of all `running` plugins, waits for their streams to finish and
+    /// shuts them down.
+    async fn stop(&self, running: Vec<Running>) {
+        let mut commands = Vec::with_capacity(running.len());
+        let mut handles = Vec::with_capacity(running.len());
+        for run in running {
+            // Closing the plugin inputs lets the plugins finish their streams
+            drop(run.input);
+            commands.push(run.proxy);
+            handles.push(run.handle);
+        }
+        let deadline = self.shutdown_deadline();
+        let finished =
+            tokio::time::timeout_at(tokio::time::Instant::from_std(deadline), join_all(handles))
+                .await;
+        if finished.is_err() {
+            warn!("Not all plugins finished their streams before the shutdown deadline");
+        }
+        Self::shutdown_all(&commands, deadline).await;
+    }
+
+    async fn shutdown_all(commands: &[PluginProxy], deadline: Instant) {
+        for cmd in commands.iter().rev() {
+            let name = cmd.info().name;
+            trace!("Shutting down plugin {}", name);
+            let result = tokio::time::timeout_at(
+                tokio::time::Instant::from_std(deadline),
+                cmd.shutdown(deadline),
+            )
+            .await;
+            match result {
+                Ok(Ok(())) => (),
+                Ok(Err(why)) => error!("Error shutting down plugin {}: {:?}", name, why),
+                Err(_) => warn!("Plugin {} didn't shut down before the deadline", name),
+            }
+        }
     }
 }
 
 #[async_trait]
 impl StreamablePlugin for Plugins {
+    /// Initializes all loaded plugins in load order. Stops at the first plugin failing to
+    /// initialize. Plugins loaded through [PluginReloader] are initialized with the same
+    /// profile.
+    async fn init(&self, profile: &Profile) -> Result<(), PluginError> {
+        self.loaded().profile = Some(profile.clone());
+        let commands = self.commands();
+        // Report invalid configurations before any plugin is initialized
+        for cmd in commands.iter() {
+            validate_config(cmd, profile)?;
+        }
+        for cmd in commands {
+            trace!("Initializing plugin {}", cmd.info().name);
+            cmd.init(profile).await?;
+        }
+        Ok(())
+    }
+
+    /// Streams all messages of `input` to every loaded plugin or only to the plugins declaring
+    /// the invoked command if routing is enabled ([Plugins::set_routing]). After `input` was closed the
+    /// plugins get the configured shutdown timeout to process their remaining messages before
+    /// they are shut down.
+    ///
+    /// Commands not allowed by the access rights are not sent to the plugins (see
+    /// [Plugins::set_access_checks]). Messages of a channel restricting its plugins
+    /// ([crate::profile::channels::ChannelSettings::plugins]) are only sent to those plugins.
+    ///
+    /// Every plugin receives the messages through its own queue (see [Plugins::set_queue]). A
+    /// plugin with a full blocking queue delays sending messages to all plugins.
+    ///
+    /// Messages of the plugins are delayed or dropped to not exceed the rate limits (see
+    /// [Plugins::set_rate_limits]).
+    ///
+    /// Requests of [Plugins::reloader] are processed while streaming.
     async fn stream(
         &self,
-        mut input: UnboundedReceiver<Message>,
+        input: Receiver<Message>,
         output: UnboundedSender<Vec<Message>>,
     ) -> Result<(), PluginError> {
-        let mut channel_inputs = Vec::with_capacity(self.commands.len());
-        for cmd in self.commands.iter() {
-            let (write, read) = unbounded();
-            let cmd = cmd.clone();
-            let output = output.clone();
-            tokio::spawn(async move {
-                if let Err(e) = cmd.stream(read, output).await {
-                    error!("Error from plugin {}: {:?}", cmd.info().name, e);
+        let limiter = match self.rate_limiter {
+            Some(ref limiter) => limiter,
+            None => {
+                self.dispatch(input, output).await;
+                return Ok(());
+            }
+        };
+        let (limited, mut throttled) = unbounded();
+        let (done_sender, done) = oneshot::channel::<()>();
+        let dispatch = async {
+            self.dispatch(input, limited).await;
+            let _ = done_sender.send(());
+        };
+        let throttle = async {
+            let mut done = done.fuse();
+            loop {
+                futures::select! {
+                    messages = throttled.next() => match messages {
+                        Some(messages) => if !limiter.forward(messages, &output).await {
+                            break;
+                        },
+                        None => break,
+                    },
+                    // Plugins still running after the shutdown deadline can't delay the end
+                    // of the stream. Already sent messages are still forwarded.
+                    _ = done => throttled.close(),
                 }
-            });
-            channel_inputs.push(write);
-        }
-        while let Some(msg) = input.next().await {
-            let mut sends = Vec::with_capacity(channel_inputs.len());
-            for sender in channel_inputs.iter_mut() {
-                sends.push(sender.send(msg.clone()));
             }
-            // Actually send to all channels/commands
-            join_all(sends).await;
-        }
+        };
+        futures::join!(dispatch, throttle);
         Ok(())
     }
 
+    /// Shuts down all loaded plugins in reverse load order. Plugins not returning before
+    /// `deadline` are skipped.
+    async fn shutdown(&self, deadline: Instant) -> Result<(), PluginError> {
+        Self::shutdown_all(&self.commands(), deadline).await;
+        Ok(())
+    }
+
+    /// Returns the worst health of all loaded plugins.
+    async fn health(&self) -> PluginHealth {
+        let mut health = PluginHealth::Healthy;
+        for (info, plugin_health) in self.health_report().await {
+            match plugin_health {
+                PluginHealth::Healthy => (),
+                PluginHealth::Degraded(reason) => {
+                    if health.is_healthy() {
+                        health = PluginHealth::Degraded(format!("{}: {}", info.name, reason));
+                    }
+                }
+                PluginHealth::Unhealthy(reason) => {
+                    return PluginHealth::Unhealthy(format!("{}: {}", info.name, reason));
+                }
+            }
+        }
+        health
+    }
+
     fn info(&self) -> PluginInfo {
         PluginInfo {
             name: "Bot-RS Core".to_string(),
@@ -156,19 +983,34 @@ impl StreamablePlugin for Plugins {
             authors: env!("CARGO_PKG_AUTHORS").to_string(),
             repo: option_env!("CARGO_PKG_REPOSITORY").map(|repo| repo.to_string()),
             commands: vec![],
+            required_rights: Default::default(),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::plugin::{Plugin, PluginError, PluginInfo, PluginProxy, StreamablePlugin};
+    use crate::command_access::{AccessFilter, AccessRights};
+    use crate::context::Context;
+    use crate::cooldown::{Cooldown, Cooldowns};
+    use crate::plugin::{
+        parse_config, Plugin, PluginError, PluginHealth, PluginInfo, PluginProxy, PluginRegistrar,
+        StreamablePlugin,
+    };
     use crate::plugins::Plugins;
+    use crate::profile::channels::ChannelSettings;
+    use crate::profile::watch::{ProfileChange, ProfileEvent};
+    use crate::profile::Profile;
+    use crate::router::ALL_MESSAGES;
     use crate::Message;
     use async_trait::async_trait;
     use bot_rs_core_derive::*;
-    use futures::{SinkExt, StreamExt};
-    use std::sync::Arc;
+    use futures::channel::mpsc::{channel, unbounded, Receiver, UnboundedSender};
+    use futures::{FutureExt, SinkExt, StreamExt};
+    use serde_json::Value;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
     use test::Bencher;
     use tokio::runtime::{Builder, Runtime};
 
@@ -192,8 +1034,614 @@ mod tests {
                 authors: "".to_string(),
                 repo: None,
                 commands: vec![],
+                required_rights: Default::default(),
+            }
+        }
+    }
+
+    /// Answers with the invoked command.
+    #[derive(Debug, StreamablePlugin)]
+    struct CommandPlugin(String);
+
+    #[async_trait]
+    impl Plugin for CommandPlugin {
+        type Error = PluginError;
+
+        async fn call(&self, _message: Message) -> Result<Vec<Message>, PluginError> {
+            Ok(vec![Message::Irc(irc_rust::Message::from(format!(
+                "PRIVMSG #channel :{}",
+                self.0
+            )))])
+        }
+
+        fn info(&self) -> PluginInfo {
+            PluginInfo {
+                commands: vec![self.0.clone()],
+                ..Plugin::info(&TestCommand)
+            }
+        }
+    }
+
+    /// Answers with the invoked command to moderators.
+    #[derive(Debug, StreamablePlugin)]
+    struct ModeratorPlugin(String);
+
+    #[async_trait]
+    impl Plugin for ModeratorPlugin {
+        type Error = PluginError;
+
+        async fn call(&self, message: Message) -> Result<Vec<Message>, PluginError> {
+            Plugin::call(&CommandPlugin(self.0.clone()), message).await
+        }
+
+        fn info(&self) -> PluginInfo {
+            let mut info = Plugin::info(&CommandPlugin(self.0.clone()));
+            info.required_rights
+                .insert(self.0.clone(), AccessFilter::badge("moderator/*").unwrap());
+            info
+        }
+    }
+
+    /// Answers with the name of the profile it was registered for.
+    #[derive(Debug, StreamablePlugin)]
+    struct ProfilePlugin(Context);
+
+    #[async_trait]
+    impl Plugin for ProfilePlugin {
+        type Error = PluginError;
+
+        async fn call(&self, _message: Message) -> Result<Vec<Message>, PluginError> {
+            Ok(vec![Message::Irc(irc_rust::Message::from(format!(
+                "PRIVMSG #channel :{}",
+                self.0.profile().name()
+            )))])
+        }
+
+        fn info(&self) -> PluginInfo {
+            Plugin::info(&TestCommand)
+        }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct GreetConfig {
+        greeting: String,
+    }
+
+    /// Answers with the greeting configured in its profile.
+    #[derive(Debug)]
+    struct ConfigPlugin(Context);
+
+    #[async_trait]
+    impl StreamablePlugin for ConfigPlugin {
+        async fn stream(
+            &self,
+            mut input: Receiver<Message>,
+            mut output: UnboundedSender<Vec<Message>>,
+        ) -> Result<(), PluginError> {
+            let config: GreetConfig = self.0.profile().plugin_config("config").unwrap().unwrap();
+            while input.next().await.is_some() {
+                output
+                    .send(vec![Message::Irc(irc_rust::Message::from(format!(
+                        "PRIVMSG #channel :{}",
+                        config.greeting
+                    )))])
+                    .await?;
+            }
+            Ok(())
+        }
+
+        fn validate_config(&self, config: Option<&Value>) -> Result<(), PluginError> {
+            parse_config::<GreetConfig>(config)?;
+            Ok(())
+        }
+
+        fn info(&self) -> PluginInfo {
+            PluginInfo {
+                name: "config".to_string(),
+                ..Plugin::info(&TestCommand)
+            }
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct LifecyclePlugin {
+        events: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    #[async_trait]
+    impl StreamablePlugin for LifecyclePlugin {
+        async fn init(&self, _profile: &Profile) -> Result<(), PluginError> {
+            self.events.lock().unwrap().push("init");
+            Ok(())
+        }
+
+        async fn stream(
+            &self,
+            mut input: Receiver<Message>,
+            _output: UnboundedSender<Vec<Message>>,
+        ) -> Result<(), PluginError> {
+            while input.next().await.is_some() {
+                self.events.lock().unwrap().push("message");
+            }
+            self.events.lock().unwrap().push("stream end");
+            Ok(())
+        }
+
+        async fn shutdown(&self, _deadline: Instant) -> Result<(), PluginError> {
+            self.events.lock().unwrap().push("shutdown");
+            Ok(())
+        }
+
+        async fn health(&self) -> PluginHealth {
+            PluginHealth::Degraded("testing".to_string())
+        }
+
+        fn info(&self) -> PluginInfo {
+            Plugin::info(&TestCommand)
+        }
+    }
+
+    /// Streams `messages` through `plugins` until they're all sent and returns the answers.
+    async fn stream(
+        plugins: &Plugins,
+        messages: Vec<Message>,
+    ) -> Result<Vec<Vec<Message>>, PluginError> {
+        let (mut input_sender, input_receiver) = channel::<Message>(0);
+        let (output_sender, output_receiver) = unbounded();
+        let send = async {
+            for message in messages {
+                input_sender.send(message).await.unwrap();
+            }
+            input_sender.close_channel();
+        };
+        let (result, _) = futures::join!(plugins.stream(input_receiver, output_sender), send);
+        result?;
+        Ok(output_receiver.collect().await)
+    }
+
+    /// Returns the first answer to every message as string.
+    fn first_answers(answers: Vec<Vec<Message>>) -> Vec<String> {
+        answers
+            .into_iter()
+            .map(|answers| answers[0].to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle() -> Result<(), PluginError> {
+        let plugin = Arc::new(LifecyclePlugin::default());
+        let events = Arc::clone(&plugin.events);
+        let plugins = Plugins::with_commands(vec![PluginProxy::from(plugin)]);
+
+        plugins.init(&Profile::empty()).await?;
+
+        stream(
+            &plugins,
+            vec![Message::Irc(irc_rust::Message::from("PRIVMSG :hello"))],
+        )
+        .await?;
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec!["init", "message", "stream end", "shutdown"]
+        );
+        assert_eq!(
+            plugins.health().await,
+            PluginHealth::Degraded(": testing".to_string())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unload() -> Result<(), PluginError> {
+        let plugin = Arc::new(LifecyclePlugin::default());
+        let events = Arc::clone(&plugin.events);
+        let library = Arc::new(None);
+        let commands = {
+            let mut registrar = PluginRegistrar::new(Arc::clone(&library));
+            registrar.register(plugin);
+            registrar.commands
+        };
+        let plugins = Plugins::with_commands(commands);
+        plugins
+            .loaded()
+            .libraries
+            .insert(PathBuf::from("test.so"), Arc::clone(&library));
+
+        let (mut input_sender, input_receiver) = channel::<Message>(0);
+        let (output_sender, _output_receiver) = unbounded();
+        let reloader = plugins.reloader();
+        let unload = async {
+            reloader.unload(PathBuf::from("test.so")).unwrap();
+            // Library is only referenced here after all plugins of it are gone
+            while Arc::strong_count(&library) > 1 {
+                tokio::time::delay_for(Duration::from_millis(1)).await;
             }
+            assert!(plugins.iter().next().is_none());
+            input_sender
+                .send(Message::Irc(irc_rust::Message::from("PRIVMSG :hello")))
+                .await
+                .unwrap();
+            input_sender.close_channel();
+        };
+        let (result, _) = futures::join!(plugins.stream(input_receiver, output_sender), unload);
+        result?;
+
+        assert_eq!(*events.lock().unwrap(), vec!["stream end", "shutdown"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_reload() -> Result<(), PluginError> {
+        let path = std::env::temp_dir().join(format!("botrs-broken-{}.so", std::process::id()));
+        std::fs::write(&path, "not a library").unwrap();
+        let plugin = Arc::new(LifecyclePlugin::default());
+        let events = Arc::clone(&plugin.events);
+        let library = Arc::new(None);
+        let commands = {
+            let mut registrar = PluginRegistrar::new(Arc::clone(&library));
+            registrar.register(plugin);
+            registrar.commands
+        };
+        let plugins = Plugins::with_commands(commands);
+        plugins
+            .loaded()
+            .libraries
+            .insert(path.clone(), Arc::clone(&library));
+        // Files which aren't plugins don't unload anything either
+        let other = path.with_extension("txt");
+        plugins
+            .loaded()
+            .libraries
+            .insert(other.clone(), Arc::clone(&library));
+
+        let (mut input_sender, input_receiver) = channel::<Message>(0);
+        let (output_sender, _output_receiver) = unbounded();
+        plugins.reloader().reload(path.clone()).unwrap();
+        plugins.reloader().reload(other.clone()).unwrap();
+        let send = async {
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+            input_sender
+                .send(Message::Irc(irc_rust::Message::from("PRIVMSG :hello")))
+                .await
+                .unwrap();
+            input_sender.close_channel();
+        };
+        let (result, _) = futures::join!(plugins.stream(input_receiver, output_sender), send);
+        result?;
+        std::fs::remove_file(&path).unwrap();
+
+        // The old version kept running
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec!["message", "stream end", "shutdown"]
+        );
+        assert!(Arc::ptr_eq(&plugins.loaded().libraries[&path], &library));
+        assert!(Arc::ptr_eq(&plugins.loaded().libraries[&other], &library));
+        // The reload was processed and the next stream processes further reloads
+        let mut reloads = plugins.reload_receiver.lock().unwrap().take().unwrap();
+        assert!(reloads.next().now_or_never().is_none());
+
+        assert!(Plugins::new().load_file(path).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_routing() -> Result<(), PluginError> {
+        let mut plugins = Plugins::with_commands(vec![
+            PluginProxy::from(Arc::new(CommandPlugin("!hello".to_string()))),
+            PluginProxy::from(Arc::new(CommandPlugin("!bye".to_string()))),
+            PluginProxy::from(Arc::new(CommandPlugin(ALL_MESSAGES.to_string()))),
+        ]);
+        plugins.set_routing(true);
+
+        let answers = stream(
+            &plugins,
+            vec![Message::Irc(irc_rust::Message::from(
+                "PRIVMSG #channel :!hello world",
+            ))],
+        )
+        .await?;
+
+        let mut answers = first_answers(answers);
+        answers.sort();
+        assert_eq!(
+            answers,
+            vec!["PRIVMSG #channel :!hello", "PRIVMSG #channel :*"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_access_checks() -> Result<(), PluginError> {
+        let mut plugins = Plugins::with_commands(vec![
+            PluginProxy::from(Arc::new(CommandPlugin("!hello".to_string()))),
+            PluginProxy::from(Arc::new(ModeratorPlugin("!ban".to_string()))),
+        ]);
+        plugins.set_routing(true);
+        plugins.set_access_checks(true);
+        let profile = Profile::new(
+            String::new(),
+            Vec::new(),
+            String::new(),
+            AccessRights::new(),
+            None,
+        );
+        plugins.init(&profile).await?;
+
+        let messages = vec![
+            // Denied as only the broadcaster can invoke commands
+            ("!hello", "subscriber/1"),
+            ("!hello", "broadcaster/1"),
+            // Denied by the required rights of ModeratorPlugin
+            ("!ban", "broadcaster/1"),
+            ("!ban", "broadcaster/1,moderator/1"),
+        ]
+        .into_iter()
+        .map(|(trailing, badges)| {
+            Message::Irc(
+                irc_rust::Message::builder("PRIVMSG")
+                    .tag("badges", badges)
+                    .param("#channel")
+                    .trailing(trailing)
+                    .build(),
+            )
+        })
+        .collect();
+        let answers = stream(&plugins, messages).await?;
+
+        let mut answers = first_answers(answers);
+        answers.sort();
+        assert_eq!(
+            answers,
+            vec!["PRIVMSG #channel :!ban", "PRIVMSG #channel :!hello"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reloaded_rights() -> Result<(), PluginError> {
+        let profile = |rights| Profile::new(String::new(), Vec::new(), String::new(), rights, None);
+        let reloaded = profile(AccessRights::from(vec![AccessFilter::badge(
+            "subscriber/*",
+        )
+        .unwrap()]));
+        let profile = profile(AccessRights::new());
+        let mut plugins = Plugins::with_commands(vec![PluginProxy::from(Arc::new(CommandPlugin(
+            "!hello".to_string(),
+        )))]);
+        plugins.context = Some(Context::new(profile.clone()));
+        plugins.set_access_checks(true);
+        plugins.init(&profile).await?;
+        let events = plugins.context().unwrap().events().clone();
+
+        let (mut input_sender, input_receiver) = channel::<Message>(0);
+        let (output_sender, mut output_receiver) = unbounded();
+        let hello = |badges: &str| {
+            Message::Irc(
+                irc_rust::Message::builder("PRIVMSG")
+                    .tag("badges", badges)
+                    .param("#channel")
+                    .trailing("!hello")
+                    .build(),
+            )
+        };
+        let send = async {
+            // Denied as only the broadcaster can invoke commands
+            input_sender.send(hello("subscriber/1")).await.unwrap();
+            input_sender.send(hello("broadcaster/1")).await.unwrap();
+            assert!(output_receiver.next().await.is_some());
+
+            events.publish(ProfileEvent {
+                profile: Arc::new(reloaded),
+                changes: vec![ProfileChange::RightsChanged],
+            });
+            input_sender.send(hello("subscriber/1")).await.unwrap();
+            input_sender.close_channel();
+        };
+        let (result, _) = futures::join!(plugins.stream(input_receiver, output_sender), send);
+        result?;
+
+        // Allowed by the reloaded rights
+        assert_eq!(output_receiver.collect::<Vec<_>>().await.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cooldowns() -> Result<(), PluginError> {
+        let mut plugins = Plugins::with_commands(vec![PluginProxy::from(Arc::new(CommandPlugin(
+            "!hello".to_string(),
+        )))]);
+        plugins.set_access_checks(true);
+        let mut profile = Profile::empty();
+        let mut cooldowns = Cooldowns::default();
+        cooldowns.set(
+            "!hello",
+            Cooldown {
+                user: Some(Duration::from_secs(60)),
+                ..Cooldown::default()
+            },
+        );
+        profile.set_cooldowns(cooldowns);
+        plugins.init(&profile).await?;
+
+        let messages = ["1", "1", "2"]
+            .iter()
+            .map(|user_id| {
+                Message::Irc(irc_rust::Message::from(format!(
+                    "@user-id={} :user!user@user.tmi.twitch.tv PRIVMSG #channel :!hello",
+                    user_id
+                )))
+            })
+            .collect();
+
+        assert_eq!(stream(&plugins, messages).await?.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_plugin_config() -> Result<(), PluginError> {
+        let register = |profile: &Profile| {
+            let mut plugins = Plugins::for_profile(profile.clone());
+            let context = plugins.context.clone();
+            let result = Plugins::register(
+                plugins.loaded.get_mut().unwrap(),
+                context.as_ref(),
+                Path::new("test.so"),
+                Arc::new(None),
+                |registrar| {
+                    let context = registrar.context().unwrap().clone();
+                    registrar.register(Arc::new(ConfigPlugin(context)))
+                },
+            );
+            (plugins, result)
+        };
+
+        let mut profile = Profile::empty();
+        // Missing configuration
+        let (plugins, result) = register(&profile);
+        assert!(result.is_err());
+        assert!(plugins.commands().is_empty());
+
+        let config = GreetConfig {
+            greeting: "Hello".to_string(),
+        };
+        profile.set_plugin_config("config", &config).unwrap();
+        assert_eq!(profile.plugin_config("config").unwrap(), Some(config));
+        assert!(profile.set_plugin_config("other", &"no map").is_err());
+        let (plugins, result) = register(&profile);
+        result.unwrap();
+        let answers = stream(
+            &plugins,
+            vec![Message::Irc(irc_rust::Message::from(
+                "PRIVMSG #channel :!hello",
+            ))],
+        )
+        .await?;
+        assert_eq!(first_answers(answers), vec!["PRIVMSG #channel :Hello"]);
+
+        // Invalid configurations are reported before initializing any plugin
+        profile
+            .set_plugin_config("config", &serde_json::json!({ "greeting": 5 }))
+            .unwrap();
+        assert!(profile.plugin_config::<GreetConfig>("config").is_err());
+        let plugins = Plugins::with_commands(vec![PluginProxy::from(Arc::new(ConfigPlugin(
+            Context::new(profile.clone()),
+        )))]);
+        assert!(plugins.init(&profile).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_profiles() -> Result<(), PluginError> {
+        let mut instances = Vec::new();
+        for name in &["foo", "bar"] {
+            let profile = Profile::new(
+                name.to_string(),
+                Vec::new(),
+                String::new(),
+                AccessRights::new(),
+                None,
+            );
+            let mut plugins = Plugins::for_profile(profile);
+            let context = plugins.context.clone();
+            // Registers a new instance of the plugin like a library loaded for each profile
+            Plugins::register(
+                plugins.loaded.get_mut().unwrap(),
+                context.as_ref(),
+                Path::new("test.so"),
+                Arc::new(None),
+                |registrar| {
+                    let context = registrar.context().unwrap().clone();
+                    registrar.register(Arc::new(ProfilePlugin(context)))
+                },
+            )
+            .unwrap();
+            instances.push(plugins);
         }
+
+        let hello = || {
+            vec![Message::Irc(irc_rust::Message::from(
+                "PRIVMSG #channel :!hello",
+            ))]
+        };
+        let (foo, bar) = futures::join!(
+            stream(&instances[0], hello()),
+            stream(&instances[1], hello())
+        );
+        assert_eq!(first_answers(foo?), vec!["PRIVMSG #channel :foo"]);
+        assert_eq!(first_answers(bar?), vec!["PRIVMSG #channel :bar"]);
+        assert_eq!(instances[1].context().unwrap().profile().name(), "bar");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_channel_settings() -> Result<(), PluginError> {
+        let mut profile = Profile::new(
+            "foo".to_string(),
+            vec!["#other".to_string()],
+            String::new(),
+            AccessRights::new(),
+            None,
+        );
+        profile.set_channel(
+            "#channel",
+            ChannelSettings {
+                plugins: Some(Vec::new()),
+                ..ChannelSettings::default()
+            },
+        );
+        let plugins = Plugins::with_commands(vec![PluginProxy::from(Arc::new(ProfilePlugin(
+            Context::new(profile.clone()),
+        )))]);
+        plugins.init(&profile).await?;
+
+        let messages = ["#channel", "#other"]
+            .iter()
+            .map(|channel| {
+                Message::Irc(irc_rust::Message::from(format!(
+                    "PRIVMSG {} :!hello",
+                    channel
+                )))
+            })
+            .collect();
+
+        // Only the message of the channel without restricted plugins reaches the plugin
+        assert_eq!(stream(&plugins, messages).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_channel_prefix() -> Result<(), PluginError> {
+        let mut profile = Profile::empty();
+        profile.add_channels(vec!["#other".to_string()]);
+        profile.set_channel(
+            "#channel",
+            ChannelSettings {
+                prefix: Some("~".to_string()),
+                ..ChannelSettings::default()
+            },
+        );
+        let mut plugins = Plugins::with_commands(vec![PluginProxy::from(Arc::new(CommandPlugin(
+            "!hello".to_string(),
+        )))]);
+        plugins.set_routing(true);
+        plugins.init(&profile).await?;
+
+        let mut messages = Vec::new();
+        for channel in &["#channel", "#other"] {
+            for trailing in &["~hello", "!hello"] {
+                messages.push(Message::Irc(irc_rust::Message::from(format!(
+                    "PRIVMSG {} :{}",
+                    channel, trailing
+                ))));
+            }
+        }
+
+        // Only the prefix of the channel invokes the command in it: `~hello` in #channel and
+        // `!hello` in #other
+        assert_eq!(stream(&plugins, messages).await?.len(), 2);
+        Ok(())
     }
 
     fn bench_plugins(b: &mut Bencher, mut runtime: Runtime, plugin_count: usize, load: usize) {
@@ -201,11 +1649,8 @@ mod tests {
         for _ in 0..plugin_count {
             raw_plugins.push(PluginProxy::from(Arc::new(TestCommand)));
         }
-        let plugins = Plugins {
-            commands: raw_plugins,
-            libraries: vec![],
-        };
-        let (mut input_sender, input_receiver) = futures::channel::mpsc::unbounded::<Message>();
+        let plugins = Plugins::with_commands(raw_plugins);
+        let (mut input_sender, input_receiver) = futures::channel::mpsc::channel::<Message>(0);
         let (output_sender, mut output_receiver) =
             futures::channel::mpsc::unbounded::<Vec<Message>>();
 
@@ -229,6 +1674,51 @@ mod tests {
         });
     }
 
+    /// Sends messages invoking the command of a single plugin to `plugin_count` plugins.
+    fn bench_routed_plugins(b: &mut Bencher, mut runtime: Runtime, plugin_count: usize) {
+        let mut plugins = Plugins::with_commands(
+            (0..plugin_count)
+                .map(|i| PluginProxy::from(Arc::new(CommandPlugin(format!("!command{}", i)))))
+                .collect(),
+        );
+        plugins.set_routing(true);
+        let (mut input_sender, input_receiver) = futures::channel::mpsc::channel::<Message>(0);
+        let (output_sender, mut output_receiver) =
+            futures::channel::mpsc::unbounded::<Vec<Message>>();
+
+        runtime.spawn(async move {
+            plugins.stream(input_receiver, output_sender).await.unwrap();
+        });
+
+        let message = Message::Irc(irc_rust::Message::from("PRIVMSG #channel :!command0"));
+
+        b.iter(|| {
+            runtime.block_on(async {
+                input_sender.send(message.clone()).await.unwrap();
+                let result = output_receiver.next().await;
+                assert_eq!(result.map(|result| result.len()), Some(1));
+            });
+        });
+    }
+
+    #[bench]
+    fn bench_64_plugin_basic_scheduler_routed(b: &mut Bencher) {
+        let runtime = Builder::new()
+            .basic_scheduler()
+            .build()
+            .expect("failed to build test runtime");
+        bench_routed_plugins(b, runtime, 64);
+    }
+
+    #[bench]
+    fn bench_64_plugin_threaded_scheduler_routed(b: &mut Bencher) {
+        let runtime = Builder::new()
+            .threaded_scheduler()
+            .build()
+            .expect("failed to build test runtime");
+        bench_routed_plugins(b, runtime, 64);
+    }
+
     #[bench]
     fn bench_1_plugin_basic_scheduler(b: &mut Bencher) {
         let runtime = Builder::new()
@@ -0,0 +1,459 @@
+//! Plugins running as child processes.
+//!
+//! A crashing process plugin doesn't take down the plugin-loader. The plugin-loader restarts
+//! the process with an exponential backoff instead.
+//!
+//! Process plugins are declared by a manifest file ending with `.process.json` in the plugins
+//! directory:
+//!
+//! ```json
+//! {
+//!   "command": "./hello-plugin",
+//!   "args": ["--verbose"],
+//!   "info": {
+//!     "name": "Hello Plugin",
+//!     "version": "0.1.0",
+//!     "authors": "mo_blaa",
+//!     "repo": null,
+//!     "commands": ["!hello"]
+//!   }
+//! }
+//! ```
+//!
+//! The process receives every [Message] as a single line of JSON on stdin and writes every
+//! result (`Vec<Message>`) as a single line of JSON to stdout. Stderr is inherited from the
+//! plugin-loader and can be used for logging. The process should exit after stdin was closed.
+//! Processes still running `shutdown_timeout_ms` (5 seconds by default) later are killed.
+//!
+//! Processes of plugins loaded for a profile get the profile owning them as JSON in the
+//! [PROFILE_ENV] environment variable. Every profile starts its own processes.
+
+use crate::context::Context;
+use crate::plugin::{PluginError, PluginInfo, StreamablePlugin};
+use crate::Message;
+use futures::channel::mpsc::{Receiver, UnboundedSender};
+use futures::{SinkExt, StreamExt};
+use std::cmp::min;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+use std::{fs, io};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+
+/// Suffix of the file name of process plugin manifests.
+pub const MANIFEST_SUFFIX: &str = ".process.json";
+
+/// Environment variable containing the profile owning a process plugin as JSON.
+pub const PROFILE_ENV: &str = "BRS_PROFILE";
+
+const DEFAULT_INITIAL_BACKOFF_MS: u64 = 1_000;
+const DEFAULT_MAX_BACKOFF_MS: u64 = 60_000;
+const DEFAULT_SHUTDOWN_TIMEOUT_MS: u64 = 5_000;
+
+/// Returns if the file at `path` is a process plugin manifest based on its name.
+pub fn is_manifest(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.ends_with(MANIFEST_SUFFIX))
+        .unwrap_or(false)
+}
+
+/// Declares how to start a process plugin.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ProcessManifest {
+    /// Executable to start. Relative paths are resolved against the directory of the manifest
+    /// if the file exists there. Otherwise the executable is looked up in `PATH`.
+    pub command: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub info: PluginInfo,
+    /// Time to wait before the first restart of a crashed process. Doubled on every
+    /// consecutive crash.
+    #[serde(default)]
+    pub initial_backoff_ms: Option<u64>,
+    /// Maximum time to wait before restarting a crashed process. Processes running longer
+    /// than this reset the backoff.
+    #[serde(default)]
+    pub max_backoff_ms: Option<u64>,
+    /// Time the process has to exit after stdin was closed before it's killed.
+    #[serde(default)]
+    pub shutdown_timeout_ms: Option<u64>,
+}
+
+/// Reason for a process plugin run to end.
+#[derive(Debug)]
+enum Exit {
+    InputClosed,
+    OutputClosed,
+    Exited(ExitStatus),
+}
+
+/// [StreamablePlugin] exchanging messages with a child process.
+#[derive(Debug, Clone)]
+pub struct ProcessPlugin {
+    manifest: ProcessManifest,
+    dir: PathBuf,
+    context: Option<Context>,
+}
+
+impl ProcessPlugin {
+    /// Creates the plugin from the manifest at `path`.
+    pub fn from_manifest(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let manifest = serde_json::from_str(&content)?;
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(ProcessPlugin::new(manifest, dir))
+    }
+
+    /// Creates the plugin from a manifest. Relative commands are resolved against `dir`.
+    pub fn new(manifest: ProcessManifest, dir: PathBuf) -> Self {
+        ProcessPlugin {
+            manifest,
+            dir,
+            context: None,
+        }
+    }
+
+    /// Passes the profile of `context` to the started processes (see [PROFILE_ENV]).
+    pub fn with_context(mut self, context: Context) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    fn command(&self) -> PathBuf {
+        let local = self.dir.join(&self.manifest.command);
+        if self.manifest.command.is_relative() && local.is_file() {
+            local
+        } else {
+            self.manifest.command.clone()
+        }
+    }
+
+    fn initial_backoff(&self) -> Duration {
+        Duration::from_millis(
+            self.manifest
+                .initial_backoff_ms
+                .unwrap_or(DEFAULT_INITIAL_BACKOFF_MS),
+        )
+    }
+
+    fn max_backoff(&self) -> Duration {
+        Duration::from_millis(
+            self.manifest
+                .max_backoff_ms
+                .unwrap_or(DEFAULT_MAX_BACKOFF_MS),
+        )
+    }
+
+    fn shutdown_timeout(&self) -> Duration {
+        Duration::from_millis(
+            self.manifest
+                .shutdown_timeout_ms
+                .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_MS),
+        )
+    }
+
+    /// Starts the process and exchanges messages until the process exits or one of the
+    /// channels is closed.
+    ///
+    /// `pending` is sent before any message of `input`. It contains the message which couldn't
+    /// be sent if the process stopped receiving messages.
+    async fn run(
+        &self,
+        input: &mut Receiver<Message>,
+        output: &mut UnboundedSender<Vec<Message>>,
+        pending: &mut Option<Message>,
+    ) -> io::Result<Exit> {
+        let mut command = Command::new(self.command());
+        if let Some(context) = &self.context {
+            command.env(PROFILE_ENV, serde_json::to_string(context.profile())?);
+        }
+        let mut child = command
+            .args(&self.manifest.args)
+            .current_dir(&self.dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .expect("stdin of process plugin not piped");
+        let stdout = child
+            .stdout
+            .take()
+            .expect("stdout of process plugin not piped");
+        let mut lines = BufReader::new(stdout).lines().fuse();
+
+        if let Some(msg) = pending.take() {
+            if !self.send(&mut stdin, msg, pending).await? {
+                return Ok(Exit::Exited(Self::kill(child).await?));
+            }
+        }
+        loop {
+            futures::select! {
+                msg = input.next() => match msg {
+                    Some(msg) => if !self.send(&mut stdin, msg, pending).await? {
+                        return Ok(Exit::Exited(Self::kill(child).await?));
+                    },
+                    None => {
+                        // Let the process finish and forward its remaining results
+                        drop(stdin);
+                        let finish = async {
+                            while let Some(line) = lines.next().await {
+                                if !self.forward(&line?, output).await {
+                                    return Ok(Exit::OutputClosed);
+                                }
+                            }
+                            (&mut child).await?;
+                            Ok::<_, io::Error>(Exit::InputClosed)
+                        };
+                        return match tokio::time::timeout(self.shutdown_timeout(), finish).await {
+                            Ok(exit) => exit,
+                            Err(_) => {
+                                warn!(
+                                    "Killing process plugin {} as it didn't exit within {:?}",
+                                    self.manifest.info.name,
+                                    self.shutdown_timeout()
+                                );
+                                Self::kill(child).await?;
+                                Ok(Exit::InputClosed)
+                            }
+                        };
+                    }
+                },
+                line = lines.next() => match line {
+                    Some(line) => if !self.forward(&line?, output).await {
+                        return Ok(Exit::OutputClosed);
+                    },
+                    None => return Ok(Exit::Exited(child.await?)),
+                },
+            }
+        }
+    }
+
+    /// Writes `msg` as a line to `stdin`. Returns `false` and keeps `msg` in `pending` to be
+    /// sent to the restarted process if the process doesn't receive messages anymore.
+    async fn send(
+        &self,
+        stdin: &mut ChildStdin,
+        msg: Message,
+        pending: &mut Option<Message>,
+    ) -> io::Result<bool> {
+        let mut line = serde_json::to_vec(&msg)?;
+        line.push(b'\n');
+        match stdin.write_all(&line).await {
+            Ok(()) => Ok(true),
+            Err(why) => {
+                warn!(
+                    "Failed to send message to process plugin {}: {}",
+                    self.manifest.info.name, why
+                );
+                *pending = Some(msg);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Kills the process and waits for it to exit.
+    async fn kill(mut child: Child) -> io::Result<ExitStatus> {
+        // Fails if the process already exited
+        let _ = child.kill();
+        child.await
+    }
+
+    /// Sends the results contained in `line` to `output`. Returns `false` if `output` is
+    /// closed.
+    async fn forward(&self, line: &str, output: &mut UnboundedSender<Vec<Message>>) -> bool {
+        match serde_json::from_str::<Vec<Message>>(line) {
+            Ok(results) => output.send(results).await.is_ok(),
+            Err(why) => {
+                warn!(
+                    "Invalid output of process plugin {}: {}",
+                    self.manifest.info.name, why
+                );
+                true
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl StreamablePlugin for ProcessPlugin {
+    /// Runs the process until `input` is closed. Restarts the process with an exponential
+    /// backoff if it exits or can't be started. Messages received while the process isn't
+    /// running and the message the process stopped receiving at are sent after the restart.
+    async fn stream(
+        &self,
+        mut input: Receiver<Message>,
+        mut output: UnboundedSender<Vec<Message>>,
+    ) -> Result<(), PluginError> {
+        let name = &self.manifest.info.name;
+        let mut backoff = self.initial_backoff();
+        let mut pending = None;
+        loop {
+            let started = Instant::now();
+            match self.run(&mut input, &mut output, &mut pending).await {
+                Ok(Exit::InputClosed) | Ok(Exit::OutputClosed) => return Ok(()),
+                Ok(Exit::Exited(status)) => warn!("Process plugin {} exited: {}", name, status),
+                Err(why) => error!("Failed to run process plugin {}: {}", name, why),
+            }
+            if started.elapsed() >= self.max_backoff() {
+                backoff = self.initial_backoff();
+            }
+            info!("Restarting process plugin {} in {:?}", name, backoff);
+            tokio::time::delay_for(backoff).await;
+            backoff = min(backoff * 2, self.max_backoff());
+        }
+    }
+
+    fn info(&self) -> PluginInfo {
+        self.manifest.info.clone()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::context::Context;
+    use crate::plugin::{PluginInfo, StreamablePlugin};
+    use crate::process::{ProcessManifest, ProcessPlugin};
+    use crate::profile::Profile;
+    use crate::Message;
+    use futures::channel::mpsc::{channel, unbounded};
+    use futures::StreamExt;
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    fn shell_plugin(script: &str) -> ProcessPlugin {
+        ProcessPlugin::new(
+            ProcessManifest {
+                command: PathBuf::from("sh"),
+                args: vec!["-c".to_string(), script.to_string()],
+                info: PluginInfo {
+                    name: "Shell".to_string(),
+                    version: "".to_string(),
+                    authors: "".to_string(),
+                    repo: None,
+                    commands: vec![],
+                    required_rights: Default::default(),
+                },
+                initial_backoff_ms: Some(1),
+                max_backoff_ms: Some(10),
+                shutdown_timeout_ms: Some(100),
+            },
+            std::env::temp_dir(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_echo() {
+        let plugin = shell_plugin(r#"while read -r line; do echo "[$line]"; done"#);
+        let (mut input_sender, input_receiver) = channel(0);
+        let (output_sender, output_receiver) = unbounded();
+        let message = Message::Irc(irc_rust::Message::from("PRIVMSG #channel :hello"));
+
+        input_sender.try_send(message.clone()).unwrap();
+        input_sender.close_channel();
+        plugin.stream(input_receiver, output_sender).await.unwrap();
+
+        let results = output_receiver.collect::<Vec<_>>().await;
+        assert_eq!(results, vec![vec![message]]);
+    }
+
+    #[tokio::test]
+    async fn test_profile() {
+        let profile = Profile::new(
+            "foo".to_string(),
+            Vec::new(),
+            String::new(),
+            Default::default(),
+            None,
+        );
+        // Only echoes messages if the profile was passed
+        let plugin = shell_plugin(
+            r#"while read -r line; do case "$BRS_PROFILE" in *'"name":"foo"'*) echo "[$line]";; esac; done"#,
+        )
+        .with_context(Context::new(profile));
+        let (mut input_sender, input_receiver) = channel(0);
+        let (output_sender, output_receiver) = unbounded();
+        let message = Message::Irc(irc_rust::Message::from("PRIVMSG #channel :hello"));
+
+        input_sender.try_send(message.clone()).unwrap();
+        input_sender.close_channel();
+        plugin.stream(input_receiver, output_sender).await.unwrap();
+
+        let results = output_receiver.collect::<Vec<_>>().await;
+        assert_eq!(results, vec![vec![message]]);
+    }
+
+    #[tokio::test]
+    async fn test_restart() {
+        // Crashes on first start
+        let marker = std::env::temp_dir().join(format!("botrs-restart-{}", std::process::id()));
+        let plugin = shell_plugin(&format!(
+            r#"if [ -e "{0}" ]; then while read -r line; do echo "[$line]"; done; else touch "{0}"; exit 1; fi"#,
+            marker.display()
+        ));
+        let (mut input_sender, input_receiver) = channel(0);
+        let (output_sender, mut output_receiver) = unbounded();
+        let message = Message::Irc(irc_rust::Message::from("PRIVMSG #channel :hello"));
+
+        let stream =
+            tokio::spawn(async move { plugin.stream(input_receiver, output_sender).await.is_ok() });
+        // Wait for the restarted process
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+        input_sender.try_send(message.clone()).unwrap();
+        assert_eq!(output_receiver.next().await, Some(vec![message]));
+
+        input_sender.close_channel();
+        assert!(stream.await.unwrap());
+        std::fs::remove_file(marker).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resend() {
+        // Stops receiving messages on first start
+        let marker = std::env::temp_dir().join(format!("botrs-resend-{}", std::process::id()));
+        let plugin = shell_plugin(&format!(
+            r#"if [ -e "{0}" ]; then while read -r line; do echo "[$line]"; done; else touch "{0}"; exec 0<&-; exec sleep 10; fi"#,
+            marker.display()
+        ));
+        let (mut input_sender, input_receiver) = channel(0);
+        let (output_sender, mut output_receiver) = unbounded();
+        let message = Message::Irc(irc_rust::Message::from("PRIVMSG #channel :hello"));
+
+        let stream =
+            tokio::spawn(async move { plugin.stream(input_receiver, output_sender).await.is_ok() });
+        // Wait for stdin to be closed
+        while !marker.exists() {
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+        input_sender.try_send(message.clone()).unwrap();
+        assert_eq!(output_receiver.next().await, Some(vec![message]));
+
+        input_sender.close_channel();
+        assert!(stream.await.unwrap());
+        std::fs::remove_file(marker).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_timeout() {
+        // Keeps running after stdin was closed
+        let plugin = shell_plugin(r#"while read -r line; do echo "[$line]"; done; exec sleep 10"#);
+        let (mut input_sender, input_receiver) = channel(0);
+        let (output_sender, output_receiver) = unbounded();
+        let message = Message::Irc(irc_rust::Message::from("PRIVMSG #channel :hello"));
+
+        input_sender.try_send(message.clone()).unwrap();
+        input_sender.close_channel();
+        let started = Instant::now();
+        plugin.stream(input_receiver, output_sender).await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let results = output_receiver.collect::<Vec<_>>().await;
+        assert_eq!(results, vec![vec![message]]);
+    }
+}
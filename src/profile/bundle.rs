@@ -0,0 +1,396 @@
+//! Export and import of profiles as bundles to move them between machines.
+//!
+//! A bundle is a gzip compressed tar archive containing:
+//!
+//! - `manifest.json`: Name of the profile and the SHA-256 checksums of all other files.
+//! - `config.{extension}`: Configuration file of the profile.
+//! - `secrets.json`: Encrypted secrets of the profile (see [crate::secrets::EncryptedFile]).
+//! - `plugins/`: Plugins directory of the profile.
+//!
+//! Bundles exported with [Secrets::Strip] contain neither the secrets file nor credentials or
+//! client secret in the configuration.
+
+use crate::profile::config::find_file;
+use crate::profile::files::write_atomic;
+use crate::profile::{Profile, ProfileError};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use openssl::sha::sha256;
+use std::collections::BTreeMap;
+use std::fs::{self, create_dir_all, read, read_dir, remove_dir_all, File};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+const MANIFEST: &str = "manifest.json";
+const SECRETS: &str = "secrets.json";
+const PLUGINS: &str = "plugins";
+const BUNDLE_VERSION: u32 = 1;
+
+/// Handling of secrets when exporting a profile.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Secrets {
+    /// Includes the (encrypted) secrets and references to them.
+    Include,
+    /// Removes all secrets. Credentials have to be set again after importing the bundle.
+    Strip,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    name: String,
+    secrets_stripped: bool,
+    /// Maps the paths of the files to their hex encoded SHA-256 checksums.
+    files: BTreeMap<String, String>,
+}
+
+/// File of a bundle with its path relative to the profile directory.
+struct Entry {
+    path: String,
+    mode: u32,
+    data: Vec<u8>,
+}
+
+/// Exports the profile stored in `profile_dir` to the bundle at `archive`.
+pub fn export(profile_dir: &Path, archive: &Path, secrets: Secrets) -> Result<(), ProfileError> {
+    let name = profile_dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| ProfileError::InvalidBundle("invalid profile directory".to_string()))?;
+    let (cfg_file, format) = find_file(profile_dir, "config");
+    let mut config = read(&cfg_file).map_err(ProfileError::from)?;
+    if secrets == Secrets::Strip {
+        let mut values = format.parse(&String::from_utf8_lossy(&config))?;
+        if let Some(values) = values.as_object_mut() {
+            values.insert("credentials".to_string(), serde_json::json!({}));
+            values.insert("client_secret".to_string(), serde_json::Value::Null);
+        }
+        config = format.serialize(&values)?.into_bytes();
+    }
+
+    let mut entries = vec![Entry {
+        path: format!("config.{}", format.extension()),
+        mode: mode(&cfg_file),
+        data: config,
+    }];
+    let secrets_file = profile_dir.join(SECRETS);
+    if secrets == Secrets::Include && secrets_file.is_file() {
+        entries.push(Entry {
+            path: SECRETS.to_string(),
+            mode: mode(&secrets_file),
+            data: read(&secrets_file).map_err(ProfileError::from)?,
+        });
+    }
+    let plugins = profile_dir.join(PLUGINS);
+    if plugins.is_dir() {
+        collect(&plugins, PLUGINS, &mut entries)?;
+    }
+
+    let manifest = Manifest {
+        version: BUNDLE_VERSION,
+        name: name.to_string(),
+        secrets_stripped: secrets == Secrets::Strip,
+        files: entries
+            .iter()
+            .map(|entry| (entry.path.clone(), checksum(&entry.data)))
+            .collect(),
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest).map_err(ProfileError::from)?;
+
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    let manifest = Entry {
+        path: MANIFEST.to_string(),
+        mode: 0o644,
+        data: manifest,
+    };
+    for entry in std::iter::once(&manifest).chain(entries.iter()) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(entry.data.len() as u64);
+        header.set_mode(entry.mode);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, &entry.path, entry.data.as_slice())
+            .map_err(ProfileError::from)?;
+    }
+    let compressed = builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(ProfileError::from)?;
+    write_atomic(archive, &compressed).map_err(ProfileError::from)
+}
+
+/// Imports the bundle at `archive` as new profile into `profiles_dir`. The profile is named
+/// `name` or keeps its exported name.
+///
+/// Returns [ProfileError::AlreadyExists] if a profile of the same name exists.
+pub fn import(
+    archive: &Path,
+    profiles_dir: &Path,
+    name: Option<&str>,
+) -> Result<Profile, ProfileError> {
+    let (manifest, entries) = read_bundle(File::open(archive).map_err(ProfileError::from)?)?;
+    let name = name.unwrap_or(&manifest.name);
+    if !is_plain(Path::new(name)) || Path::new(name).components().count() != 1 {
+        return Err(ProfileError::InvalidBundle(format!(
+            "invalid profile name '{}'",
+            name
+        )));
+    }
+    let target = profiles_dir.join(name);
+    if target.exists() {
+        return Err(ProfileError::AlreadyExists(name.into()));
+    }
+
+    // Write to a temporary directory first to not leave partially imported profiles
+    let tmp = profiles_dir.join(format!(".{}.import-{}", name, std::process::id()));
+    let result = write_entries(&tmp, &entries).and_then(|_| {
+        if target.exists() {
+            return Err(ProfileError::AlreadyExists(name.into()));
+        }
+        fs::rename(&tmp, &target).map_err(ProfileError::from)
+    });
+    if let Err(why) = result {
+        let _ = remove_dir_all(&tmp);
+        return Err(why);
+    }
+    Profile::from_path(&target)
+}
+
+/// Reads and verifies all files of a bundle.
+fn read_bundle<R: Read>(reader: R) -> Result<(Manifest, Vec<Entry>), ProfileError> {
+    let mut archive = tar::Archive::new(GzDecoder::new(reader));
+    let mut manifest = None;
+    let mut entries = Vec::new();
+    for entry in archive.entries().map_err(ProfileError::from)? {
+        let mut entry = entry.map_err(ProfileError::from)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path().map_err(ProfileError::from)?.into_owned();
+        if !is_plain(&path) {
+            return Err(ProfileError::InvalidBundle(format!(
+                "invalid path '{}'",
+                path.display()
+            )));
+        }
+        let path = path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let mode = entry.header().mode().unwrap_or(0o644);
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).map_err(ProfileError::from)?;
+        if path == MANIFEST {
+            manifest = Some(serde_json::from_slice::<Manifest>(&data)?);
+        } else {
+            entries.push(Entry { path, mode, data });
+        }
+    }
+
+    let manifest =
+        manifest.ok_or_else(|| ProfileError::InvalidBundle("missing manifest".to_string()))?;
+    if manifest.version > BUNDLE_VERSION {
+        return Err(ProfileError::InvalidBundle(format!(
+            "unsupported bundle version {}",
+            manifest.version
+        )));
+    }
+    for entry in entries.iter() {
+        match manifest.files.get(&entry.path) {
+            Some(expected) if *expected == checksum(&entry.data) => (),
+            Some(_) => {
+                return Err(ProfileError::InvalidBundle(format!(
+                    "checksum mismatch of '{}'",
+                    entry.path
+                )))
+            }
+            None => {
+                return Err(ProfileError::InvalidBundle(format!(
+                    "unknown file '{}'",
+                    entry.path
+                )))
+            }
+        }
+    }
+    if entries.len() != manifest.files.len() {
+        return Err(ProfileError::InvalidBundle("missing files".to_string()));
+    }
+    Ok((manifest, entries))
+}
+
+fn write_entries(dir: &Path, entries: &[Entry]) -> Result<(), ProfileError> {
+    create_dir_all(dir.join(PLUGINS)).map_err(ProfileError::from)?;
+    for entry in entries {
+        let path = dir.join(&entry.path);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).map_err(ProfileError::from)?;
+        }
+        fs::write(&path, &entry.data).map_err(ProfileError::from)?;
+        set_mode(&path, entry.mode)?;
+    }
+    Ok(())
+}
+
+/// Adds all files in `dir` to `entries` with paths relative to the profile directory.
+fn collect(dir: &Path, prefix: &str, entries: &mut Vec<Entry>) -> Result<(), ProfileError> {
+    let mut paths = read_dir(dir)
+        .map_err(ProfileError::from)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<PathBuf>, _>>()
+        .map_err(ProfileError::from)?;
+    // Reproducible order of the archive
+    paths.sort();
+    for path in paths {
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => format!("{}/{}", prefix, name),
+            None => {
+                warn!("skipping file with invalid name: {}", path.display());
+                continue;
+            }
+        };
+        if path.is_dir() {
+            collect(&path, &name, entries)?;
+        } else if path.is_file() {
+            entries.push(Entry {
+                path: name,
+                mode: mode(&path),
+                data: read(&path).map_err(ProfileError::from)?,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Returns if `path` is relative and doesn't leave its root.
+fn is_plain(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_)))
+}
+
+fn checksum(data: &[u8]) -> String {
+    sha256(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(unix)]
+fn mode(path: &Path) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .map(|metadata| metadata.permissions().mode() & 0o777)
+        .unwrap_or(0o644)
+}
+
+#[cfg(not(unix))]
+fn mode(_path: &Path) -> u32 {
+    0o644
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<(), ProfileError> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777)).map_err(ProfileError::from)
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<(), ProfileError> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::bundle::{checksum, export, import, read_bundle, Secrets};
+    use crate::profile::ProfileError;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::fs::{create_dir_all, read, read_to_string, remove_dir_all, write};
+    use std::path::PathBuf;
+
+    fn dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("botrs-bundle-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_export_import() {
+        let source = dir("source").join("foo");
+        let target = dir("target");
+        create_dir_all(source.join("plugins/nested")).unwrap();
+        create_dir_all(&target).unwrap();
+        write(
+            source.join("config.json"),
+            r##"{"version":2,"name":"foo","channels":["#foo"],"credentials":{"Twitch":{"Store":"foo/twitch"}},"client_id":"id","client_secret":null,"rights":{"filters":[]}}"##,
+        )
+        .unwrap();
+        write(source.join("secrets.json"), "encrypted").unwrap();
+        write(source.join("plugins/hello.so"), b"\x7fELF").unwrap();
+        write(source.join("plugins/nested/plugin.toml"), "command = []").unwrap();
+        write(source.join("config.json.v0.bak"), "{}").unwrap();
+
+        let archive = dir("foo.tar.gz");
+        export(&source, &archive, Secrets::Include).unwrap();
+        let profile = import(&archive, &target, None).unwrap();
+        assert_eq!(profile.name(), "foo");
+        assert_eq!(profile.get_channels(), vec!["#foo".to_string()]);
+        assert_eq!(
+            read(target.join("foo/plugins/hello.so")).unwrap(),
+            b"\x7fELF"
+        );
+        assert_eq!(
+            read_to_string(target.join("foo/plugins/nested/plugin.toml")).unwrap(),
+            "command = []"
+        );
+        assert_eq!(
+            read_to_string(target.join("foo/secrets.json")).unwrap(),
+            "encrypted"
+        );
+        assert!(!target.join("foo/config.json.v0.bak").exists());
+
+        assert!(matches!(
+            import(&archive, &target, None),
+            Err(ProfileError::AlreadyExists(_))
+        ));
+        assert!(import(&archive, &target, Some("../bar")).is_err());
+
+        export(&source, &archive, Secrets::Strip).unwrap();
+        let profile = import(&archive, &target, Some("bar")).unwrap();
+        assert_eq!(profile.name(), "bar");
+        assert!(!target.join("bar/secrets.json").exists());
+        let config = read_to_string(target.join("bar/config.json")).unwrap();
+        assert!(!config.contains("foo/twitch"));
+
+        remove_dir_all(source.parent().unwrap()).unwrap();
+        remove_dir_all(&target).unwrap();
+        std::fs::remove_file(&archive).unwrap();
+    }
+
+    #[test]
+    fn test_checksums() {
+        let bundle = |config: &[u8]| {
+            let manifest = format!(
+                r#"{{"version":1,"name":"foo","secrets_stripped":false,"files":{{"config.json":"{}"}}}}"#,
+                checksum(b"{}")
+            );
+            let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+            for (path, data) in &[
+                ("manifest.json", manifest.as_bytes()),
+                ("config.json", config),
+            ] {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                builder.append_data(&mut header, path, *data).unwrap();
+            }
+            builder.into_inner().unwrap().finish().unwrap()
+        };
+
+        assert!(read_bundle(bundle(b"{}").as_slice()).is_ok());
+        assert!(matches!(
+            read_bundle(bundle(b"{\"name\":\"bar\"}").as_slice()),
+            Err(ProfileError::InvalidBundle(_))
+        ));
+    }
+}
@@ -0,0 +1,145 @@
+//! Settings of the channels a profile joins.
+//!
+//! Channels are identified by their normalized name: lowercase with a leading `#`, e.g. `#foo`
+//! for `Foo`. Every channel can override the access rights of the profile and restrict the
+//! plugins receiving its messages.
+
+use crate::command_access::AccessRights;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+
+/// Settings of a single channel. Unset settings fall back to the ones of the profile.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ChannelSettings {
+    /// Access rights used instead of the rights of the profile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rights: Option<AccessRights>,
+    /// Names of the plugins receiving messages of the channel. All plugins receive them if
+    /// unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plugins: Option<Vec<String>>,
+    /// Prefix of the commands in the channel instead of the [crate::router::COMMAND_PREFIXES],
+    /// e.g. `~`. Commands invoked with it reach the plugins with the
+    /// [crate::router::DEFAULT_PREFIX], e.g. `~hello` as `!hello`. Other prefixes don't invoke
+    /// commands routed to plugins in the channel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// Language of the channel as IETF language tag, e.g. `en`. Plugins read it from the profile
+    /// of their [crate::context::Context] to answer in the language of the channel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+impl ChannelSettings {
+    /// Returns if the plugin named `plugin` receives messages of the channel.
+    pub fn is_enabled(&self, plugin: &str) -> bool {
+        match self.plugins {
+            Some(ref plugins) => plugins.iter().any(|enabled| enabled == plugin),
+            None => true,
+        }
+    }
+
+    /// Returns if no setting overrides the ones of the profile.
+    pub fn is_default(&self) -> bool {
+        *self == ChannelSettings::default()
+    }
+}
+
+impl Display for ChannelSettings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut settings = Vec::new();
+        if let Some(ref prefix) = self.prefix {
+            settings.push(format!("Prefix: {}", prefix));
+        }
+        if let Some(ref language) = self.language {
+            settings.push(format!("Language: {}", language));
+        }
+        if let Some(ref plugins) = self.plugins {
+            settings.push(format!("Plugins: [{}]", plugins.join(", ")));
+        }
+        if self.rights.is_some() {
+            settings.push("Own Access Rights".to_string());
+        }
+        if settings.is_empty() {
+            write!(f, "Default")
+        } else {
+            write!(f, "{}", settings.join(", "))
+        }
+    }
+}
+
+/// Returns the normalized name of `channel` or `None` if the name is empty.
+pub fn normalize(channel: &str) -> Option<String> {
+    let name = channel.trim().trim_start_matches('#');
+    if name.is_empty() {
+        None
+    } else {
+        Some(format!("#{}", name.to_lowercase()))
+    }
+}
+
+/// Deserializes channel settings normalizing their names. Fails on invalid and duplicate names.
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, ChannelSettings>, D::Error> {
+    let mut channels = BTreeMap::new();
+    for (channel, settings) in BTreeMap::<String, ChannelSettings>::deserialize(deserializer)? {
+        let name = normalize(&channel)
+            .ok_or_else(|| D::Error::custom(format!("invalid channel name '{}'", channel)))?;
+        if channels.insert(name, settings).is_some() {
+            return Err(D::Error::custom(format!("duplicate channel '{}'", channel)));
+        }
+    }
+    Ok(channels)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::channels::{normalize, ChannelSettings};
+    use crate::profile::Profile;
+    use serde_json::json;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("#Foo"), Some("#foo".to_string()));
+        assert_eq!(normalize(" bar"), Some("#bar".to_string()));
+        assert_eq!(normalize("#"), None);
+        assert_eq!(normalize(""), None);
+    }
+
+    #[test]
+    fn test_channels() {
+        let mut profile = Profile::empty();
+        profile.add_channels(vec![
+            "#Foo".to_string(),
+            "foo".to_string(),
+            "#bar".to_string(),
+        ]);
+        assert_eq!(
+            profile.get_channels(),
+            vec!["#bar".to_string(), "#foo".to_string()]
+        );
+
+        let settings = ChannelSettings {
+            plugins: Some(vec!["hello".to_string()]),
+            prefix: Some("?".to_string()),
+            ..ChannelSettings::default()
+        };
+        profile.set_channel("#FOO", settings.clone());
+        assert_eq!(profile.channel("foo"), Some(&settings));
+        assert!(profile.channel("#foo").unwrap().is_enabled("hello"));
+        assert!(!profile.channel("#foo").unwrap().is_enabled("other"));
+        assert!(profile.channel("#bar").unwrap().is_enabled("other"));
+        // Adding an existing channel keeps its settings
+        profile.add_channels(vec!["#foo".to_string()]);
+        assert_eq!(profile.channel("#foo"), Some(&settings));
+        assert_eq!(profile.remove_channel("Foo"), Some(settings));
+        assert_eq!(profile.get_channels(), vec!["#bar".to_string()]);
+
+        let mut config = serde_json::to_value(&profile).unwrap();
+        config["channels"] = json!({ "#Foo": {}, "#foo": {} });
+        assert!(serde_json::from_value::<Profile>(config).is_err());
+    }
+}
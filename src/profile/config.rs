@@ -0,0 +1,621 @@
+//! Layered configuration of Bot-RS.
+//!
+//! The effective configuration merges the following layers. Later layers take precedence:
+//!
+//! 1. Defaults (see [Config::default]).
+//! 2. The configuration file of the profile (`config.json`, `config.toml` or `config.yaml`).
+//! 3. Environment variables (see [ENV_VARS]).
+//! 4. Command line overrides in the format `key=value` (see [Config::add_args]).
+//!
+//! Values are addressed by dot separated keys, e.g. `twitch.auth`. Objects are merged across
+//! layers: A layer only replaces the values it sets, e.g. a layer setting `twitch.auth` keeps
+//! `twitch.scopes` of lower layers. Values that aren't objects, including arrays, are replaced
+//! as a whole.
+//!
+//! The configuration is separate from the settings stored in the profile itself: The
+//! environment and command line overrides only apply to values read through [Config], they
+//! don't change the fields of a loaded [Profile](crate::profile::Profile).
+//!
+//! TOML and YAML files are only supported with the `toml` and `yaml` features.
+
+use crate::profile::channels::normalize;
+use crate::profile::ProfileError;
+use crate::secrets::mask;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fmt::{self, Display, Formatter};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+/// Authentication flow used for Twitch: `token`, `code` or `client_credentials`.
+pub const KEY_TWITCH_AUTH: &str = "twitch.auth";
+/// Scopes requested from Twitch.
+pub const KEY_TWITCH_SCOPES: &str = "twitch.scopes";
+/// Redirect URI of the OAuth flows.
+pub const KEY_TWITCH_REDIRECT_URI: &str = "twitch.redirect_uri";
+/// Channels joined by the bot and their settings, e.g. `{ "#foo": {} }`. Lists of channel
+/// names set in the environment or on the command line are added with default settings.
+pub const KEY_CHANNELS: &str = "channels";
+
+/// Values nested in these keys are secrets, e.g. `credentials.Twitch.Plain`.
+const SECRET_KEYS: [&str; 2] = ["credentials", "client_secret"];
+
+pub const DEFAULT_TWITCH_AUTH: &str = "token";
+pub const DEFAULT_TWITCH_REDIRECT_URI: &str = "http://localhost:4334/";
+pub const DEFAULT_TWITCH_SCOPES: [&str; 8] = [
+    "channel:moderate",
+    "chat:edit",
+    "chat:read",
+    "user:edit:follows",
+    "user_follows_edit",
+    "user:edit",
+    "user_read",
+    "whispers:edit",
+];
+
+/// Format of environment variables.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum EnvFormat {
+    Text,
+    /// Comma separated list.
+    List,
+    /// Comma separated channel names stored like [KEY_CHANNELS].
+    Channels,
+    Json,
+}
+
+/// Environment variables overriding configuration keys.
+pub const ENV_VARS: [(&str, &str, EnvFormat); 3] = [
+    ("BRS_TWITCH_AUTH", KEY_TWITCH_AUTH, EnvFormat::Text),
+    ("BRS_TWITCH_SCOPES", KEY_TWITCH_SCOPES, EnvFormat::List),
+    ("BRS_JOINED_CHANNELS", KEY_CHANNELS, EnvFormat::Channels),
+];
+
+/// Formats of configuration files.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Format {
+    Json,
+    #[cfg(feature = "toml")]
+    Toml,
+    #[cfg(feature = "yaml")]
+    Yaml,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format::Json
+    }
+}
+
+impl Format {
+    /// Supported formats in the order they're looked up by [find_file].
+    pub fn all() -> Vec<Format> {
+        vec![
+            Format::Json,
+            #[cfg(feature = "toml")]
+            Format::Toml,
+            #[cfg(feature = "yaml")]
+            Format::Yaml,
+        ]
+    }
+
+    /// Returns the format of the file at `path` by its extension.
+    pub fn of(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_str()?;
+        Format::all()
+            .into_iter()
+            .find(|format| format.extensions().contains(&extension))
+    }
+
+    /// Returns the file extensions of the format. The first one is used for new files.
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Format::Json => &["json"],
+            #[cfg(feature = "toml")]
+            Format::Toml => &["toml"],
+            #[cfg(feature = "yaml")]
+            Format::Yaml => &["yaml", "yml"],
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        self.extensions()[0]
+    }
+
+    pub fn parse(self, content: &str) -> Result<Value, ProfileError> {
+        match self {
+            Format::Json => serde_json::from_str(content).map_err(ProfileError::from),
+            #[cfg(feature = "toml")]
+            Format::Toml => toml::from_str(content)
+                .map_err(|why| ProfileError::Format(format!("invalid TOML: {}", why))),
+            #[cfg(feature = "yaml")]
+            Format::Yaml => serde_yaml::from_str(content)
+                .map_err(|why| ProfileError::Format(format!("invalid YAML: {}", why))),
+        }
+    }
+
+    pub fn serialize<T: Serialize>(self, value: &T) -> Result<String, ProfileError> {
+        match self {
+            Format::Json => serde_json::to_string_pretty(value).map_err(ProfileError::from),
+            #[cfg(feature = "toml")]
+            Format::Toml => {
+                // TOML has no null values
+                let mut value = serde_json::to_value(value).map_err(ProfileError::from)?;
+                strip_nulls(&mut value);
+                toml::Value::try_from(value)
+                    .and_then(|value| toml::to_string_pretty(&value))
+                    .map_err(|why| ProfileError::Format(format!("invalid TOML: {}", why)))
+            }
+            #[cfg(feature = "yaml")]
+            Format::Yaml => serde_yaml::to_string(value)
+                .map_err(|why| ProfileError::Format(format!("invalid YAML: {}", why))),
+        }
+    }
+}
+
+#[cfg(feature = "toml")]
+fn strip_nulls(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            object.retain(|_, value| !value.is_null());
+            object.values_mut().for_each(strip_nulls);
+        }
+        Value::Array(array) => array.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+/// Returns the file `{name}.{extension}` in `dir` of the first supported [Format] which exists.
+/// Defaults to JSON if none exists.
+pub fn find_file(dir: &Path, name: &str) -> (PathBuf, Format) {
+    Format::all()
+        .into_iter()
+        .flat_map(|format| {
+            format
+                .extensions()
+                .iter()
+                .map(move |extension| (dir.join(format!("{}.{}", name, extension)), format))
+        })
+        .find(|(path, _)| path.exists())
+        .unwrap_or_else(|| (dir.join(format!("{}.json", name)), Format::Json))
+}
+
+/// Origin of a configuration value.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(String),
+    Cli,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "file '{}'", path.display()),
+            Source::Env(var) => write!(f, "environment variable {}", var),
+            Source::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// Configuration merged from multiple layers. See the [module documentation](self).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    // Layers in ascending precedence
+    layers: Vec<(Source, Map<String, Value>)>,
+}
+
+impl Default for Config {
+    /// Configuration only containing the defaults.
+    fn default() -> Self {
+        let mut defaults = Map::new();
+        insert(
+            &mut defaults,
+            KEY_TWITCH_AUTH,
+            Value::from(DEFAULT_TWITCH_AUTH),
+        );
+        insert(
+            &mut defaults,
+            KEY_TWITCH_REDIRECT_URI,
+            Value::from(DEFAULT_TWITCH_REDIRECT_URI),
+        );
+        insert(
+            &mut defaults,
+            KEY_TWITCH_SCOPES,
+            Value::from(DEFAULT_TWITCH_SCOPES.to_vec()),
+        );
+        Config {
+            layers: vec![(Source::Default, defaults)],
+        }
+    }
+}
+
+impl Config {
+    /// Loads all layers: the defaults, the configuration file in `profile_dir` if given, the
+    /// environment and the command line overrides `args`.
+    pub fn load<I, S>(profile_dir: Option<&Path>, args: I) -> Result<Self, ProfileError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut config = Config::default();
+        if let Some(dir) = profile_dir {
+            let (path, _) = find_file(dir, "config");
+            if path.exists() {
+                config.add_file(&path)?;
+            }
+        }
+        config.add_env();
+        config.add_args(args)?;
+        Ok(config)
+    }
+
+    /// Adds a layer taking precedence over all existing layers. `values` has to be an object.
+    pub fn add_layer(&mut self, source: Source, values: Value) -> Result<(), ProfileError> {
+        match values {
+            Value::Object(values) => {
+                self.layers.push((source, values));
+                Ok(())
+            }
+            _ => Err(ProfileError::Format(format!(
+                "configuration of {} has to be an object",
+                source
+            ))),
+        }
+    }
+
+    /// Adds the configuration file at `path`. The format is determined by the extension.
+    pub fn add_file(&mut self, path: &Path) -> Result<(), ProfileError> {
+        let format = Format::of(path).ok_or_else(|| {
+            ProfileError::Format(format!("unsupported config file: {}", path.display()))
+        })?;
+        let values = format.parse(&read_to_string(path).map_err(ProfileError::from)?)?;
+        self.add_layer(Source::File(path.to_path_buf()), values)
+    }
+
+    /// Adds the set [ENV_VARS]. Each variable is a separate layer.
+    pub fn add_env(&mut self) {
+        for (var, key, format) in ENV_VARS.iter() {
+            let value = match std::env::var(var) {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            let list = || {
+                Value::from(
+                    value
+                        .split(',')
+                        .map(|item| item.trim().to_string())
+                        .filter(|item| !item.is_empty())
+                        .collect::<Vec<_>>(),
+                )
+            };
+            let value = match format {
+                EnvFormat::Text => Value::from(value),
+                EnvFormat::List => list(),
+                EnvFormat::Channels => channel_map(list()),
+                EnvFormat::Json => match serde_json::from_str(&value) {
+                    Ok(value) => value,
+                    Err(why) => {
+                        warn!("ignoring invalid JSON in {}: {}", var, why);
+                        continue;
+                    }
+                },
+            };
+            let mut values = Map::new();
+            insert(&mut values, key, value);
+            self.layers.push((Source::Env(var.to_string()), values));
+        }
+    }
+
+    /// Adds overrides in the format `key=value`. Values are parsed as JSON and used as strings
+    /// if they aren't valid JSON, e.g. `twitch.auth=code` or `channels=["#foo","#bar"]`. Lists
+    /// of channels are stored like [KEY_CHANNELS].
+    pub fn add_args<I, S>(&mut self, args: I) -> Result<(), ProfileError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut values = Map::new();
+        for arg in args {
+            let arg = arg.as_ref();
+            let (key, value) = match arg.find('=') {
+                Some(index) if index > 0 => (&arg[..index], &arg[index + 1..]),
+                _ => {
+                    return Err(ProfileError::Format(format!(
+                        "invalid override '{}': expected key=value",
+                        arg
+                    )))
+                }
+            };
+            let mut value =
+                serde_json::from_str(value).unwrap_or_else(|_| Value::from(value.to_string()));
+            if key == KEY_CHANNELS {
+                value = channel_map(value);
+            }
+            insert(&mut values, key, value);
+        }
+        if !values.is_empty() {
+            self.layers.push((Source::Cli, values));
+        }
+        Ok(())
+    }
+
+    /// Returns the effective value of `key`. Objects are merged across all layers.
+    pub fn get(&self, key: &str) -> Option<Value> {
+        let mut effective: Option<Value> = None;
+        for (_, values) in self.layers.iter() {
+            if let Some(value) = find(values, key) {
+                match effective {
+                    Some(ref mut effective) => merge(effective, value),
+                    None => effective = Some(value.clone()),
+                }
+            }
+        }
+        effective
+    }
+
+    /// Returns the effective value of `key` deserialized as `T`.
+    pub fn get_as<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, ProfileError> {
+        self.get(key)
+            .map(|value| serde_json::from_value(value).map_err(ProfileError::from))
+            .transpose()
+    }
+
+    /// Returns where the effective value of `key` came from.
+    pub fn source(&self, key: &str) -> Option<&Source> {
+        self.lookup(key).map(|(source, _)| source)
+    }
+
+    /// Returns all effective values by their keys.
+    pub fn entries(&self) -> Vec<(String, &Value, &Source)> {
+        let mut keys = Vec::new();
+        for (_, values) in self.layers.iter() {
+            collect_keys(values, "", &mut keys);
+        }
+        keys.sort();
+        keys.dedup();
+        keys.into_iter()
+            // Keys nested in a value replaced by a higher layer aren't effective
+            .filter_map(|key| {
+                let (source, value) = self.lookup(&key)?;
+                match value {
+                    // Only empty objects are values, e.g. channels with default settings
+                    Value::Object(values) if !values.is_empty() => None,
+                    _ => Some((key, value, source)),
+                }
+            })
+            .collect()
+    }
+
+    fn lookup(&self, key: &str) -> Option<(&Source, &Value)> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|(source, values)| Some((source, find(values, key)?)))
+    }
+}
+
+/// Lists all effective values and their sources. Secrets are masked.
+impl Display for Config {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (key, value, source) in self.entries() {
+            let secret = key.split('.').any(|part| SECRET_KEYS.contains(&part));
+            let value = match value.as_str() {
+                _ if !secret || value.is_null() => value.to_string(),
+                Some(value) => mask(value),
+                None => mask(&value.to_string()),
+            };
+            writeln!(f, "{} = {} ({})", key, value, source)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the value at the dot separated `key` in `values`.
+fn find<'a>(values: &'a Map<String, Value>, key: &str) -> Option<&'a Value> {
+    let mut parts = key.split('.');
+    let mut value = values.get(parts.next()?)?;
+    for part in parts {
+        value = value.get(part)?;
+    }
+    Some(value)
+}
+
+/// Merges `value` into `effective`. Values of `value` take precedence.
+fn merge(effective: &mut Value, value: &Value) {
+    match (effective, value) {
+        (Value::Object(effective), Value::Object(values)) => {
+            for (key, value) in values {
+                match effective.get_mut(key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        effective.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (effective, value) => *effective = value.clone(),
+    }
+}
+
+/// Inserts `value` at the dot separated `key` into `values`.
+fn insert(values: &mut Map<String, Value>, key: &str, value: Value) {
+    match key.find('.') {
+        None => {
+            values.insert(key.to_string(), value);
+        }
+        Some(index) => {
+            let nested = values
+                .entry(&key[..index])
+                .or_insert_with(|| Value::Object(Map::new()));
+            if !nested.is_object() {
+                *nested = Value::Object(Map::new());
+            }
+            if let Value::Object(nested) = nested {
+                insert(nested, &key[index + 1..], value);
+            }
+        }
+    }
+}
+
+/// Converts a list of channel names into channels with default settings. Invalid names are
+/// skipped, other values are kept.
+fn channel_map(value: Value) -> Value {
+    match value {
+        Value::Array(names) => Value::Object(
+            names
+                .iter()
+                .filter_map(Value::as_str)
+                .filter_map(normalize)
+                .map(|channel| (channel, Value::Object(Map::new())))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+fn collect_keys(values: &Map<String, Value>, prefix: &str, keys: &mut Vec<String>) {
+    for (key, value) in values {
+        let key = format!("{}{}", prefix, key);
+        if let Value::Object(nested) = value {
+            collect_keys(nested, &format!("{}.", key), keys);
+        }
+        keys.push(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::channels::ChannelSettings;
+    use crate::profile::config::{
+        Config, Source, DEFAULT_TWITCH_AUTH, KEY_CHANNELS, KEY_TWITCH_AUTH, KEY_TWITCH_SCOPES,
+    };
+    use crate::profile::Profile;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_layers() {
+        let mut config = Config::default();
+        assert_eq!(
+            config.get(KEY_TWITCH_AUTH),
+            Some(json!(DEFAULT_TWITCH_AUTH))
+        );
+        assert_eq!(config.source(KEY_TWITCH_AUTH), Some(&Source::Default));
+
+        let file = Source::File(PathBuf::from("config.json"));
+        config
+            .add_layer(
+                file.clone(),
+                json!({ "twitch": { "auth": "code" }, "channels": { "#foo": {} } }),
+            )
+            .unwrap();
+        assert_eq!(config.get(KEY_TWITCH_AUTH), Some(json!("code")));
+        assert_eq!(config.source(KEY_TWITCH_AUTH), Some(&file));
+        // Other values of replaced objects keep their source
+        assert_eq!(config.source(KEY_TWITCH_SCOPES), Some(&Source::Default));
+
+        config
+            .add_args(vec![
+                "twitch.auth=client_credentials",
+                r##"channels=["#Bar"]"##,
+            ])
+            .unwrap();
+        assert_eq!(
+            config.get_as::<String>(KEY_TWITCH_AUTH).unwrap().as_deref(),
+            Some("client_credentials")
+        );
+        // Channels are merged with the channels of lower layers
+        assert_eq!(
+            config.get(KEY_CHANNELS),
+            Some(json!({ "#bar": {}, "#foo": {} }))
+        );
+        assert_eq!(config.source("channels.#bar"), Some(&Source::Cli));
+        assert_eq!(config.source("channels.#foo"), Some(&file));
+        assert!(config.add_args(vec!["=foo"]).is_err());
+        assert!(config.add_args(vec!["foo"]).is_err());
+
+        let entries = config.entries();
+        let keys = entries
+            .iter()
+            .map(|(key, _, _)| key.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                "channels.#bar",
+                "channels.#foo",
+                KEY_TWITCH_AUTH,
+                "twitch.redirect_uri",
+                KEY_TWITCH_SCOPES
+            ]
+        );
+        assert!(config
+            .to_string()
+            .contains("twitch.auth = \"client_credentials\" (command line)"));
+    }
+
+    #[test]
+    fn test_env() {
+        std::env::set_var("BRS_JOINED_CHANNELS", "#foo, #bar");
+        let config = Config::load(None, Vec::<String>::new()).unwrap();
+        std::env::remove_var("BRS_JOINED_CHANNELS");
+        let channels = config
+            .get_as::<BTreeMap<String, ChannelSettings>>(KEY_CHANNELS)
+            .unwrap()
+            .unwrap();
+        assert_eq!(channels.keys().collect::<Vec<_>>(), vec!["#bar", "#foo"]);
+        assert!(channels.values().all(ChannelSettings::is_default));
+        assert_eq!(
+            config.source("channels.#foo"),
+            Some(&Source::Env("BRS_JOINED_CHANNELS".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_profile_keeps_config() {
+        let dir = std::env::temp_dir()
+            .join(format!("botrs-config-{}", std::process::id()))
+            .join("foo");
+        create_dir_all(&dir).unwrap();
+        let mut profile = serde_json::to_value(Profile::empty()).unwrap();
+        profile["twitch"] = json!({ "auth": "code" });
+        profile["client_secret"] = json!({ "Plain": "secret" });
+        write(dir.join("config.json"), profile.to_string()).unwrap();
+
+        // Moving the secrets into the store rewrites the file
+        let profile = Profile::from_path_with_passphrase(&dir, Some("passphrase")).unwrap();
+        let config = Config::load(Some(&dir), Vec::<String>::new()).unwrap();
+        assert_eq!(config.get(KEY_TWITCH_AUTH), Some(json!("code")));
+        // Saving the profile writes unknown values back
+        let saved = serde_json::to_value(&profile).unwrap();
+        assert_eq!(saved["twitch"], json!({ "auth": "code" }));
+        assert_eq!(serde_json::from_value::<Profile>(saved).unwrap(), profile);
+
+        remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_mask() {
+        let mut config = Config::default();
+        config
+            .add_layer(
+                Source::Cli,
+                json!({
+                    "profile": {
+                        "credentials": { "Twitch": { "Plain": "oauth:thisisaverylongtoken" } },
+                        "client_secret": { "Plain": "secret" },
+                    }
+                }),
+            )
+            .unwrap();
+        let display = config.to_string();
+        assert!(display.contains("profile.credentials.Twitch.Plain = ********oken"));
+        assert!(!display.contains("\"secret\""));
+    }
+}
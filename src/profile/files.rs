@@ -0,0 +1,224 @@
+//! Crash-safe writes and advisory locks of profile files.
+//!
+//! Files are written to a temporary file in the same directory first which then replaces the
+//! original, so readers only ever see the old or the new content. Processes editing the same
+//! profile serialize their writes through a [ProfileLock].
+
+use crate::profile::ProfileError;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const LOCK_FILE: &str = ".lock";
+/// Time to wait for a lock held by another process.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+const LOCK_RETRY: Duration = Duration::from_millis(20);
+
+/// Number of temporary files created by [write_atomic]. Keeps concurrent writes of a process
+/// apart.
+static TMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes `contents` to `path` atomically by renaming a fully written temporary file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_atomic_with(path, contents, false)
+}
+
+/// Writes `contents` to `path` like [write_atomic]. On Unix only the owner can read and write
+/// the file (mode `0600`), e.g. for secrets.
+pub fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_atomic_with(path, contents, true)
+}
+
+fn write_atomic_with(path: &Path, contents: &[u8], private: bool) -> io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut tmp_name = name.to_os_string();
+    tmp_name.push(format!(
+        ".tmp-{}-{}",
+        std::process::id(),
+        TMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp = dir.join(tmp_name);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        if private {
+            options.mode(0o600);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    let result = options
+        .open(&tmp)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return result;
+    }
+    // Persist the rename itself. Directories can't be opened as files on all platforms.
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Advisory lock of a profile directory held until dropped.
+///
+/// Locks are meant to be held shortly, e.g. while loading, modifying and saving a profile.
+/// The lock is taken on the lock file through the OS (`flock` on Unix, an exclusively opened
+/// file on Windows), so it is released even if its process crashed. The lock file itself
+/// stays in the profile directory.
+#[derive(Debug)]
+pub struct ProfileLock {
+    path: PathBuf,
+    // Holds the lock until closed
+    _file: File,
+}
+
+impl ProfileLock {
+    /// Locks the profile directory `dir`. Waits up to 5 seconds for other processes to release
+    /// their lock.
+    pub fn acquire(dir: &Path) -> Result<Self, ProfileError> {
+        Self::acquire_timeout(dir, LOCK_TIMEOUT)
+    }
+
+    pub fn acquire_timeout(dir: &Path, timeout: Duration) -> Result<Self, ProfileError> {
+        let path = dir.join(LOCK_FILE);
+        let deadline = Instant::now() + timeout;
+        loop {
+            match try_lock(&path).map_err(ProfileError::IO)? {
+                Some(mut file) => {
+                    // The owner is only informational
+                    let _ = file.set_len(0);
+                    let _ = write!(file, "{}", std::process::id());
+                    return Ok(ProfileLock { path, _file: file });
+                }
+                None if Instant::now() >= deadline => {
+                    return Err(ProfileError::Locked(dir.to_path_buf()));
+                }
+                None => thread::sleep(LOCK_RETRY),
+            }
+        }
+    }
+
+    /// Returns the path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Opens and locks the lock file at `path`. Returns `None` if another process holds the lock.
+#[cfg(unix)]
+fn try_lock(path: &Path) -> io::Result<Option<File>> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(Some(file));
+    }
+    let why = io::Error::last_os_error();
+    if why.kind() == io::ErrorKind::WouldBlock {
+        Ok(None)
+    } else {
+        Err(why)
+    }
+}
+
+/// Opens the lock file at `path` exclusively. Returns `None` if another process holds the lock.
+#[cfg(windows)]
+fn try_lock(path: &Path) -> io::Result<Option<File>> {
+    use std::os::windows::fs::OpenOptionsExt;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+
+    match OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .share_mode(0)
+        .open(path)
+    {
+        Ok(file) => Ok(Some(file)),
+        Err(why) if why.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => Ok(None),
+        Err(why) => Err(why),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::files::{write_atomic, ProfileLock, LOCK_FILE};
+    use crate::profile::ProfileError;
+    use std::fs::{create_dir_all, read_dir, read_to_string, remove_dir_all, write};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = std::env::temp_dir().join(format!("botrs-atomic-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(read_to_string(&path).unwrap(), "second");
+
+        // Concurrent writes of a process don't share their temporary files
+        let path = Arc::new(path);
+        let writers = (0..8)
+            .map(|i| {
+                let path = Arc::clone(&path);
+                thread::spawn(move || write_atomic(&path, i.to_string().as_bytes()))
+            })
+            .collect::<Vec<_>>();
+        for writer in writers {
+            writer.join().unwrap().unwrap();
+        }
+        assert_eq!(read_to_string(&*path).unwrap().len(), 1);
+        // No temporary files are left over
+        assert_eq!(read_dir(&dir).unwrap().count(), 1);
+
+        #[cfg(unix)]
+        {
+            use crate::profile::files::write_private;
+            use std::os::unix::fs::PermissionsExt;
+
+            let secrets = dir.join("secrets.json");
+            write_private(&secrets, b"secret").unwrap();
+            let mode = std::fs::metadata(&secrets).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lock() {
+        let dir = std::env::temp_dir().join(format!("botrs-lock-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let lock = ProfileLock::acquire(&dir).unwrap();
+        assert!(matches!(
+            ProfileLock::acquire_timeout(&dir, Duration::from_millis(50)),
+            Err(ProfileError::Locked(_))
+        ));
+        drop(lock);
+        ProfileLock::acquire_timeout(&dir, Duration::from_millis(50)).unwrap();
+
+        // Lock files left over by crashed processes don't hold the lock
+        write(dir.join(LOCK_FILE), "1").unwrap();
+        ProfileLock::acquire_timeout(&dir, Duration::from_millis(50)).unwrap();
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+//! Migrations of profile configuration files written by older versions of Bot-RS.
+//!
+//! Every configuration file stores the `version` of its format. Files without version predate
+//! versioning and are version `0`. Loading a profile applies the migrations from its version up
+//! to [CURRENT_VERSION] in order.
+
+use crate::auth::Credentials;
+use crate::profile::channels::normalize;
+use crate::profile::ProfileError;
+use crate::secrets::SecretRef;
+use serde::de::Error;
+use serde_json::{Error as JsonError, Map, Value};
+
+/// Version of the configuration files written by this version of Bot-RS.
+pub const CURRENT_VERSION: u32 = 3;
+
+/// Replacement of secrets removed by [redact_secrets].
+const REDACTED: &str = "********";
+
+type Migration = fn(&mut Map<String, Value>);
+
+/// Migration at index `n` upgrades a configuration from version `n` to `n + 1`.
+const MIGRATIONS: [Migration; CURRENT_VERSION as usize] = [v0_to_v1, v1_to_v2, v2_to_v3];
+
+/// Returns the version of the configuration `config`.
+pub fn version(config: &Value) -> Result<u32, ProfileError> {
+    match config.get("version") {
+        None => Ok(0),
+        Some(version) => version
+            .as_u64()
+            .filter(|version| *version <= u64::from(u32::MAX))
+            .map(|version| version as u32)
+            .ok_or_else(|| {
+                ProfileError::Json(JsonError::custom(format!(
+                    "invalid profile version: {}",
+                    version
+                )))
+            }),
+    }
+}
+
+/// Replaces secrets kept in plaintext by configurations of any version, e.g. to keep them out of
+/// backups. References into a secret store are kept. Returns if any secret was replaced.
+pub fn redact_secrets(config: &mut Value) -> bool {
+    fn is_stored(secret: &Value) -> bool {
+        secret.is_null() || secret.get("Store").is_some()
+    }
+
+    let mut redacted = false;
+    if let Some(Value::Object(credentials)) = config.get_mut("credentials") {
+        for credentials in credentials.values_mut() {
+            if !is_stored(credentials) {
+                *credentials = Value::from(REDACTED);
+                redacted = true;
+            }
+        }
+    }
+    if let Some(secret) = config.get_mut("client_secret") {
+        if !is_stored(secret) {
+            *secret = Value::from(REDACTED);
+            redacted = true;
+        }
+    }
+    redacted
+}
+
+/// Returns if the configuration `config` of the [CURRENT_VERSION] keeps secrets in plaintext
+/// (see [SecretRef::Plain]).
+pub fn has_plain_secrets(config: &Value) -> bool {
+    let credentials = config
+        .get("credentials")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flat_map(|credentials| credentials.values());
+    credentials
+        .chain(config.get("client_secret"))
+        .any(|secret| secret.get("Plain").is_some())
+}
+
+/// Upgrades `config` to [CURRENT_VERSION]. Returns the version of `config` before the
+/// migration.
+pub fn migrate(config: &mut Value) -> Result<u32, ProfileError> {
+    let version = version(config)?;
+    if version > CURRENT_VERSION {
+        return Err(ProfileError::UnsupportedVersion {
+            found: version,
+            supported: CURRENT_VERSION,
+        });
+    }
+    if version == CURRENT_VERSION {
+        return Ok(version);
+    }
+    let object = config.as_object_mut().ok_or_else(|| {
+        ProfileError::Json(JsonError::custom("profile config has to be an object"))
+    })?;
+    for migration in MIGRATIONS[version as usize..].iter() {
+        migration(object);
+    }
+    object.insert("version".to_string(), Value::from(CURRENT_VERSION));
+    Ok(version)
+}
+
+/// Adds the fields which unversioned configurations may lack.
+fn v0_to_v1(config: &mut Map<String, Value>) {
+    config
+        .entry("credentials")
+        .or_insert_with(|| Value::Object(Map::new()));
+    config.entry("client_secret").or_insert(Value::Null);
+    config
+        .entry("rights")
+        .or_insert_with(|| serde_json::json!({ "filters": [] }));
+}
+
+/// Replaces plaintext credentials and client secret with [SecretRef::Plain] until they're
+/// moved into a secret store.
+fn v1_to_v2(config: &mut Map<String, Value>) {
+    fn plain(secret: String) -> Value {
+        serde_json::to_value(SecretRef::Plain(secret)).expect("failed to serialize secret")
+    }
+
+    if let Some(Value::Object(credentials)) = config.get_mut("credentials") {
+        for credentials in credentials.values_mut() {
+            if let Ok(parsed) = serde_json::from_value::<Credentials>(credentials.clone()) {
+                *credentials = plain(parsed.to_string());
+            }
+        }
+    }
+    if let Some(Value::String(secret)) = config.get("client_secret") {
+        let secret = plain(secret.clone());
+        config.insert("client_secret".to_string(), secret);
+    }
+}
+
+/// Replaces the list of channels with the settings of every channel. Names are normalized and
+/// duplicates removed.
+fn v2_to_v3(config: &mut Map<String, Value>) {
+    let list = match config.get("channels") {
+        Some(Value::Array(list)) => list.clone(),
+        Some(_) => return,
+        None => Vec::new(),
+    };
+    let channels = list
+        .iter()
+        .filter_map(Value::as_str)
+        .filter_map(normalize)
+        .map(|channel| (channel, Value::Object(Map::new())))
+        .collect();
+    config.insert("channels".to_string(), Value::Object(channels));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::{Credentials, Platform};
+    use crate::profile::migration::{migrate, CURRENT_VERSION};
+    use crate::profile::{Profile, ProfileError};
+    use crate::secrets::{EncryptedFile, EnvSecrets};
+    use serde_json::json;
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+
+    #[test]
+    fn test_migrate() {
+        let mut config = json!({
+            "name": "foo",
+            "channels": ["#foo"],
+            "client_id": "id",
+        });
+        assert_eq!(migrate(&mut config).unwrap(), 0);
+        assert_eq!(config["version"], json!(CURRENT_VERSION));
+        assert_eq!(config["credentials"], json!({}));
+        assert_eq!(config["rights"], json!({ "filters": [] }));
+        assert_eq!(config["client_secret"], json!(null));
+        let profile: Profile = serde_json::from_value(config.clone()).unwrap();
+        assert_eq!(profile.get_channels(), vec!["#foo".to_string()]);
+
+        // Already migrated configs are unchanged
+        let migrated = config.clone();
+        assert_eq!(migrate(&mut config).unwrap(), CURRENT_VERSION);
+        assert_eq!(config, migrated);
+
+        match migrate(&mut json!({ "version": CURRENT_VERSION + 1 })) {
+            Err(ProfileError::UnsupportedVersion { found, supported }) => {
+                assert_eq!(found, CURRENT_VERSION + 1);
+                assert_eq!(supported, CURRENT_VERSION);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(migrate(&mut json!({ "version": "1" })).is_err());
+    }
+
+    #[test]
+    fn test_plaintext_secrets() {
+        let mut config = json!({
+            "version": 1,
+            "name": "foo",
+            "channels": [],
+            "credentials": { "Twitch": { "OAuthToken": { "token": "thisisatoken" } } },
+            "client_id": "id",
+            "client_secret": "secret",
+            "rights": { "filters": [] },
+        });
+        assert_eq!(migrate(&mut config).unwrap(), 1);
+        assert_eq!(
+            config["credentials"],
+            json!({ "Twitch": { "Plain": "oauth:thisisatoken" } })
+        );
+        assert_eq!(config["client_secret"], json!({ "Plain": "secret" }));
+        let profile: Profile = serde_json::from_value(config).unwrap();
+        let store = EnvSecrets::default();
+        assert_eq!(
+            profile.get_credentials(&Platform::Twitch, &store).unwrap(),
+            Some(Credentials::OAuthToken {
+                token: "thisisatoken".to_string()
+            })
+        );
+        assert_eq!(
+            profile.get_client_secret(&store).unwrap().as_deref(),
+            Some("secret")
+        );
+    }
+
+    #[test]
+    fn test_channel_settings() {
+        let mut config = json!({
+            "version": 2,
+            "name": "foo",
+            "channels": ["#Foo", "foo", "bar", "#"],
+            "credentials": {},
+            "client_id": "id",
+            "client_secret": null,
+            "rights": { "filters": [] },
+        });
+        assert_eq!(migrate(&mut config).unwrap(), 2);
+        assert_eq!(config["channels"], json!({ "#bar": {}, "#foo": {} }));
+        let profile: Profile = serde_json::from_value(config).unwrap();
+        assert!(profile.channel("#foo").unwrap().is_default());
+    }
+
+    #[test]
+    fn test_from_path() {
+        let dir = std::env::temp_dir()
+            .join(format!("botrs-migration-{}", std::process::id()))
+            .join("foo");
+        create_dir_all(&dir).unwrap();
+        let original = r#"{"name":"foo","channels":[],"credentials":{},"client_id":"id","client_secret":null,"rights":{"filters":[]}}"#;
+        write(dir.join("config.json"), original).unwrap();
+
+        let profile = Profile::from_path(&dir).unwrap();
+        assert_eq!(profile.get_client_id(), "id");
+        assert_eq!(
+            read_to_string(dir.join("config.json.v0.bak")).unwrap(),
+            original
+        );
+        let migrated: serde_json::Value =
+            serde_json::from_str(&read_to_string(dir.join("config.json")).unwrap()).unwrap();
+        assert_eq!(migrated["version"], json!(CURRENT_VERSION));
+        assert_eq!(Profile::from_path(&dir).unwrap(), profile);
+
+        write(
+            dir.join("config.json"),
+            format!(r#"{{"version":{}}}"#, CURRENT_VERSION + 1),
+        )
+        .unwrap();
+        assert!(matches!(
+            Profile::from_path(&dir),
+            Err(ProfileError::UnsupportedVersion { .. })
+        ));
+
+        remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_store_migrated_secrets() {
+        let dir = std::env::temp_dir()
+            .join(format!("botrs-migration-secrets-{}", std::process::id()))
+            .join("foo");
+        create_dir_all(&dir).unwrap();
+        let original = r#"{"version":1,"name":"foo","channels":[],"credentials":{"Twitch":{"OAuthToken":{"token":"thisisatoken"}}},"client_id":"id","client_secret":"secret","rights":{"filters":[]}}"#;
+        write(dir.join("config.json"), original).unwrap();
+
+        // Secrets stay in plaintext without a passphrase
+        let profile = Profile::from_path_with_passphrase(&dir, None).unwrap();
+        assert!(profile.has_plain_secrets());
+        // The backup doesn't keep any plaintext secret
+        let backup = dir.join("config.json.v1.bak");
+        let content = read_to_string(&backup).unwrap();
+        assert!(!content.contains("thisisatoken") && !content.contains(r#""secret""#));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&backup).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // Secrets of the already migrated profile are moved once a passphrase is given
+        let profile = Profile::from_path_with_passphrase(&dir, Some("passphrase")).unwrap();
+        assert!(!profile.has_plain_secrets());
+        let config = read_to_string(dir.join("config.json")).unwrap();
+        assert!(!config.contains("Plain") && !config.contains("thisisatoken"));
+        let store = EncryptedFile::open(dir.join("secrets.json"), "passphrase").unwrap();
+        assert_eq!(
+            profile.get_credentials(&Platform::Twitch, &store).unwrap(),
+            Some(Credentials::OAuthToken {
+                token: "thisisatoken".to_string()
+            })
+        );
+        assert_eq!(
+            profile.get_client_secret(&store).unwrap().as_deref(),
+            Some("secret")
+        );
+
+        remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,732 @@
+pub mod bundle;
+pub mod channels;
+pub mod config;
+pub mod files;
+pub mod migration;
+pub mod watch;
+
+use crate::auth::{Credentials, Platform};
+use crate::command_access::AccessRights;
+use crate::cooldown::Cooldowns;
+use crate::profile::channels::ChannelSettings;
+use crate::profile::config::{find_file, Config, Format};
+use crate::profile::files::{write_atomic, write_private, ProfileLock};
+use crate::secrets::{EncryptedFile, SecretError, SecretRef, SecretStore, ENV_SECRETS_PASSPHRASE};
+use core::fmt;
+use dirs_next::config_dir;
+use serde::de::DeserializeOwned;
+use serde::export::fmt::Display;
+use serde::export::Formatter;
+use serde::Serialize;
+use serde_json::{Error as JsonError, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs::{create_dir_all, read_dir, read_to_string, remove_dir_all, DirEntry};
+use std::path::{Path, PathBuf};
+use std::{error, io};
+
+const SECRETS_FILE: &str = "secrets.json";
+
+#[derive(Debug)]
+pub enum ProfileError {
+    AlreadyExists(OsString),
+    /// The profile directory is locked by another process. See [ProfileLock].
+    Locked(PathBuf),
+    /// Invalid TOML, YAML or configuration override.
+    Format(String),
+    /// Malformed bundle or checksum mismatch. See [bundle].
+    InvalidBundle(String),
+    /// The configuration section of a plugin doesn't match the type of the plugin.
+    PluginConfig {
+        plugin: String,
+        reason: String,
+    },
+    IO(io::Error),
+    Json(JsonError),
+    /// The profile was written by a newer version of Bot-RS.
+    UnsupportedVersion {
+        found: u32,
+        supported: u32,
+    },
+    /// Plaintext secrets couldn't be moved into the secret store while migrating.
+    Secrets(SecretError),
+}
+
+impl From<io::Error> for ProfileError {
+    fn from(content: io::Error) -> Self {
+        ProfileError::IO(content)
+    }
+}
+
+impl From<JsonError> for ProfileError {
+    fn from(content: JsonError) -> Self {
+        ProfileError::Json(content)
+    }
+}
+
+impl From<SecretError> for ProfileError {
+    fn from(content: SecretError) -> Self {
+        ProfileError::Secrets(content)
+    }
+}
+
+impl error::Error for ProfileError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ProfileError::IO(source) => Some(source),
+            ProfileError::Json(source) => Some(source),
+            ProfileError::Secrets(source) => Some(source),
+            ProfileError::AlreadyExists(_)
+            | ProfileError::Locked(_)
+            | ProfileError::Format(_)
+            | ProfileError::InvalidBundle(_)
+            | ProfileError::PluginConfig { .. }
+            | ProfileError::UnsupportedVersion { .. } => None,
+        }
+    }
+}
+
+impl Display for ProfileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::IO(why) => write!(f, "failed to read/write config file '{}'", why),
+            ProfileError::Json(why) => write!(f, "invalid config file: {}", why),
+            ProfileError::AlreadyExists(name) => write!(
+                f,
+                "profile named '{}' already exists",
+                name.to_str().unwrap()
+            ),
+            ProfileError::Format(why) => write!(f, "invalid config: {}", why),
+            ProfileError::InvalidBundle(why) => write!(f, "invalid profile bundle: {}", why),
+            ProfileError::PluginConfig { plugin, reason } => {
+                write!(f, "invalid config of plugin '{}': {}", plugin, reason)
+            }
+            ProfileError::Locked(path) => {
+                write!(
+                    f,
+                    "profile at '{}' is locked by another process",
+                    path.display()
+                )
+            }
+            ProfileError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "profile version {} is newer than the supported version {}",
+                found, supported
+            ),
+            ProfileError::Secrets(why) => write!(f, "failed to store secrets: {}", why),
+        }
+    }
+}
+
+/// Profile configuration containing configurations for the Bot-RS Core functionality.
+///
+/// Profile configurations are located at `{{ENV_CONFIG_DIR}}/profiles/{profile-name}`.
+///
+/// A Profile is only allowed to join the channel its named after.
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct Profile {
+    /// Version of the configuration file format. See [migration].
+    #[serde(default)]
+    version: u32,
+    name: String,
+    /// Channels to join keyed by their normalized names. See [channels].
+    #[serde(deserialize_with = "channels::deserialize")]
+    channels: BTreeMap<String, ChannelSettings>,
+    credentials: HashMap<Platform, SecretRef>,
+    client_id: String,
+    client_secret: Option<SecretRef>,
+    rights: AccessRights,
+    #[serde(default)]
+    cooldowns: Cooldowns,
+    /// Configuration sections of the plugins keyed by [crate::plugin::PluginInfo::name].
+    #[serde(default)]
+    plugins: BTreeMap<String, Value>,
+    /// Values of the configuration file not used by the profile itself, e.g. the ones of the
+    /// layered [Config]. Written back when saving the profile.
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+    /// Format of the configuration file.
+    #[serde(skip)]
+    format: Format,
+}
+
+impl Profile {
+    pub fn empty() -> Self {
+        Self::new(
+            String::new(),
+            Vec::new(),
+            String::new(),
+            AccessRights::empty(),
+            None,
+        )
+    }
+
+    pub fn new(
+        name: String,
+        channels: Vec<String>,
+        client_id: String,
+        rights: AccessRights,
+        client_secret: Option<SecretRef>,
+    ) -> Self {
+        let mut profile = Profile {
+            version: migration::CURRENT_VERSION,
+            name,
+            channels: BTreeMap::new(),
+            client_id,
+            client_secret,
+            credentials: HashMap::new(),
+            rights,
+            cooldowns: Cooldowns::default(),
+            plugins: BTreeMap::new(),
+            extra: Map::new(),
+            format: Format::default(),
+        };
+        profile.add_channels(channels);
+        profile
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the normalized names of the channels.
+    pub fn get_channels(&self) -> Vec<String> {
+        self.channels.keys().cloned().collect()
+    }
+
+    /// Returns the channels and their settings.
+    pub fn channels(&self) -> &BTreeMap<String, ChannelSettings> {
+        &self.channels
+    }
+
+    /// Returns the settings of `channel`. The name is normalized, e.g. `Foo` returns the
+    /// settings of `#foo`.
+    pub fn channel(&self, channel: &str) -> Option<&ChannelSettings> {
+        channels::normalize(channel).and_then(|name| self.channels.get(&name))
+    }
+
+    /// Adds or replaces `channel` with its settings. Empty names are ignored.
+    pub fn set_channel(&mut self, channel: &str, settings: ChannelSettings) {
+        if let Some(name) = channels::normalize(channel) {
+            self.channels.insert(name, settings);
+        }
+    }
+
+    pub fn remove_channel(&mut self, channel: &str) -> Option<ChannelSettings> {
+        channels::normalize(channel).and_then(|name| self.channels.remove(&name))
+    }
+
+    /// Returns the access rights of `channel` or the ones of the profile if the channel doesn't
+    /// override them.
+    pub fn rights_for(&self, channel: &str) -> &AccessRights {
+        self.channel(channel)
+            .and_then(|settings| settings.rights.as_ref())
+            .unwrap_or(&self.rights)
+    }
+
+    pub fn get_client_id(&self) -> String {
+        self.client_id.clone()
+    }
+
+    /// Returns the client secret resolved through `store`.
+    pub fn get_client_secret(
+        &self,
+        store: &dyn SecretStore,
+    ) -> Result<Option<String>, SecretError> {
+        self.client_secret
+            .as_ref()
+            .map(|secret| secret.resolve(store))
+            .transpose()
+    }
+
+    /// Stores the client secret in `store`.
+    pub fn set_client_secret(
+        &mut self,
+        secret: String,
+        store: &mut dyn SecretStore,
+    ) -> Result<(), SecretError> {
+        let key = self.secret_key("client_secret");
+        store.set(&key, secret)?;
+        self.client_secret = Some(SecretRef::Store(key));
+        Ok(())
+    }
+
+    /// Returns the configuration section of `plugin` deserialized into `T` or `None` if the
+    /// profile has no section for the plugin.
+    pub fn plugin_config<T: DeserializeOwned>(
+        &self,
+        plugin: &str,
+    ) -> Result<Option<T>, ProfileError> {
+        self.plugins
+            .get(plugin)
+            .map(|config| {
+                T::deserialize(config).map_err(|why| ProfileError::PluginConfig {
+                    plugin: plugin.to_string(),
+                    reason: why.to_string(),
+                })
+            })
+            .transpose()
+    }
+
+    /// Returns the raw configuration section of `plugin`.
+    pub fn plugin_config_value(&self, plugin: &str) -> Option<&Value> {
+        self.plugins.get(plugin)
+    }
+
+    /// Replaces the configuration section of `plugin`. `config` has to serialize to a map to be
+    /// representable in every [Format].
+    pub fn set_plugin_config<T: Serialize>(
+        &mut self,
+        plugin: &str,
+        config: &T,
+    ) -> Result<(), ProfileError> {
+        let value = serde_json::to_value(config).map_err(|why| ProfileError::PluginConfig {
+            plugin: plugin.to_string(),
+            reason: why.to_string(),
+        })?;
+        Self::check_plugin_config(plugin, &value)?;
+        self.plugins.insert(plugin.to_string(), value);
+        Ok(())
+    }
+
+    /// Removes the configuration section of `plugin` and returns it.
+    pub fn remove_plugin_config(&mut self, plugin: &str) -> Option<Value> {
+        self.plugins.remove(plugin)
+    }
+
+    fn check_plugin_config(plugin: &str, config: &Value) -> Result<(), ProfileError> {
+        if config.is_object() {
+            Ok(())
+        } else {
+            Err(ProfileError::PluginConfig {
+                plugin: plugin.to_string(),
+                reason: "expected a map".to_string(),
+            })
+        }
+    }
+
+    /// Adds `channels` with default settings. Names are normalized and channels which were
+    /// already added keep their settings.
+    pub fn add_channels(&mut self, channels: Vec<String>) {
+        for channel in channels
+            .iter()
+            .filter_map(|channel| channels::normalize(channel))
+        {
+            self.channels.entry(channel).or_default();
+        }
+    }
+
+    pub fn from_dir(dir: &DirEntry) -> Result<Self, ProfileError> {
+        Self::from_path(&dir.path())
+    }
+
+    /// Loads the profile stored in the directory `path`. The configuration file can be any
+    /// supported [Format], e.g. `config.json`.
+    ///
+    /// Configurations of older versions are migrated (see [migration]) and written back. The
+    /// original configuration is kept as `config.{extension}.v{version}.bak` without its
+    /// plaintext secrets. Plaintext secrets are moved into the secret store if
+    /// `BRS_SECRETS_PASSPHRASE` is set (see [Profile::from_path_with_passphrase]).
+    pub fn from_path(path: &Path) -> Result<Self, ProfileError> {
+        let passphrase = std::env::var(ENV_SECRETS_PASSPHRASE).ok();
+        Self::from_path_with_passphrase(path, passphrase.as_deref())
+    }
+
+    /// Loads the profile stored in the directory `path` like [Profile::from_path]. Plaintext
+    /// secrets are moved into the [EncryptedFile] of the profile opened with `passphrase`. They
+    /// stay in the configuration until the profile is loaded with a passphrase.
+    pub fn from_path_with_passphrase(
+        path: &Path,
+        passphrase: Option<&str>,
+    ) -> Result<Self, ProfileError> {
+        // Load config file of profile
+        let (cfg_file, format) = find_file(path, "config");
+        let read = || -> Result<(String, serde_json::Value), ProfileError> {
+            let content = read_to_string(&cfg_file).map_err(ProfileError::from)?;
+            let config = format.parse(&content)?;
+            Ok((content, config))
+        };
+        let (mut content, mut config) = read()?;
+        let mut _lock = None;
+        if migration::version(&config)? < migration::CURRENT_VERSION
+            || (passphrase.is_some() && migration::has_plain_secrets(&config))
+        {
+            _lock = Some(ProfileLock::acquire(path)?);
+            // Another process may have changed the profile in the meantime
+            let (locked_content, locked_config) = read()?;
+            content = locked_content;
+            config = locked_config;
+        }
+        let version = migration::migrate(&mut config)?;
+        let mut profile: Profile =
+            serde_json::from_value(config.clone()).map_err(ProfileError::from)?;
+        profile.name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .expect("failed to create string from profile dir name")
+            .to_string();
+        profile.format = format;
+        for (plugin, config) in profile.plugins.iter() {
+            Self::check_plugin_config(plugin, config)?;
+        }
+
+        let migrated = version != migration::CURRENT_VERSION;
+        if migrated {
+            let backup = path.join(format!("config.{}.v{}.bak", format.extension(), version));
+            // Keep the backup of the first migration
+            if !backup.exists() {
+                let mut original = format.parse(&content)?;
+                if migration::redact_secrets(&mut original) {
+                    content = format.serialize(&original)?;
+                }
+                write_private(&backup, content.as_bytes()).map_err(ProfileError::from)?;
+            }
+        }
+        let mut stored = false;
+        match passphrase {
+            Some(passphrase) if profile.has_plain_secrets() => {
+                let mut store = EncryptedFile::open(path.join(SECRETS_FILE), passphrase)?;
+                stored = profile.store_secrets(&mut store)?;
+                // Only the secrets change, other values of the file are kept as they are
+                config["credentials"] =
+                    serde_json::to_value(&profile.credentials).map_err(ProfileError::from)?;
+                config["client_secret"] =
+                    serde_json::to_value(&profile.client_secret).map_err(ProfileError::from)?;
+            }
+            None if profile.has_plain_secrets() => warn!(
+                "profile '{}' keeps plaintext secrets until loaded with {} set",
+                profile.name, ENV_SECRETS_PASSPHRASE
+            ),
+            _ => (),
+        }
+        if migrated || stored {
+            let serialized = format.serialize(&config)?;
+            write_atomic(&cfg_file, serialized.as_bytes()).map_err(ProfileError::from)?;
+        }
+        if migrated {
+            info!(
+                "migrated profile '{}' from version {} to {}",
+                profile.name,
+                version,
+                migration::CURRENT_VERSION
+            );
+        }
+        if stored {
+            info!(
+                "moved plaintext secrets of profile '{}' into the secret store",
+                profile.name
+            );
+        }
+
+        Ok(profile)
+    }
+
+    /// Returns the effective [Config] of the profile overridden by the environment and `args`.
+    /// The overrides don't apply to the profile itself, e.g. to its channels or access rights.
+    pub fn config<I, S>(&self, args: I) -> Result<Config, ProfileError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Config::load(Some(&self.path()), args)
+    }
+
+    pub fn profile_dir(name: OsString) -> PathBuf {
+        Profiles::profiles_dir().join(name)
+    }
+
+    /// Sets the given credentials for the platform. Overwrites existing credentials for the platform.
+    ///
+    /// The credentials are kept in `store`, the profile only references them.
+    pub fn set_credentials(
+        &mut self,
+        platform: Platform,
+        creds: Credentials,
+        store: &mut dyn SecretStore,
+    ) -> Result<(), SecretError> {
+        let key = self.secret_key(&format!("{:?}", platform).to_lowercase());
+        store.set(&key, creds.to_string())?;
+        self.credentials.insert(platform, SecretRef::Store(key));
+        Ok(())
+    }
+
+    /// Returns the credentials for the platform resolved through `store`.
+    pub fn get_credentials(
+        &self,
+        platform: &Platform,
+        store: &dyn SecretStore,
+    ) -> Result<Option<Credentials>, SecretError> {
+        match self.credentials.get(platform) {
+            None => Ok(None),
+            Some(secret) => secret.resolve(store)?.parse().map(Some).map_err(|_| {
+                SecretError::InvalidFormat(format!("invalid credentials: {}", secret))
+            }),
+        }
+    }
+
+    /// Moves secrets still kept in plaintext (see [SecretRef::Plain]) into `store`. Returns if
+    /// any secret was moved. The profile has to be saved afterwards.
+    pub fn store_secrets(&mut self, store: &mut dyn SecretStore) -> Result<bool, SecretError> {
+        let mut moved = false;
+        let platforms = self.credentials.keys().cloned().collect::<Vec<_>>();
+        for platform in platforms {
+            if let Some(SecretRef::Plain(creds)) = self.credentials.get(&platform) {
+                let key = self.secret_key(&format!("{:?}", platform).to_lowercase());
+                store.set(&key, creds.clone())?;
+                self.credentials.insert(platform, SecretRef::Store(key));
+                moved = true;
+            }
+        }
+        if let Some(SecretRef::Plain(secret)) = &self.client_secret {
+            let key = self.secret_key("client_secret");
+            store.set(&key, secret.clone())?;
+            self.client_secret = Some(SecretRef::Store(key));
+            moved = true;
+        }
+        Ok(moved)
+    }
+
+    /// Returns if any secret is still kept in plaintext (see [SecretRef::Plain]).
+    pub fn has_plain_secrets(&self) -> bool {
+        self.credentials
+            .values()
+            .chain(self.client_secret.iter())
+            .any(|secret| matches!(secret, SecretRef::Plain(_)))
+    }
+
+    /// Opens the secret store of the profile, the [EncryptedFile] at [Profile::secrets_path]
+    /// with the passphrase set as `BRS_SECRETS_PASSPHRASE`. Fails with
+    /// [SecretError::NoPassphrase] if the passphrase isn't set. Secrets can be read from the
+    /// environment through [crate::secrets::EnvSecrets] instead.
+    pub fn secrets(&self) -> Result<Box<dyn SecretStore>, SecretError> {
+        match std::env::var(ENV_SECRETS_PASSPHRASE) {
+            Ok(passphrase) => Ok(Box::new(EncryptedFile::open(
+                self.secrets_path(),
+                &passphrase,
+            )?)),
+            Err(_) => Err(SecretError::NoPassphrase),
+        }
+    }
+
+    pub fn secrets_path(&self) -> PathBuf {
+        self.path().join(SECRETS_FILE)
+    }
+
+    fn secret_key(&self, name: &str) -> String {
+        format!("{}/{}", self.name, name)
+    }
+
+    pub fn rights(&self) -> &AccessRights {
+        &self.rights
+    }
+
+    /// Returns the cooldowns of the commands. See [crate::cooldown].
+    pub fn cooldowns(&self) -> &Cooldowns {
+        &self.cooldowns
+    }
+
+    pub fn set_cooldowns(&mut self, cooldowns: Cooldowns) {
+        self.cooldowns = cooldowns;
+    }
+
+    pub fn path(&self) -> PathBuf {
+        Profiles::profiles_dir().join(&self.name)
+    }
+
+    pub fn plugins_path(&self) -> PathBuf {
+        self.path().join("plugins")
+    }
+
+    /// Locks the profile directory against concurrent modifications by other processes. Hold
+    /// the lock while loading, modifying and saving the profile with [Profile::save_locked] to
+    /// not lose their changes.
+    pub fn lock(&self) -> Result<ProfileLock, ProfileError> {
+        let path = self.path();
+        create_dir_all(&path).map_err(ProfileError::from)?;
+        ProfileLock::acquire(&path)
+    }
+
+    /// Saves the profile. Waits for other processes to finish saving the same profile.
+    pub fn save(&self) -> Result<(), ProfileError> {
+        let lock = self.lock()?;
+        self.save_locked(&lock)
+    }
+
+    /// Saves the profile while already holding its `lock`.
+    pub fn save_locked(&self, lock: &ProfileLock) -> Result<(), ProfileError> {
+        let path = self.path();
+        debug_assert!(lock.path().starts_with(&path), "lock of another profile");
+        let serialized = self.format.serialize(self)?;
+        create_dir_all(&path).map_err(ProfileError::from)?;
+        let cfg_file = path.join(format!("config.{}", self.format.extension()));
+        write_atomic(&cfg_file, serialized.as_bytes()).map_err(ProfileError::from)?;
+        create_dir_all(self.plugins_path()).map_err(ProfileError::from)?;
+        Ok(())
+    }
+
+    pub fn delete(&self) -> Result<(), ProfileError> {
+        remove_dir_all(self.path()).map_err(ProfileError::IO)
+    }
+
+    /// Exports the saved profile including its plugins to the bundle at `archive`.
+    pub fn export(&self, archive: &Path, secrets: bundle::Secrets) -> Result<(), ProfileError> {
+        bundle::export(&self.path(), archive, secrets)
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Location:\t{}",
+            Self::profile_dir(OsString::from(&self.name)).display()
+        )?;
+        writeln!(f, "Name:\t\t{}", self.name)?;
+        if self.credentials.is_empty() {
+            write!(f, "Credentials:\tNone")?;
+        } else {
+            writeln!(f, "Credentials:")?;
+            for (platform, creds) in self.credentials.iter() {
+                writeln!(f, "\t{:?}: {}", platform, creds)?;
+            }
+        }
+        writeln!(f, "Channels:\t{}", self.get_channels().join(", "))?;
+        for (channel, settings) in self.channels.iter() {
+            if !settings.is_default() {
+                writeln!(f, "\t{}: {}", channel, settings)?;
+            }
+        }
+        if self.rights.is_empty() {
+            writeln!(f, "Access Rights:\tOnly Broadcaster")?;
+        } else {
+            writeln!(f, "Access Rights:")?;
+            for filter in self.rights.iter() {
+                writeln!(f, "\t{}", filter)?;
+            }
+            for (command, filters) in self.rights.commands() {
+                for filter in filters {
+                    writeln!(f, "\t{}: {}", command, filter)?;
+                }
+            }
+        }
+        if !self.cooldowns.is_empty() {
+            writeln!(f, "Cooldowns:")?;
+            for (command, cooldown) in self.cooldowns.commands.iter() {
+                writeln!(f, "\t{}: {}", command, cooldown)?;
+            }
+        }
+        if !self.plugins.is_empty() {
+            writeln!(f, "Plugin Configs:")?;
+            for (plugin, config) in self.plugins.iter() {
+                writeln!(f, "\t{}: {}", plugin, config)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Profiles {
+    profiles: HashMap<OsString, Profile>,
+}
+
+impl Profiles {
+    pub fn profiles_dir() -> PathBuf {
+        Configs::cfg_dir().join("profiles")
+    }
+
+    pub fn load() -> Self {
+        let path = Self::profiles_dir();
+        create_dir_all(&path).expect("failed to create profile config files");
+        let paths = read_dir(&path).expect("failed to read config directory");
+        let mut profiles = HashMap::new();
+
+        for path in paths {
+            let path = path.expect("failed to get path of configuration subdir");
+            if path.file_type().expect("failed to get file-type").is_dir() {
+                match Profile::from_dir(&path) {
+                    Err(why) => warn!(
+                        "failed to load profile config '{}': {}",
+                        path.path().display(),
+                        why
+                    ),
+                    Ok(profile) => {
+                        profiles.insert(path.file_name(), profile);
+                    }
+                }
+            }
+        }
+
+        Profiles { profiles }
+    }
+
+    /// Imports the bundle at `archive` as new profile named `name` or its exported name.
+    pub fn import(&mut self, archive: &Path, name: Option<&str>) -> Result<&Profile, ProfileError> {
+        if let Some(name) = name {
+            let osstr = OsString::from(name);
+            if self.profiles.contains_key(&osstr) {
+                return Err(ProfileError::AlreadyExists(osstr));
+            }
+        }
+        let profile = bundle::import(archive, &Self::profiles_dir(), name)?;
+        let osstr = OsString::from(profile.name());
+        self.profiles.insert(osstr.clone(), profile);
+        Ok(&self.profiles[&osstr])
+    }
+
+    pub fn add(&mut self, profile: Profile) -> Result<(), ProfileError> {
+        let osstr = OsString::from(&profile.name);
+        if self.profiles.contains_key(&osstr) {
+            return Err(ProfileError::AlreadyExists(osstr));
+        }
+        self.profiles.insert(osstr, profile);
+        Ok(())
+    }
+
+    pub fn delete<S: AsRef<str>>(&mut self, name: S) -> Result<(), ProfileError> {
+        let osstr = OsString::from(name.as_ref());
+        if let Some(profile) = self.profiles.remove(&osstr) {
+            profile.delete()?;
+        }
+        Ok(())
+    }
+
+    pub fn get<S: AsRef<str>>(&self, profile: S) -> Option<&Profile> {
+        let osstr = OsString::from(profile.as_ref());
+        self.profiles.get(&osstr)
+    }
+
+    pub fn get_mut<S: AsRef<str>>(&mut self, profile: S) -> Option<&mut Profile> {
+        let osstr = OsString::from(profile.as_ref());
+        self.profiles.get_mut(&osstr)
+    }
+
+    pub fn save(&self) -> Result<(), ProfileError> {
+        for (_, profile) in self.profiles.iter() {
+            profile.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn iter(&self) -> std::collections::hash_map::Iter<OsString, Profile> {
+        self.profiles.iter()
+    }
+}
+
+pub struct Configs;
+
+impl Configs {
+    pub fn cfg_dir() -> PathBuf {
+        config_dir()
+            .expect("missing config directory")
+            .join("botrs")
+    }
+
+    pub fn log_path() -> PathBuf {
+        Self::cfg_dir().join("log")
+    }
+
+    pub fn stats_path() -> PathBuf {
+        Self::cfg_dir().join("stats")
+    }
+}
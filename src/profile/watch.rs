@@ -0,0 +1,440 @@
+//! Live reloading of profiles edited while the bot runs.
+//!
+//! A [ProfileWatcher] re-reads the configuration file of a profile after it changed, compares
+//! it with the previously loaded profile and publishes the differences as [ProfileEvent] to
+//! all subscribers. Secrets are compared by their fingerprints (see [SecretFingerprints]), so
+//! secrets rotated in the secret store of the profile are detected as well. Plugins subscribe
+//! through [crate::context::Context::profile_events] if the watcher publishes to the events of
+//! their context (see [ProfileWatcher::with_events]).
+
+use crate::auth::Platform;
+use crate::profile::config::find_file;
+use crate::profile::{Profile, ProfileError, SECRETS_FILE};
+use crate::secrets::{EncryptedFile, SecretRef, SecretStore, ENV_SECRETS_PASSPHRASE};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use openssl::sha::sha256;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Debug, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "plugin-loader")]
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// A single difference between two versions of a profile.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ProfileChange {
+    ChannelAdded(String),
+    ChannelRemoved(String),
+    /// Settings of the channel changed. See [crate::profile::channels::ChannelSettings].
+    ChannelChanged(String),
+    RightsChanged,
+    CooldownsChanged,
+    /// Credentials of the platform were added or replaced.
+    CredentialsRotated(Platform),
+    CredentialsRemoved(Platform),
+    /// Client id or client secret changed.
+    ClientChanged,
+    /// Configuration section of the named plugin was added, changed or removed.
+    PluginConfigChanged(String),
+}
+
+/// Changes of a profile published by a [ProfileWatcher].
+#[derive(Clone, Debug)]
+pub struct ProfileEvent {
+    /// Profile after the changes.
+    pub profile: Arc<Profile>,
+    pub changes: Vec<ProfileChange>,
+}
+
+/// Returns the differences between `old` and `new`.
+pub fn diff(old: &Profile, new: &Profile) -> Vec<ProfileChange> {
+    let mut changes = Vec::new();
+    for (channel, settings) in new.channels.iter() {
+        match old.channels.get(channel) {
+            None => changes.push(ProfileChange::ChannelAdded(channel.clone())),
+            Some(old_settings) if old_settings != settings => {
+                changes.push(ProfileChange::ChannelChanged(channel.clone()))
+            }
+            Some(_) => (),
+        }
+    }
+    for channel in old.channels.keys() {
+        if !new.channels.contains_key(channel) {
+            changes.push(ProfileChange::ChannelRemoved(channel.clone()));
+        }
+    }
+    if old.rights != new.rights {
+        changes.push(ProfileChange::RightsChanged);
+    }
+    if old.cooldowns != new.cooldowns {
+        changes.push(ProfileChange::CooldownsChanged);
+    }
+    for (platform, credentials) in new.credentials.iter() {
+        if old.credentials.get(platform) != Some(credentials) {
+            changes.push(ProfileChange::CredentialsRotated(platform.clone()));
+        }
+    }
+    for platform in old.credentials.keys() {
+        if !new.credentials.contains_key(platform) {
+            changes.push(ProfileChange::CredentialsRemoved(platform.clone()));
+        }
+    }
+    if old.client_id != new.client_id || old.client_secret != new.client_secret {
+        changes.push(ProfileChange::ClientChanged);
+    }
+    let plugins = old.plugins.keys().chain(new.plugins.keys());
+    for plugin in plugins.collect::<BTreeSet<_>>() {
+        if old.plugins.get(plugin) != new.plugins.get(plugin) {
+            changes.push(ProfileChange::PluginConfigChanged(plugin.clone()));
+        }
+    }
+    changes
+}
+
+/// Fingerprints of the secrets referenced by a profile. Secrets rotated in a [SecretStore] keep
+/// their [SecretRef], so only their fingerprints tell them apart.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SecretFingerprints {
+    credentials: HashMap<Platform, [u8; 32]>,
+    client_secret: Option<[u8; 32]>,
+}
+
+impl SecretFingerprints {
+    /// Fingerprints the secrets of `profile` resolved through `store`. Secrets missing in the
+    /// store aren't fingerprinted.
+    pub fn of(profile: &Profile, store: &dyn SecretStore) -> Self {
+        let fingerprint =
+            |secret: &SecretRef| secret.resolve(store).ok().map(|s| sha256(s.as_bytes()));
+        SecretFingerprints {
+            credentials: profile
+                .credentials
+                .iter()
+                .filter_map(|(platform, secret)| Some((platform.clone(), fingerprint(secret)?)))
+                .collect(),
+            client_secret: profile.client_secret.as_ref().and_then(fingerprint),
+        }
+    }
+
+    /// Returns the secrets that changed between `self` and `new`. Removed credentials are
+    /// detected by [diff] instead.
+    pub fn diff(&self, new: &SecretFingerprints) -> Vec<ProfileChange> {
+        let mut changes = Vec::new();
+        for (platform, fingerprint) in new.credentials.iter() {
+            if self.credentials.get(platform) != Some(fingerprint) {
+                changes.push(ProfileChange::CredentialsRotated(platform.clone()));
+            }
+        }
+        if self.client_secret != new.client_secret {
+            changes.push(ProfileChange::ClientChanged);
+        }
+        changes
+    }
+}
+
+/// Subscribers of the [ProfileEvent]s of a profile. Clones share their subscribers.
+#[derive(Clone, Debug, Default)]
+pub struct ProfileEvents(Arc<Mutex<Vec<UnboundedSender<ProfileEvent>>>>);
+
+impl ProfileEvents {
+    /// Returns a receiver of all events published after subscribing.
+    pub fn subscribe(&self) -> UnboundedReceiver<ProfileEvent> {
+        let (sender, receiver) = unbounded();
+        self.0
+            .lock()
+            .expect("profile events lock poisoned")
+            .push(sender);
+        receiver
+    }
+
+    pub(crate) fn publish(&self, event: ProfileEvent) {
+        self.0
+            .lock()
+            .expect("profile events lock poisoned")
+            .retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+    }
+}
+
+impl PartialEq for ProfileEvents {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ProfileEvents {}
+
+/// Modification time and size of the configuration and the secrets file of a profile.
+type Modified = [Option<(SystemTime, u64)>; 2];
+
+/// Reloads a profile after its configuration or secrets file changed. See the [module](self)
+/// docs.
+pub struct ProfileWatcher {
+    path: PathBuf,
+    profile: Profile,
+    events: ProfileEvents,
+    modified: Modified,
+    passphrase: Option<String>,
+    secrets: SecretFingerprints,
+}
+
+/// Never prints the passphrase.
+impl Debug for ProfileWatcher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProfileWatcher")
+            .field("path", &self.path)
+            .field("profile", &self.profile)
+            .field("events", &self.events)
+            .field("modified", &self.modified)
+            .finish()
+    }
+}
+
+impl ProfileWatcher {
+    /// Watches the directory of `profile`.
+    pub fn new(profile: Profile) -> Self {
+        Self::with_path(profile.path(), profile)
+    }
+
+    /// Watches the profile directory at `path` which `profile` was loaded from. Secrets are
+    /// read with the passphrase set as `BRS_SECRETS_PASSPHRASE`.
+    pub fn with_path(path: PathBuf, profile: Profile) -> Self {
+        let modified = Self::modified(&path);
+        let watcher = ProfileWatcher {
+            path,
+            profile,
+            events: ProfileEvents::default(),
+            modified,
+            passphrase: None,
+            secrets: SecretFingerprints::default(),
+        };
+        watcher.with_passphrase(std::env::var(ENV_SECRETS_PASSPHRASE).ok())
+    }
+
+    /// Reads the secrets of the profile with `passphrase`. Rotated secrets aren't detected
+    /// without a passphrase.
+    pub fn with_passphrase(mut self, passphrase: Option<String>) -> Self {
+        self.passphrase = passphrase;
+        self.secrets = match self.fingerprints(&self.profile) {
+            Ok(secrets) => secrets,
+            Err(why) => {
+                warn!(
+                    "failed to read secrets of profile '{}': {}",
+                    self.profile.name, why
+                );
+                SecretFingerprints::default()
+            }
+        };
+        self
+    }
+
+    /// Publishes the changes to `events` instead of new subscribers, e.g. the events of the
+    /// [crate::context::Context] of the plugins of the profile.
+    pub fn with_events(mut self, events: ProfileEvents) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub fn events(&self) -> &ProfileEvents {
+        &self.events
+    }
+
+    /// Returns a receiver of all changes detected after subscribing.
+    pub fn subscribe(&self) -> UnboundedReceiver<ProfileEvent> {
+        self.events.subscribe()
+    }
+
+    /// Returns the last loaded version of the profile.
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Re-reads the profile and publishes its changes. Returns the changes which are empty if
+    /// the profile didn't change.
+    pub fn reload(&mut self) -> Result<Vec<ProfileChange>, ProfileError> {
+        self.modified = Self::modified(&self.path);
+        let profile = Profile::from_path_with_passphrase(&self.path, self.passphrase.as_deref())?;
+        let secrets = self.fingerprints(&profile)?;
+        let mut changes = diff(&self.profile, &profile);
+        for change in self.secrets.diff(&secrets) {
+            if !changes.contains(&change) {
+                changes.push(change);
+            }
+        }
+        self.secrets = secrets;
+        if !changes.is_empty() {
+            self.profile = profile;
+            self.events.publish(ProfileEvent {
+                profile: Arc::new(self.profile.clone()),
+                changes: changes.clone(),
+            });
+        }
+        Ok(changes)
+    }
+
+    /// Checks the configuration and the secrets file for modifications every `interval` and
+    /// reloads the profile after one was modified.
+    ///
+    /// Invalid configurations are logged and skipped until the file is modified again.
+    /// Returns if the profile directory can't be read.
+    #[cfg(feature = "plugin-loader")]
+    pub async fn watch(mut self, interval: Duration) -> Result<(), ProfileError> {
+        loop {
+            tokio::time::delay_for(interval).await;
+            fs::metadata(&self.path).map_err(ProfileError::from)?;
+            let modified = Self::modified(&self.path);
+            if modified == self.modified {
+                continue;
+            }
+            match self.reload() {
+                Ok(changes) if !changes.is_empty() => {
+                    info!("Reloaded profile '{}': {:?}", self.profile.name, changes)
+                }
+                Ok(_) => (),
+                Err(why) => warn!(
+                    "failed to reload profile '{}': {}",
+                    self.path.display(),
+                    why
+                ),
+            }
+        }
+    }
+
+    /// Returns modification time and size of the configuration and the secrets file in `path`.
+    fn modified(path: &Path) -> Modified {
+        let (cfg_file, _) = find_file(path, "config");
+        let modified = |file: PathBuf| {
+            let metadata = fs::metadata(file).ok()?;
+            Some((metadata.modified().ok()?, metadata.len()))
+        };
+        [modified(cfg_file), modified(path.join(SECRETS_FILE))]
+    }
+
+    /// Fingerprints the secrets of `profile` if a passphrase is set.
+    fn fingerprints(&self, profile: &Profile) -> Result<SecretFingerprints, ProfileError> {
+        match self.passphrase {
+            Some(ref passphrase) => {
+                let store = EncryptedFile::open(self.path.join(SECRETS_FILE), passphrase)?;
+                Ok(SecretFingerprints::of(profile, &store))
+            }
+            None => Ok(SecretFingerprints::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Platform;
+    use crate::command_access::{AccessFilter, AccessRights};
+    use crate::profile::watch::{diff, ProfileChange, ProfileWatcher};
+    use crate::profile::Profile;
+    use crate::secrets::{EncryptedFile, SecretRef, SecretStore};
+    use futures::{FutureExt, StreamExt};
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    fn profile() -> Profile {
+        Profile::new(
+            "foo".to_string(),
+            vec!["#foo".to_string(), "#bar".to_string()],
+            "id".to_string(),
+            AccessRights::new(),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = profile();
+        assert!(diff(&old, &old).is_empty());
+
+        let mut new = profile();
+        new.remove_channel("#bar");
+        new.add_channels(vec!["#baz".to_string()]);
+        new.channels.get_mut("#foo").unwrap().language = Some("de".to_string());
+        new.rights = AccessRights::from(vec![AccessFilter::broadcaster()]);
+        new.credentials
+            .insert(Platform::Twitch, SecretRef::Store("foo/twitch".to_string()));
+        new.plugins
+            .insert("hello".to_string(), serde_json::json!({}));
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                ProfileChange::ChannelAdded("#baz".to_string()),
+                ProfileChange::ChannelChanged("#foo".to_string()),
+                ProfileChange::ChannelRemoved("#bar".to_string()),
+                ProfileChange::RightsChanged,
+                ProfileChange::CredentialsRotated(Platform::Twitch),
+                ProfileChange::PluginConfigChanged("hello".to_string()),
+            ]
+        );
+        assert_eq!(
+            diff(&new, &old)[4],
+            ProfileChange::CredentialsRemoved(Platform::Twitch)
+        );
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = std::env::temp_dir().join(format!("botrs-watch-{}", std::process::id()));
+        let path = dir.join("foo");
+        create_dir_all(&path).unwrap();
+        let mut profile = profile();
+        let config = path.join("config.json");
+        write(&config, serde_json::to_string(&profile).unwrap()).unwrap();
+
+        let mut watcher = ProfileWatcher::with_path(path.clone(), profile.clone());
+        let mut events = watcher.subscribe();
+        assert!(watcher.reload().unwrap().is_empty());
+        assert!(events.next().now_or_never().is_none());
+
+        profile.add_channels(vec!["#baz".to_string()]);
+        write(&config, serde_json::to_string(&profile).unwrap()).unwrap();
+        let changes = watcher.reload().unwrap();
+        assert_eq!(
+            changes,
+            vec![ProfileChange::ChannelAdded("#baz".to_string())]
+        );
+        let event = events.next().now_or_never().unwrap().unwrap();
+        assert_eq!(event.changes, changes);
+        assert_eq!(event.profile.get_channels().len(), 3);
+        assert_eq!(watcher.profile().get_channels().len(), 3);
+
+        // Invalid configurations keep the last valid profile
+        write(&config, "{").unwrap();
+        assert!(watcher.reload().is_err());
+        assert_eq!(watcher.profile().get_channels().len(), 3);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotated_secret() {
+        let dir = std::env::temp_dir().join(format!("botrs-watch-secrets-{}", std::process::id()));
+        let path = dir.join("foo");
+        create_dir_all(&path).unwrap();
+        let mut profile = profile();
+        let key = "foo/twitch".to_string();
+        profile
+            .credentials
+            .insert(Platform::Twitch, SecretRef::Store(key.clone()));
+        write(
+            path.join("config.json"),
+            serde_json::to_string(&profile).unwrap(),
+        )
+        .unwrap();
+        let mut store = EncryptedFile::open(path.join("secrets.json"), "passphrase").unwrap();
+        store.set(&key, "oauth:first".to_string()).unwrap();
+
+        let mut watcher = ProfileWatcher::with_path(path.clone(), profile)
+            .with_passphrase(Some("passphrase".to_string()));
+        assert!(watcher.reload().unwrap().is_empty());
+
+        // The reference stays the same, only the stored secret changes
+        store.set(&key, "oauth:second".to_string()).unwrap();
+        assert_eq!(
+            watcher.reload().unwrap(),
+            vec![ProfileChange::CredentialsRotated(Platform::Twitch)]
+        );
+        assert!(watcher.reload().unwrap().is_empty());
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+//! Queues between the dispatcher of [crate::plugins::Plugins] and the plugins.
+//!
+//! Every plugin receives its messages one at a time. Messages the plugin didn't receive yet are
+//! queued. By default queues are unbounded. [crate::plugins::Plugins::set_queue] limits the
+//! queues to a capacity and sets what happens if a slow plugin lets its queue overflow.
+
+use crate::Message;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Handling of messages for a plugin whose queue is full.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum OverflowPolicy {
+    /// The dispatcher waits until the plugin received a message. Slows down all plugins.
+    Block,
+    /// The oldest queued message is dropped to queue the new one.
+    DropOldest,
+    /// The new message is dropped.
+    DropNewest,
+    /// The plugin is stopped and receives no more messages.
+    Disconnect,
+}
+
+/// Configuration of a bounded queue.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub struct QueueConfig {
+    /// Maximum number of queued messages.
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl QueueConfig {
+    pub fn new(capacity: usize, overflow: OverflowPolicy) -> Self {
+        QueueConfig { capacity, overflow }
+    }
+}
+
+/// Messages queued for a single plugin.
+#[derive(Debug)]
+pub(crate) struct MessageQueue {
+    messages: VecDeque<Message>,
+    config: Option<QueueConfig>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl MessageQueue {
+    /// Creates an unbounded queue if `config` is `None`. Dropped messages are counted in
+    /// `dropped`.
+    pub(crate) fn new(config: Option<QueueConfig>, dropped: Arc<AtomicU64>) -> Self {
+        MessageQueue {
+            messages: VecDeque::new(),
+            config,
+            dropped,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        match self.config {
+            Some(config) => self.messages.len() >= config.capacity,
+            None => false,
+        }
+    }
+
+    /// Returns if new messages should be received. Blocking queues don't accept messages
+    /// while full.
+    pub(crate) fn accepts(&self) -> bool {
+        match self.config {
+            Some(config) if config.overflow == OverflowPolicy::Block => !self.is_full(),
+            _ => true,
+        }
+    }
+
+    /// Queues `msg` applying the overflow policy. Returns `false` if the plugin should be
+    /// disconnected.
+    pub(crate) fn push(&mut self, msg: Message) -> bool {
+        if !self.is_full() {
+            self.messages.push_back(msg);
+            return true;
+        }
+        match self.config.map(|config| config.overflow) {
+            Some(OverflowPolicy::DropOldest) => {
+                self.messages.pop_front();
+                self.messages.push_back(msg);
+                self.count_dropped(1);
+            }
+            Some(OverflowPolicy::Disconnect) => {
+                self.count_dropped(1);
+                return false;
+            }
+            // Blocking queues only overflow if more messages were received than accepted
+            _ => self.count_dropped(1),
+        }
+        true
+    }
+
+    /// Puts a message taken through [MessageQueue::pop] back to the front.
+    pub(crate) fn push_front(&mut self, msg: Message) {
+        self.messages.push_front(msg);
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Message> {
+        self.messages.pop_front()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Drops all queued messages.
+    pub(crate) fn clear(&mut self) {
+        self.count_dropped(self.messages.len() as u64);
+        self.messages.clear();
+    }
+
+    /// Counts `count` dropped messages that were never queued.
+    pub(crate) fn count_dropped(&self, count: u64) {
+        if count > 0 {
+            self.dropped.fetch_add(count, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::queue::{MessageQueue, OverflowPolicy, QueueConfig};
+    use crate::Message;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    fn message(text: &str) -> Message {
+        Message::Irc(irc_rust::Message::from(format!(
+            "PRIVMSG #channel :{}",
+            text
+        )))
+    }
+
+    fn queue(overflow: OverflowPolicy) -> (MessageQueue, Arc<AtomicU64>) {
+        let dropped = Arc::new(AtomicU64::new(0));
+        let mut queue =
+            MessageQueue::new(Some(QueueConfig::new(2, overflow)), Arc::clone(&dropped));
+        assert!(queue.push(message("1")));
+        assert!(queue.push(message("2")));
+        (queue, dropped)
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let (mut queue, dropped) = queue(OverflowPolicy::DropOldest);
+        assert!(queue.accepts());
+        assert!(queue.push(message("3")));
+        assert_eq!(queue.pop(), Some(message("2")));
+        assert_eq!(queue.pop(), Some(message("3")));
+        assert_eq!(dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_drop_newest() {
+        let (mut queue, dropped) = queue(OverflowPolicy::DropNewest);
+        assert!(queue.push(message("3")));
+        assert_eq!(queue.pop(), Some(message("1")));
+        assert_eq!(queue.pop(), Some(message("2")));
+        assert!(queue.is_empty());
+        assert_eq!(dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_block() {
+        let (mut queue, dropped) = queue(OverflowPolicy::Block);
+        assert!(!queue.accepts());
+        queue.pop();
+        assert!(queue.accepts());
+        assert_eq!(dropped.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (mut queue, dropped) = queue(OverflowPolicy::Disconnect);
+        assert!(!queue.push(message("3")));
+        queue.clear();
+        assert!(queue.is_empty());
+        assert_eq!(dropped.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_unbounded() {
+        let dropped = Arc::new(AtomicU64::new(0));
+        let mut queue = MessageQueue::new(None, Arc::clone(&dropped));
+        for i in 0..100 {
+            assert!(queue.push(message(&i.to_string())));
+        }
+        assert!(queue.accepts());
+        assert_eq!(dropped.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,562 @@
+//! Rate limits of outgoing messages matching the limits of Twitch chat.
+//!
+//! Twitch mutes accounts sending too many messages. The limits depend on whether the bot is a
+//! moderator (or the broadcaster) of the channel a message is sent to, which the
+//! [RateLimiter] learns from incoming `USERSTATE` messages ([RateLimiter::observe]) or
+//! [RateLimiter::set_moderator].
+//!
+//! [crate::plugins::Plugins::set_rate_limits] delays messages of the plugins exceeding the
+//! limits and drops messages which would have to wait longer than [RateLimits::max_delay].
+
+use crate::Message;
+#[cfg(feature = "plugin-loader")]
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
+#[cfg(feature = "plugin-loader")]
+use futures::StreamExt;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Maximum number of messages in a period. The count has to be positive (see
+/// [RateLimits::validate]).
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct Limit {
+    pub count: usize,
+    pub period: Duration,
+}
+
+impl Limit {
+    pub const fn new(count: usize, period: Duration) -> Self {
+        Limit { count, period }
+    }
+}
+
+/// Invalid [RateLimits] passed to a [RateLimiter].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InvalidLimit(pub String);
+
+impl Display for InvalidLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for InvalidLimit {}
+
+/// Limits of outgoing messages. Defaults to the limits of Twitch for regular accounts.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct RateLimits {
+    /// Chat messages of the account to channels it doesn't moderate.
+    pub user: Limit,
+    /// Chat messages of the account if sent to a channel it moderates.
+    pub moderator: Limit,
+    /// Chat messages to a single channel the account doesn't moderate.
+    pub channel: Option<Limit>,
+    /// Whispers of the account.
+    pub whispers: Vec<Limit>,
+    /// Distinct accounts the account whispers to.
+    pub whisper_recipients: Option<Limit>,
+    /// Messages which would have to wait longer are dropped. `None` to never drop messages.
+    pub max_delay: Option<Duration>,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            user: Limit::new(20, Duration::from_secs(30)),
+            moderator: Limit::new(100, Duration::from_secs(30)),
+            channel: Some(Limit::new(1, Duration::from_secs(1))),
+            whispers: vec![
+                Limit::new(3, Duration::from_secs(1)),
+                Limit::new(100, Duration::from_secs(60)),
+            ],
+            whisper_recipients: Some(Limit::new(40, Duration::from_secs(24 * 60 * 60))),
+            max_delay: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+impl RateLimits {
+    /// Fails if a limit allows no messages at all.
+    pub fn validate(&self) -> Result<(), InvalidLimit> {
+        let limits = vec![
+            ("user", Some(&self.user)),
+            ("moderator", Some(&self.moderator)),
+            ("channel", self.channel.as_ref()),
+            ("whisper_recipients", self.whisper_recipients.as_ref()),
+        ]
+        .into_iter()
+        .chain(self.whispers.iter().map(|limit| ("whispers", Some(limit))));
+        for (name, limit) in limits {
+            if limit.map(|limit| limit.count == 0).unwrap_or(false) {
+                return Err(InvalidLimit(format!(
+                    "{} limit has to allow at least one message",
+                    name
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Source of the current time of a [RateLimiter].
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// [Clock] returning [Instant::now].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// [Clock] only advancing if told to. Used to test rate limits.
+#[derive(Debug)]
+pub struct ManualClock(Mutex<Instant>);
+
+impl ManualClock {
+    pub fn new(now: Instant) -> Self {
+        ManualClock(Mutex::new(now))
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().expect("clock lock poisoned") += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new(Instant::now())
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().expect("clock lock poisoned")
+    }
+}
+
+/// Kind of outgoing message determining the applying limits.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Outgoing {
+    Chat(String),
+    Whisper(String),
+    Unlimited,
+}
+
+impl Outgoing {
+    fn of(msg: &Message) -> Self {
+        let Message::Irc(msg) = msg;
+        if msg.command() != "PRIVMSG" {
+            return Outgoing::Unlimited;
+        }
+        let (mut params, trailing) = match msg.params() {
+            Some(params) => params.into_parts(),
+            None => return Outgoing::Unlimited,
+        };
+        let whisper = trailing
+            .and_then(|trailing| {
+                trailing
+                    .strip_prefix("/w ")
+                    .or_else(|| trailing.strip_prefix(".w "))
+            })
+            .and_then(|rest| rest.split_whitespace().next());
+        if let Some(recipient) = whisper {
+            return Outgoing::Whisper(recipient.to_lowercase());
+        }
+        match params.next() {
+            Some(channel) => Outgoing::Chat(normalize(channel)),
+            None => Outgoing::Unlimited,
+        }
+    }
+}
+
+fn normalize(channel: &str) -> String {
+    format!("#{}", channel.trim_start_matches('#').to_lowercase())
+}
+
+/// Returns the time until another message can be sent without exceeding `limit`. `sent`
+/// contains the times messages were sent in order. `limit` has to allow at least one message.
+fn wait(sent: &VecDeque<Instant>, limit: Limit, now: Instant) -> Option<Duration> {
+    let recent = sent
+        .iter()
+        .rev()
+        .take_while(|time| **time + limit.period > now)
+        .count();
+    if recent < limit.count {
+        return None;
+    }
+    // Another message can be sent as soon as the oldest of the last `count` messages expires
+    let oldest = sent[sent.len() - limit.count];
+    Some(oldest + limit.period - now)
+}
+
+/// Removes the entries of `sent` older than `period`.
+fn prune(sent: &mut VecDeque<Instant>, period: Duration, now: Instant) {
+    while sent
+        .front()
+        .map(|time| *time + period <= now)
+        .unwrap_or(false)
+    {
+        sent.pop_front();
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    chat: VecDeque<Instant>,
+    channels: HashMap<String, VecDeque<Instant>>,
+    whispers: VecDeque<Instant>,
+    // Maps recipients to the time they were first whispered to
+    recipients: HashMap<String, Instant>,
+    moderated: HashSet<String>,
+}
+
+/// Decides when outgoing messages can be sent without exceeding the [RateLimits].
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    clock: Arc<dyn Clock>,
+    state: Mutex<State>,
+}
+
+impl RateLimiter {
+    /// Fails if the limits are invalid (see [RateLimits::validate]).
+    pub fn new(limits: RateLimits) -> Result<Self, InvalidLimit> {
+        RateLimiter::with_clock(limits, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(limits: RateLimits, clock: Arc<dyn Clock>) -> Result<Self, InvalidLimit> {
+        limits.validate()?;
+        Ok(RateLimiter {
+            limits,
+            clock,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    pub fn limits(&self) -> &RateLimits {
+        &self.limits
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("rate limiter lock poisoned")
+    }
+
+    /// Sets if the account is a moderator (or the broadcaster) of `channel`.
+    pub fn set_moderator(&self, channel: &str, moderator: bool) {
+        let channel = normalize(channel);
+        let mut state = self.state();
+        if moderator {
+            state.moderated.insert(channel);
+        } else {
+            state.moderated.remove(&channel);
+        }
+    }
+
+    /// Updates the moderator status of the account in a channel from an incoming `USERSTATE`
+    /// message.
+    pub fn observe(&self, msg: &Message) {
+        let Message::Irc(irc) = msg;
+        if irc.command() != "USERSTATE" {
+            return;
+        }
+        let channel = match irc.params().and_then(|params| params.into_parts().0.next()) {
+            Some(channel) => channel,
+            None => return,
+        };
+        let tags = match irc.tags() {
+            Ok(Some(tags)) => tags,
+            _ => return,
+        };
+        let moderator = tags.get("mod") == Some("1")
+            || tags
+                .get("badges")
+                .map(|badges| {
+                    badges
+                        .split(',')
+                        .any(|badge| badge.starts_with("broadcaster/"))
+                })
+                .unwrap_or(false);
+        self.set_moderator(channel, moderator);
+    }
+
+    /// Checks if `msg` can be sent now. Records the message as sent if it can. Returns the time
+    /// to wait before trying again otherwise.
+    pub fn try_acquire(&self, msg: &Message) -> Result<(), Duration> {
+        let now = self.clock.now();
+        let limits = &self.limits;
+        let mut state = self.state();
+        match Outgoing::of(msg) {
+            Outgoing::Unlimited => Ok(()),
+            Outgoing::Chat(channel) => {
+                let moderator = state.moderated.contains(&channel);
+                let account = if moderator {
+                    limits.moderator
+                } else {
+                    limits.user
+                };
+                let mut waits = vec![wait(&state.chat, account, now)];
+                if let (Some(limit), false) = (limits.channel, moderator) {
+                    if let Some(sent) = state.channels.get(&channel) {
+                        waits.push(wait(sent, limit, now));
+                    }
+                }
+                if let Some(wait) = waits.into_iter().flatten().max() {
+                    return Err(wait);
+                }
+
+                let period = limits.user.period.max(limits.moderator.period);
+                prune(&mut state.chat, period, now);
+                state.chat.push_back(now);
+                if let (Some(limit), false) = (limits.channel, moderator) {
+                    state.channels.retain(|_, sent| {
+                        prune(sent, limit.period, now);
+                        !sent.is_empty()
+                    });
+                    state.channels.entry(channel).or_default().push_back(now);
+                }
+                Ok(())
+            }
+            Outgoing::Whisper(recipient) => {
+                let mut waits = limits
+                    .whispers
+                    .iter()
+                    .map(|limit| wait(&state.whispers, *limit, now))
+                    .collect::<Vec<_>>();
+                if let Some(limit) = limits.whisper_recipients {
+                    state
+                        .recipients
+                        .retain(|_, first| *first + limit.period > now);
+                    if !state.recipients.contains_key(&recipient)
+                        && state.recipients.len() >= limit.count
+                    {
+                        waits.push(
+                            state
+                                .recipients
+                                .values()
+                                .min()
+                                .map(|first| *first + limit.period - now),
+                        );
+                    }
+                }
+                if let Some(wait) = waits.into_iter().flatten().max() {
+                    return Err(wait);
+                }
+
+                let period = limits
+                    .whispers
+                    .iter()
+                    .map(|limit| limit.period)
+                    .max()
+                    .unwrap_or_default();
+                prune(&mut state.whispers, period, now);
+                state.whispers.push_back(now);
+                if limits.whisper_recipients.is_some() {
+                    state.recipients.entry(recipient).or_insert(now);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Forwards the messages of `input` to `output` as soon as the limits allow it. Messages
+    /// which would have to wait longer than [RateLimits::max_delay] are dropped.
+    #[cfg(feature = "plugin-loader")]
+    pub async fn throttle(
+        &self,
+        mut input: UnboundedReceiver<Vec<Message>>,
+        output: UnboundedSender<Vec<Message>>,
+    ) {
+        while let Some(messages) = input.next().await {
+            if !self.forward(messages, &output).await {
+                return;
+            }
+        }
+    }
+
+    /// Forwards `messages` to `output` as soon as the limits allow it. Returns `false` if
+    /// `output` was closed.
+    #[cfg(feature = "plugin-loader")]
+    pub(crate) async fn forward(
+        &self,
+        messages: Vec<Message>,
+        output: &UnboundedSender<Vec<Message>>,
+    ) -> bool {
+        let mut batch = Vec::with_capacity(messages.len());
+        for msg in messages {
+            loop {
+                match self.try_acquire(&msg) {
+                    Ok(()) => {
+                        batch.push(msg);
+                        break;
+                    }
+                    Err(wait) if self.limits.max_delay.map(|max| wait > max).unwrap_or(false) => {
+                        warn!("Dropped message exceeding rate limits: {:?}", msg);
+                        break;
+                    }
+                    Err(wait) => {
+                        // Sends the allowed messages before waiting
+                        if !batch.is_empty()
+                            && output.unbounded_send(std::mem::take(&mut batch)).is_err()
+                        {
+                            return false;
+                        }
+                        tokio::time::delay_for(wait).await;
+                    }
+                }
+            }
+        }
+        batch.is_empty() || output.unbounded_send(batch).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rate_limit::{Limit, ManualClock, RateLimiter, RateLimits};
+    use crate::Message;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn privmsg(channel: &str, text: &str) -> Message {
+        Message::Irc(irc_rust::Message::from(format!(
+            "PRIVMSG {} :{}",
+            channel, text
+        )))
+    }
+
+    fn limiter(limits: RateLimits) -> (RateLimiter, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::default());
+        (
+            RateLimiter::with_clock(limits, clock.clone()).unwrap(),
+            clock,
+        )
+    }
+
+    #[test]
+    fn test_account_limit() {
+        let (limiter, clock) = limiter(RateLimits {
+            channel: None,
+            ..RateLimits::default()
+        });
+        for _ in 0..20 {
+            assert_eq!(limiter.try_acquire(&privmsg("#foo", "hello")), Ok(()));
+            clock.advance(Duration::from_millis(100));
+        }
+        assert_eq!(
+            limiter.try_acquire(&privmsg("#bar", "hello")),
+            Err(Duration::from_secs(28))
+        );
+
+        // Moderators can send more messages
+        limiter.observe(&Message::Irc(irc_rust::Message::from(
+            "@badges=moderator/1;mod=1 :tmi.twitch.tv USERSTATE #Bar",
+        )));
+        assert_eq!(limiter.try_acquire(&privmsg("#bar", "hello")), Ok(()));
+        assert!(limiter.try_acquire(&privmsg("#foo", "hello")).is_err());
+
+        // The message to #bar counts towards the account limit as well
+        clock.advance(Duration::from_secs(28));
+        assert_eq!(
+            limiter.try_acquire(&privmsg("#foo", "hello")),
+            Err(Duration::from_millis(100))
+        );
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(limiter.try_acquire(&privmsg("#foo", "hello")), Ok(()));
+    }
+
+    #[test]
+    fn test_channel_limit() {
+        let (limiter, clock) = limiter(RateLimits::default());
+        assert_eq!(limiter.try_acquire(&privmsg("#foo", "hello")), Ok(()));
+        assert_eq!(
+            limiter.try_acquire(&privmsg("#FOO", "hello")),
+            Err(Duration::from_secs(1))
+        );
+        assert_eq!(limiter.try_acquire(&privmsg("#bar", "hello")), Ok(()));
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(
+            limiter.try_acquire(&privmsg("#foo", "hello")),
+            Err(Duration::from_millis(500))
+        );
+
+        limiter.set_moderator("foo", true);
+        assert_eq!(limiter.try_acquire(&privmsg("#foo", "hello")), Ok(()));
+        assert_eq!(limiter.try_acquire(&privmsg("#foo", "hello")), Ok(()));
+    }
+
+    #[test]
+    fn test_whisper_limits() {
+        let (limiter, clock) = limiter(RateLimits {
+            whisper_recipients: Some(Limit::new(2, Duration::from_secs(60))),
+            ..RateLimits::default()
+        });
+        assert_eq!(limiter.try_acquire(&privmsg("#jtv", "/w a hello")), Ok(()));
+        assert_eq!(limiter.try_acquire(&privmsg("#jtv", "/w b hello")), Ok(()));
+        assert_eq!(limiter.try_acquire(&privmsg("#jtv", "/w a hello")), Ok(()));
+        assert_eq!(
+            limiter.try_acquire(&privmsg("#jtv", "/w a hello")),
+            Err(Duration::from_secs(1))
+        );
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            limiter.try_acquire(&privmsg("#jtv", "/w c hello")),
+            Err(Duration::from_secs(59))
+        );
+        assert_eq!(limiter.try_acquire(&privmsg("#jtv", "/w b hello")), Ok(()));
+
+        // Not limited
+        let pong = Message::Irc(irc_rust::Message::from("PONG :tmi.twitch.tv"));
+        for _ in 0..100 {
+            assert_eq!(limiter.try_acquire(&pong), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_invalid_limits() {
+        let limits = RateLimits {
+            channel: Some(Limit::new(0, Duration::from_secs(1))),
+            ..RateLimits::default()
+        };
+        assert!(limits.validate().is_err());
+        assert!(RateLimiter::new(limits).is_err());
+        let limits = RateLimits {
+            whispers: vec![Limit::new(0, Duration::from_secs(1))],
+            ..RateLimits::default()
+        };
+        assert!(RateLimiter::new(limits).is_err());
+        assert!(RateLimiter::new(RateLimits::default()).is_ok());
+    }
+
+    #[cfg(feature = "plugin-loader")]
+    #[tokio::test]
+    async fn test_throttle() {
+        let limiter = RateLimiter::new(RateLimits {
+            channel: Some(Limit::new(1, Duration::from_millis(50))),
+            max_delay: Some(Duration::from_millis(100)),
+            ..RateLimits::default()
+        })
+        .unwrap();
+        let (input_sender, input_receiver) = futures::channel::mpsc::unbounded();
+        let (output_sender, output_receiver) = futures::channel::mpsc::unbounded();
+        input_sender
+            .unbounded_send(vec![privmsg("#foo", "1"), privmsg("#foo", "2")])
+            .unwrap();
+        input_sender.close_channel();
+
+        limiter.throttle(input_receiver, output_sender).await;
+
+        let batches = futures::StreamExt::collect::<Vec<_>>(output_receiver).await;
+        assert_eq!(
+            batches,
+            vec![vec![privmsg("#foo", "1")], vec![privmsg("#foo", "2")]]
+        );
+    }
+}
@@ -0,0 +1,255 @@
+//! Routing of messages to the plugins which declared the invoked command.
+//!
+//! With routing enabled ([crate::plugins::Plugins::set_routing]) a PRIVMSG is only sent to
+//! plugins which contain the first word of its trailing (e.g. `!hello`) in
+//! [PluginInfo::commands] if the word starts with one of the [COMMAND_PREFIXES]. Commands are
+//! compared case-insensitively. Commands declared without prefix (e.g. `hello`) are invoked
+//! with the [DEFAULT_PREFIX].
+//!
+//! Plugins declaring [ALL_MESSAGES] as command receive every message. Messages without a
+//! command (e.g. PING or JOIN) are only sent to these plugins.
+//!
+//! Channels can set their own prefix ([crate::profile::channels::ChannelSettings::prefix]).
+//! Only commands invoked with it are routed in such channels (see [command_with_prefix]). They're
+//! passed to the plugins with the [DEFAULT_PREFIX] (see [with_default_prefix]).
+
+use crate::plugin::PluginInfo;
+use crate::Message;
+use std::collections::HashMap;
+
+/// Command declared by plugins to receive all messages if routing is enabled.
+pub const ALL_MESSAGES: &str = "*";
+
+/// Characters starting a command. Messages starting with any other character are no commands
+/// (see [crate::command_access::AccessFilter::default_command_start]).
+pub const COMMAND_PREFIXES: &[char] = &['!', '?', '¡', '¿'];
+
+/// Prefix of the commands declared by plugins.
+pub const DEFAULT_PREFIX: char = '!';
+
+/// Returns the command invoked by `msg`. This is the first word of the trailing of a PRIVMSG
+/// if it starts with one of the [COMMAND_PREFIXES].
+pub fn command(msg: &Message) -> Option<String> {
+    first_word(msg)
+        .filter(|word| word.starts_with(COMMAND_PREFIXES))
+        .map(str::to_lowercase)
+}
+
+/// Returns the command invoked by `msg` in a channel with its own `prefix` with the
+/// [DEFAULT_PREFIX]. Only `prefix` invokes commands in such channels, e.g. `~hello` invokes
+/// `!hello` for the prefix `~` while `!hello` invokes no command.
+pub fn command_with_prefix(msg: &Message, prefix: &str) -> Option<String> {
+    if prefix.is_empty() {
+        return command(msg);
+    }
+    first_word(msg)?
+        .strip_prefix(prefix)
+        .filter(|command| !command.is_empty())
+        .map(|command| format!("{}{}", DEFAULT_PREFIX, command.to_lowercase()))
+}
+
+/// Returns the first word of the trailing of `msg` if it's a PRIVMSG.
+fn first_word(msg: &Message) -> Option<&str> {
+    match msg {
+        Message::Irc(msg) => {
+            if msg.command() != "PRIVMSG" {
+                return None;
+            }
+            let (_, trailing) = msg.params()?.into_parts();
+            trailing?.split_whitespace().next()
+        }
+    }
+}
+
+/// Replaces `prefix` at the start of the trailing of a PRIVMSG with the [DEFAULT_PREFIX], e.g.
+/// `?hello` becomes `!hello` for the prefix `?`. Other messages are returned unchanged.
+pub fn with_default_prefix(msg: Message, prefix: &str) -> Message {
+    match msg {
+        Message::Irc(irc) => {
+            let raw = irc.to_string();
+            let trailing = match irc.params().and_then(|params| params.into_parts().1) {
+                Some(trailing) if irc.command() == "PRIVMSG" && !prefix.is_empty() => trailing,
+                _ => return Message::Irc(irc),
+            };
+            match trailing.strip_prefix(prefix) {
+                // The trailing is the end of the raw message
+                Some(command) if command.starts_with(|c: char| !c.is_whitespace()) => {
+                    Message::Irc(irc_rust::Message::from(format!(
+                        "{}{}{}",
+                        &raw[..raw.len() - trailing.len()],
+                        DEFAULT_PREFIX,
+                        command
+                    )))
+                }
+                _ => Message::Irc(irc),
+            }
+        }
+    }
+}
+
+/// Returns the channel `msg` was sent to if it's a PRIVMSG to a channel.
+pub fn channel(msg: &Message) -> Option<&str> {
+    match msg {
+        Message::Irc(msg) => {
+            if msg.command() != "PRIVMSG" {
+                return None;
+            }
+            let (mut params, _) = msg.params()?.into_parts();
+            params.next().filter(|channel| channel.starts_with('#'))
+        }
+    }
+}
+
+/// Returns `command` in lowercase with the [DEFAULT_PREFIX] if it doesn't start with one of
+/// the [COMMAND_PREFIXES], e.g. `Hello` becomes `!hello`.
+pub(crate) fn normalize(command: &str) -> String {
+    let command = command.to_lowercase();
+    if command.starts_with(COMMAND_PREFIXES) {
+        command
+    } else {
+        format!("{}{}", DEFAULT_PREFIX, command)
+    }
+}
+
+/// Index of the plugins to send messages to. Plugins are referenced by their position.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(not(feature = "plugin-loader"), allow(dead_code))]
+pub(crate) struct Router {
+    commands: HashMap<String, Vec<usize>>,
+    all: Vec<usize>,
+}
+
+#[cfg_attr(not(feature = "plugin-loader"), allow(dead_code))]
+impl Router {
+    pub(crate) fn new<I: IntoIterator<Item = PluginInfo>>(plugins: I) -> Self {
+        let mut router = Router::default();
+        for (index, info) in plugins.into_iter().enumerate() {
+            if info.commands.iter().any(|command| command == ALL_MESSAGES) {
+                router.all.push(index);
+                continue;
+            }
+            for command in info.commands {
+                let indices = router.commands.entry(normalize(&command)).or_default();
+                if indices.last() != Some(&index) {
+                    indices.push(index);
+                }
+            }
+        }
+        router
+    }
+
+    /// Returns the sorted positions of all plugins a message invoking `command` has to be sent
+    /// to (see [command]).
+    pub(crate) fn route(&self, command: Option<&str>) -> Vec<usize> {
+        let commanded = command
+            .and_then(|command| self.commands.get(command))
+            .map(Vec::as_slice)
+            .unwrap_or(&[]);
+        let mut targets = Vec::with_capacity(self.all.len() + commanded.len());
+        targets.extend_from_slice(&self.all);
+        targets.extend_from_slice(commanded);
+        targets.sort_unstable();
+        targets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::plugin::PluginInfo;
+    use crate::router::{command, command_with_prefix, with_default_prefix, Router, ALL_MESSAGES};
+    use crate::Message;
+
+    fn info(commands: &[&str]) -> PluginInfo {
+        PluginInfo {
+            name: "".to_string(),
+            version: "".to_string(),
+            authors: "".to_string(),
+            repo: None,
+            commands: commands.iter().map(|command| command.to_string()).collect(),
+            required_rights: Default::default(),
+        }
+    }
+
+    fn privmsg(trailing: &str) -> Message {
+        Message::Irc(irc_rust::Message::from(format!(
+            "PRIVMSG #channel :{}",
+            trailing
+        )))
+    }
+
+    #[test]
+    fn test_command() {
+        assert_eq!(
+            command(&privmsg("!Hello there")),
+            Some("!hello".to_string())
+        );
+        assert_eq!(command(&privmsg("")), None);
+        // Messages without command prefix invoke no command
+        assert_eq!(command(&privmsg("hello there")), None);
+        assert_eq!(command(&privmsg("?Hello")), Some("?hello".to_string()));
+        assert_eq!(
+            command(&Message::Irc(irc_rust::Message::from("PING :!hello"))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_default_prefix() {
+        let msg = Message::Irc(irc_rust::Message::from(
+            "@badges=broadcaster/1 :foo!foo@foo PRIVMSG #channel :~hello there".to_string(),
+        ));
+        assert_eq!(
+            with_default_prefix(msg, "~").to_string(),
+            "@badges=broadcaster/1 :foo!foo@foo PRIVMSG #channel :!hello there"
+        );
+        assert_eq!(
+            command(&with_default_prefix(privmsg("bot hi"), "bot ")),
+            Some("!hi".to_string())
+        );
+        // Messages without the prefix or a command stay unchanged
+        for trailing in &["hello", "~", "~ hello"] {
+            assert_eq!(
+                with_default_prefix(privmsg(trailing), "~").to_string(),
+                privmsg(trailing).to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_command_with_prefix() {
+        assert_eq!(
+            command_with_prefix(&privmsg("~Hello there"), "~"),
+            Some("!hello".to_string())
+        );
+        // Only the prefix of the channel invokes commands
+        for trailing in &["!hello", "hello", "~", "~ hello"] {
+            assert_eq!(command_with_prefix(&privmsg(trailing), "~"), None);
+        }
+        assert_eq!(
+            command_with_prefix(&privmsg("!hello"), ""),
+            Some("!hello".to_string())
+        );
+    }
+
+    #[test]
+    fn test_route() {
+        let router = Router::new(vec![
+            info(&["!hello", "!hi"]),
+            info(&[ALL_MESSAGES]),
+            info(&["!HELLO"]),
+            info(&[]),
+            info(&["hello"]),
+        ]);
+
+        let route = |msg: &Message| router.route(command(msg).as_deref());
+
+        // Commands without prefix are declared with the default prefix
+        assert_eq!(route(&privmsg("!hello world")), vec![0, 1, 2, 4]);
+        assert_eq!(route(&privmsg("!hi")), vec![0, 1]);
+        assert_eq!(route(&privmsg("hello")), vec![1]);
+        assert_eq!(
+            route(&Message::Irc(irc_rust::Message::from("PING :tmi"))),
+            vec![1]
+        );
+    }
+}
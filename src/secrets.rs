@@ -0,0 +1,428 @@
+//! Storage of secrets like OAuth tokens outside of the profile configuration.
+//!
+//! Profiles only keep [SecretRef]s to their secrets. The secrets themselves are kept in a
+//! [SecretStore]:
+//!
+//! - [EncryptedFile]: File encrypted with a passphrase (AES-256-GCM with a key derived by
+//!   PBKDF2). Used by default if the passphrase is set as `BRS_SECRETS_PASSPHRASE`.
+//! - [EnvSecrets]: Read-only store reading the secrets from environment variables, e.g. when
+//!   running in a container. Has to be used explicitly.
+//!
+//! See [crate::profile::Profile::secrets].
+
+use crate::profile::files::write_private;
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::pkcs5::pbkdf2_hmac;
+use openssl::rand::rand_bytes;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use serde_json::Error as JsonError;
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::fs::read_to_string;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::{env, error};
+
+/// Environment variable containing the passphrase of the [EncryptedFile] of a profile.
+pub const ENV_SECRETS_PASSPHRASE: &str = "BRS_SECRETS_PASSPHRASE";
+/// Default prefix of the environment variables read by [EnvSecrets].
+pub const ENV_SECRETS_PREFIX: &str = "BRS_SECRET_";
+
+const FORMAT_VERSION: u32 = 1;
+const KDF_ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+#[derive(Debug)]
+pub enum SecretError {
+    IO(io::Error),
+    Json(JsonError),
+    Crypto(ErrorStack),
+    /// The passphrase is wrong or the file was modified.
+    Decrypt,
+    /// The file isn't a valid secrets file.
+    InvalidFormat(String),
+    /// The store doesn't support modifications.
+    ReadOnly,
+    /// The referenced secret doesn't exist.
+    Missing(String),
+    /// No passphrase of the [EncryptedFile] is set as `BRS_SECRETS_PASSPHRASE`.
+    NoPassphrase,
+}
+
+impl From<io::Error> for SecretError {
+    fn from(error: io::Error) -> Self {
+        SecretError::IO(error)
+    }
+}
+
+impl From<JsonError> for SecretError {
+    fn from(error: JsonError) -> Self {
+        SecretError::Json(error)
+    }
+}
+
+impl From<ErrorStack> for SecretError {
+    fn from(error: ErrorStack) -> Self {
+        SecretError::Crypto(error)
+    }
+}
+
+impl error::Error for SecretError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SecretError::IO(source) => Some(source),
+            SecretError::Json(source) => Some(source),
+            SecretError::Crypto(source) => Some(source),
+            SecretError::Decrypt
+            | SecretError::InvalidFormat(_)
+            | SecretError::ReadOnly
+            | SecretError::Missing(_)
+            | SecretError::NoPassphrase => None,
+        }
+    }
+}
+
+impl Display for SecretError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::IO(why) => write!(f, "failed to read/write secrets file: {}", why),
+            SecretError::Json(why) => write!(f, "invalid secrets file: {}", why),
+            SecretError::Crypto(why) => write!(f, "failed to encrypt secrets: {}", why),
+            SecretError::Decrypt => write!(f, "wrong passphrase or corrupted secrets file"),
+            SecretError::InvalidFormat(why) => write!(f, "invalid secrets file: {}", why),
+            SecretError::ReadOnly => write!(f, "secret store is read-only"),
+            SecretError::Missing(key) => write!(f, "missing secret '{}'", key),
+            SecretError::NoPassphrase => write!(
+                f,
+                "no secrets passphrase set, set {} to store secrets",
+                ENV_SECRETS_PASSPHRASE
+            ),
+        }
+    }
+}
+
+/// Reference to a secret of a profile.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum SecretRef {
+    /// Key of the secret in the [SecretStore].
+    Store(String),
+    /// Secret kept in plaintext by profiles written before secret stores existed. Moved into
+    /// a store by [crate::profile::Profile::store_secrets].
+    Plain(String),
+}
+
+impl SecretRef {
+    /// Returns the referenced secret.
+    pub fn resolve(&self, store: &dyn SecretStore) -> Result<String, SecretError> {
+        match self {
+            SecretRef::Store(key) => store
+                .get(key)?
+                .ok_or_else(|| SecretError::Missing(key.clone())),
+            SecretRef::Plain(secret) => Ok(secret.clone()),
+        }
+    }
+}
+
+/// Never prints plaintext secrets.
+impl Display for SecretRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretRef::Store(key) => write!(f, "stored as '{}'", key),
+            SecretRef::Plain(secret) => write!(f, "{} (plaintext)", mask(secret)),
+        }
+    }
+}
+
+/// Masks all but the last 4 characters of long secrets and short secrets completely.
+pub fn mask(secret: &str) -> String {
+    let chars = secret.chars().count();
+    if chars < 16 {
+        "********".to_string()
+    } else {
+        let visible = secret.chars().skip(chars - 4).collect::<String>();
+        format!("********{}", visible)
+    }
+}
+
+/// Storage of secrets by key.
+pub trait SecretStore: Debug + Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<String>, SecretError>;
+
+    /// Sets the secret of `key`. Overwrites existing secrets.
+    fn set(&mut self, key: &str, secret: String) -> Result<(), SecretError>;
+
+    fn remove(&mut self, key: &str) -> Result<(), SecretError>;
+}
+
+/// Read-only [SecretStore] reading secrets from environment variables.
+///
+/// Keys are mapped to variables by uppercasing them, replacing all non-alphanumeric characters
+/// with `_` and adding a prefix, e.g. `foo/twitch` is read from `BRS_SECRET_FOO_TWITCH`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EnvSecrets {
+    prefix: String,
+}
+
+impl Default for EnvSecrets {
+    fn default() -> Self {
+        EnvSecrets::new(ENV_SECRETS_PREFIX)
+    }
+}
+
+impl EnvSecrets {
+    pub fn new<S: Into<String>>(prefix: S) -> Self {
+        EnvSecrets {
+            prefix: prefix.into(),
+        }
+    }
+
+    /// Returns the name of the environment variable containing the secret `key`.
+    pub fn var(&self, key: &str) -> String {
+        let key = key
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+impl SecretStore for EnvSecrets {
+    fn get(&self, key: &str) -> Result<Option<String>, SecretError> {
+        Ok(env::var(self.var(key)).ok())
+    }
+
+    fn set(&mut self, _key: &str, _secret: String) -> Result<(), SecretError> {
+        Err(SecretError::ReadOnly)
+    }
+
+    fn remove(&mut self, _key: &str) -> Result<(), SecretError> {
+        Err(SecretError::ReadOnly)
+    }
+}
+
+/// Contents of an [EncryptedFile] on disk. Binary values are hex encoded.
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    version: u32,
+    iterations: u32,
+    salt: String,
+    nonce: String,
+    tag: String,
+    data: String,
+}
+
+/// [SecretStore] persisting the secrets in a file encrypted with a passphrase.
+///
+/// Every modification rewrites the file with a new nonce.
+pub struct EncryptedFile {
+    path: PathBuf,
+    salt: Vec<u8>,
+    key: Vec<u8>,
+    secrets: BTreeMap<String, String>,
+}
+
+impl Debug for EncryptedFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedFile")
+            .field("path", &self.path)
+            .field("keys", &self.secrets.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl EncryptedFile {
+    /// Opens the secrets file at `path`. Creates an empty store if the file doesn't exist yet.
+    pub fn open<P: Into<PathBuf>>(path: P, passphrase: &str) -> Result<Self, SecretError> {
+        let path = path.into();
+        if !path.exists() {
+            let mut salt = vec![0; SALT_LEN];
+            rand_bytes(&mut salt)?;
+            let key = derive_key(passphrase, &salt, KDF_ITERATIONS)?;
+            return Ok(EncryptedFile {
+                path,
+                salt,
+                key,
+                secrets: BTreeMap::new(),
+            });
+        }
+
+        let sealed: Sealed = serde_json::from_str(&read_to_string(&path)?)?;
+        if sealed.version != FORMAT_VERSION {
+            return Err(SecretError::InvalidFormat(format!(
+                "unsupported version {}",
+                sealed.version
+            )));
+        }
+        let salt = decode_hex(&sealed.salt)?;
+        let key = derive_key(passphrase, &salt, sealed.iterations)?;
+        let plain = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &key,
+            Some(&decode_hex(&sealed.nonce)?),
+            &[],
+            &decode_hex(&sealed.data)?,
+            &decode_hex(&sealed.tag)?,
+        )
+        .map_err(|_| SecretError::Decrypt)?;
+        let secrets = serde_json::from_slice(&plain)?;
+        Ok(EncryptedFile {
+            path,
+            salt,
+            key,
+            secrets,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the keys of all stored secrets.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.secrets.keys()
+    }
+
+    /// Writes the encrypted secrets to the file.
+    pub fn save(&self) -> Result<(), SecretError> {
+        let plain = serde_json::to_vec(&self.secrets)?;
+        let mut nonce = vec![0; NONCE_LEN];
+        rand_bytes(&mut nonce)?;
+        let mut tag = vec![0; TAG_LEN];
+        let data = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key,
+            Some(&nonce),
+            &[],
+            &plain,
+            &mut tag,
+        )?;
+        let sealed = Sealed {
+            version: FORMAT_VERSION,
+            iterations: KDF_ITERATIONS,
+            salt: encode_hex(&self.salt),
+            nonce: encode_hex(&nonce),
+            tag: encode_hex(&tag),
+            data: encode_hex(&data),
+        };
+        let json = serde_json::to_string_pretty(&sealed)?;
+        write_private(&self.path, json.as_bytes())?;
+        Ok(())
+    }
+}
+
+impl SecretStore for EncryptedFile {
+    fn get(&self, key: &str) -> Result<Option<String>, SecretError> {
+        Ok(self.secrets.get(key).cloned())
+    }
+
+    fn set(&mut self, key: &str, secret: String) -> Result<(), SecretError> {
+        self.secrets.insert(key.to_string(), secret);
+        self.save()
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), SecretError> {
+        if self.secrets.remove(key).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> Result<Vec<u8>, SecretError> {
+    let mut key = vec![0; KEY_LEN];
+    pbkdf2_hmac(
+        passphrase.as_bytes(),
+        salt,
+        iterations as usize,
+        MessageDigest::sha256(),
+        &mut key,
+    )?;
+    Ok(key)
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, SecretError> {
+    let invalid = || SecretError::InvalidFormat(format!("invalid hex: {}", hex));
+    hex.as_bytes()
+        .chunks(2)
+        .map(|byte| {
+            std::str::from_utf8(byte)
+                .ok()
+                .filter(|byte| byte.len() == 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::secrets::{mask, EncryptedFile, EnvSecrets, SecretError, SecretRef, SecretStore};
+    use std::fs::{read_to_string, remove_file};
+
+    #[test]
+    fn test_encrypted_file() {
+        let path = std::env::temp_dir().join(format!("botrs-secrets-{}.json", std::process::id()));
+        let mut store = EncryptedFile::open(&path, "passphrase").unwrap();
+        assert_eq!(store.get("foo/twitch").unwrap(), None);
+        store
+            .set("foo/twitch", "oauth:thisisatoken".to_string())
+            .unwrap();
+        assert!(!read_to_string(&path).unwrap().contains("thisisatoken"));
+
+        let mut store = EncryptedFile::open(&path, "passphrase").unwrap();
+        assert_eq!(
+            store.get("foo/twitch").unwrap().as_deref(),
+            Some("oauth:thisisatoken")
+        );
+        assert!(matches!(
+            EncryptedFile::open(&path, "wrong"),
+            Err(SecretError::Decrypt)
+        ));
+
+        store.remove("foo/twitch").unwrap();
+        let store = EncryptedFile::open(&path, "passphrase").unwrap();
+        assert_eq!(store.keys().count(), 0);
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_env_secrets() {
+        let mut store = EnvSecrets::new("BRS_TEST_SECRET_");
+        assert_eq!(store.var("foo/twitch"), "BRS_TEST_SECRET_FOO_TWITCH");
+        std::env::set_var("BRS_TEST_SECRET_FOO_TWITCH", "oauth:token");
+        let reference = SecretRef::Store("foo/twitch".to_string());
+        assert_eq!(reference.resolve(&store).unwrap(), "oauth:token");
+        assert!(matches!(
+            SecretRef::Store("bar".to_string()).resolve(&store),
+            Err(SecretError::Missing(_))
+        ));
+        assert!(matches!(
+            store.set("foo/twitch", String::new()),
+            Err(SecretError::ReadOnly)
+        ));
+    }
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask("short"), "********");
+        assert_eq!(mask("oauth:thisisalongtoken"), "********oken");
+        assert_eq!(
+            SecretRef::Plain("oauth:thisisalongtoken".to_string()).to_string(),
+            "********oken (plaintext)"
+        );
+    }
+}
@@ -0,0 +1,724 @@
+//! Supervision of plugins streamed by [crate::plugins::Plugins].
+//!
+//! A plugin whose [StreamablePlugin::stream] returns while its input is still open crashed.
+//! The supervisor decides based on the [RestartPolicy] of the plugin if and when the stream
+//! is restarted. Messages received until the restart are delivered to the restarted stream,
+//! messages already sent to the crashed stream are lost. Restarted plugins are not
+//! initialized again.
+//!
+//! Every crash, restart and abandoned plugin is reported as [SupervisorEvent] to all
+//! receivers returned by [crate::plugins::Plugins::supervisor_events].
+
+use crate::plugin::{PluginError, PluginInfo, StreamablePlugin};
+use crate::queue::{MessageQueue, QueueConfig};
+use crate::Message;
+use futures::channel::mpsc::{channel, unbounded, Receiver, UnboundedReceiver, UnboundedSender};
+use futures::future::{self, Fuse};
+use futures::stream::Next;
+use futures::{Future, FutureExt, StreamExt};
+use std::cmp::min;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Decides if and when a crashed plugin is restarted.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum RestartPolicy {
+    /// Crashed plugins stay stopped.
+    Never,
+    /// Crashed plugins are restarted immediately.
+    Always,
+    /// Crashed plugins are restarted after `initial`, which is doubled on every consecutive
+    /// crash up to `max`. Plugins running longer than `max` reset the delay to `initial`.
+    ExponentialBackoff { initial: Duration, max: Duration },
+    /// Crashed plugins are restarted immediately unless they were already restarted `max`
+    /// times within `window`.
+    MaxRestarts { max: usize, window: Duration },
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy::Never
+    }
+}
+
+/// Reported by the supervisor of a plugin.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum SupervisorEvent {
+    /// The stream of the plugin returned an error or returned while its input was still open.
+    /// Contains the error returned by the stream if any.
+    Crashed {
+        plugin: PluginInfo,
+        error: Option<String>,
+    },
+    /// The plugin is restarted after `delay`. `attempt` counts all restarts of the plugin.
+    Restarting {
+        plugin: PluginInfo,
+        attempt: usize,
+        delay: Duration,
+    },
+    /// The restart policy doesn't allow another restart. The plugin stays stopped.
+    GaveUp { plugin: PluginInfo, restarts: usize },
+    /// The queue of the plugin overflowed with [crate::queue::OverflowPolicy::Disconnect]. The
+    /// plugin stays stopped.
+    Disconnected { plugin: PluginInfo },
+}
+
+/// Applies a [RestartPolicy] to the crashes of a single plugin.
+#[derive(Debug)]
+struct Restarts {
+    policy: RestartPolicy,
+    count: usize,
+    backoff: Option<Duration>,
+    history: VecDeque<Instant>,
+}
+
+impl Restarts {
+    fn new(policy: RestartPolicy) -> Self {
+        Restarts {
+            policy,
+            count: 0,
+            backoff: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Returns the delay before restarting a plugin which crashed at `now` after running for
+    /// `ran`. Returns `None` if the plugin shouldn't be restarted.
+    fn next(&mut self, now: Instant, ran: Duration) -> Option<Duration> {
+        let delay = match self.policy {
+            RestartPolicy::Never => return None,
+            RestartPolicy::Always => Duration::from_secs(0),
+            RestartPolicy::ExponentialBackoff { initial, max } => {
+                let delay = match self.backoff {
+                    Some(backoff) if ran < max => backoff,
+                    _ => initial,
+                };
+                self.backoff = Some(min(delay * 2, max));
+                delay
+            }
+            RestartPolicy::MaxRestarts { max, window } => {
+                while let Some(restart) = self.history.front() {
+                    if now.duration_since(*restart) < window {
+                        break;
+                    }
+                    self.history.pop_front();
+                }
+                if self.history.len() >= max {
+                    return None;
+                }
+                self.history.push_back(now);
+                Duration::from_secs(0)
+            }
+        };
+        self.count += 1;
+        Some(delay)
+    }
+}
+
+/// Restart policies and queues of all plugins and receivers of their [SupervisorEvent]s.
+#[derive(Debug, Default)]
+pub(crate) struct Supervisor {
+    policy: RestartPolicy,
+    policies: HashMap<String, RestartPolicy>,
+    queue: Option<QueueConfig>,
+    queues: HashMap<String, Option<QueueConfig>>,
+    dropped: Mutex<HashMap<String, Arc<AtomicU64>>>,
+    subscribers: Arc<Mutex<Vec<UnboundedSender<SupervisorEvent>>>>,
+}
+
+impl Supervisor {
+    pub(crate) fn set_policy(&mut self, policy: RestartPolicy) {
+        self.policy = policy;
+    }
+
+    pub(crate) fn set_plugin_policy(&mut self, name: String, policy: RestartPolicy) {
+        self.policies.insert(name, policy);
+    }
+
+    pub(crate) fn set_queue(&mut self, queue: Option<QueueConfig>) {
+        self.queue = queue;
+    }
+
+    pub(crate) fn set_plugin_queue(&mut self, name: String, queue: Option<QueueConfig>) {
+        self.queues.insert(name, queue);
+    }
+
+    /// Returns the number of dropped messages of every plugin which was streamed.
+    pub(crate) fn dropped(&self) -> HashMap<String, u64> {
+        self.dropped
+            .lock()
+            .expect("supervisor lock poisoned")
+            .iter()
+            .map(|(name, dropped)| (name.clone(), dropped.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    pub(crate) fn subscribe(&self) -> UnboundedReceiver<SupervisorEvent> {
+        let (sender, receiver) = unbounded();
+        self.subscribers
+            .lock()
+            .expect("supervisor lock poisoned")
+            .push(sender);
+        receiver
+    }
+
+    /// Returns a future streaming `plugin` with its restart policy and queue until `input` was
+    /// closed.
+    pub(crate) fn supervise<P: StreamablePlugin>(
+        &self,
+        plugin: P,
+        input: Receiver<Message>,
+        output: UnboundedSender<Vec<Message>>,
+    ) -> impl Future<Output = ()> {
+        let info = plugin.info();
+        let policy = self
+            .policies
+            .get(&info.name)
+            .unwrap_or(&self.policy)
+            .clone();
+        let config = *self.queues.get(&info.name).unwrap_or(&self.queue);
+        let dropped = Arc::clone(
+            self.dropped
+                .lock()
+                .expect("supervisor lock poisoned")
+                .entry(info.name.clone())
+                .or_default(),
+        );
+        let supervised = Supervised {
+            plugin,
+            info,
+            restarts: Restarts::new(policy),
+            queue: MessageQueue::new(config, dropped),
+            events: Events(Arc::clone(&self.subscribers)),
+            closed: false,
+        };
+        supervised.run(input, output)
+    }
+}
+
+#[derive(Debug)]
+struct Events(Arc<Mutex<Vec<UnboundedSender<SupervisorEvent>>>>);
+
+impl Events {
+    fn emit(&self, event: SupervisorEvent) {
+        self.0
+            .lock()
+            .expect("supervisor lock poisoned")
+            .retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+    }
+}
+
+/// Reason for a plugin stream to end.
+enum Stop {
+    /// Input of the supervisor was closed.
+    Closed,
+    /// Plugin was disconnected as its queue overflowed.
+    Disconnected,
+    /// Plugin stream returned while its input was still open.
+    Crashed(Option<String>),
+}
+
+/// A plugin and the state of its supervision.
+struct Supervised<P> {
+    plugin: P,
+    info: PluginInfo,
+    restarts: Restarts,
+    queue: MessageQueue,
+    events: Events,
+    /// Input of the supervisor was closed.
+    closed: bool,
+}
+
+impl<P: StreamablePlugin> Supervised<P> {
+    async fn run(mut self, mut input: Receiver<Message>, output: UnboundedSender<Vec<Message>>) {
+        loop {
+            let started = Instant::now();
+            let error = match self.stream(&mut input, &output).await {
+                Stop::Closed => {
+                    self.queue.clear();
+                    return;
+                }
+                Stop::Disconnected => {
+                    warn!(
+                        "Disconnected plugin {} as its queue is full",
+                        self.info.name
+                    );
+                    self.events.emit(SupervisorEvent::Disconnected {
+                        plugin: self.info.clone(),
+                    });
+                    return;
+                }
+                Stop::Crashed(error) => error,
+            };
+
+            error!(
+                "Plugin {} stopped unexpectedly: {}",
+                self.info.name,
+                error.as_deref().unwrap_or("stream returned")
+            );
+            self.events.emit(SupervisorEvent::Crashed {
+                plugin: self.info.clone(),
+                error,
+            });
+
+            let delay = match self.restarts.next(Instant::now(), started.elapsed()) {
+                Some(delay) => delay,
+                None => {
+                    warn!("Not restarting plugin {}", self.info.name);
+                    self.events.emit(SupervisorEvent::GaveUp {
+                        plugin: self.info.clone(),
+                        restarts: self.restarts.count,
+                    });
+                    return;
+                }
+            };
+            info!("Restarting plugin {} in {:?}", self.info.name, delay);
+            self.events.emit(SupervisorEvent::Restarting {
+                plugin: self.info.clone(),
+                attempt: self.restarts.count,
+                delay,
+            });
+
+            // Queue messages until the restart
+            let mut delay = tokio::time::delay_for(delay).fuse();
+            loop {
+                let received = {
+                    let mut next = self.next(&mut input);
+                    futures::select! {
+                        _ = delay => break,
+                        msg = next => msg,
+                    }
+                };
+                match received {
+                    Some(msg) => {
+                        if !self.queue.push(msg) {
+                            self.queue.clear();
+                            self.events.emit(SupervisorEvent::Disconnected {
+                                plugin: self.info.clone(),
+                            });
+                            return;
+                        }
+                    }
+                    None => {
+                        self.queue.clear();
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the next message of `input` if the queue accepts messages.
+    fn next<'a>(&self, input: &'a mut Receiver<Message>) -> Fuse<Next<'a, Receiver<Message>>> {
+        if !self.closed && self.queue.accepts() {
+            input.next().fuse()
+        } else {
+            Fuse::terminated()
+        }
+    }
+
+    /// Streams the plugin until its stream returns. Queued messages are sent to the plugin
+    /// one at a time: the next message is sent after the plugin received the previous one.
+    async fn stream(
+        &mut self,
+        input: &mut Receiver<Message>,
+        output: &UnboundedSender<Vec<Message>>,
+    ) -> Stop {
+        enum Event {
+            Stopped(Result<(), PluginError>),
+            Received(Option<Message>),
+            Ready(bool),
+        }
+
+        // Holds a single message until the plugin received it
+        let (mut sender, receiver) = channel(0);
+        let mut stream = self.plugin.stream(receiver, output.clone()).fuse();
+        // Plugin still receives messages
+        let mut receiving = true;
+        let mut disconnected = false;
+        loop {
+            if receiving && (self.closed || disconnected) && self.queue.is_empty() {
+                // Lets the stream finish
+                sender.close_channel();
+                receiving = false;
+            }
+            let event = {
+                let mut next = self.next(input);
+                let mut ready = if receiving && !self.queue.is_empty() {
+                    future::poll_fn(|cx| sender.poll_ready(cx)).fuse()
+                } else {
+                    Fuse::terminated()
+                };
+                futures::select_biased! {
+                    result = stream => Event::Stopped(result),
+                    ready = ready => Event::Ready(ready.is_ok()),
+                    msg = next => Event::Received(msg),
+                }
+            };
+            match event {
+                Event::Stopped(result) => {
+                    let error = result.err().map(|e| e.to_string());
+                    return if disconnected {
+                        Stop::Disconnected
+                    } else if self.closed {
+                        if let Some(error) = error {
+                            // Input was closed, so the plugin is stopped anyway
+                            error!("Error from plugin {}: {}", self.info.name, error);
+                            self.events.emit(SupervisorEvent::Crashed {
+                                plugin: self.info.clone(),
+                                error: Some(error),
+                            });
+                        }
+                        Stop::Closed
+                    } else {
+                        Stop::Crashed(error)
+                    };
+                }
+                Event::Received(Some(msg)) => {
+                    if disconnected {
+                        self.queue.count_dropped(1);
+                    } else if !self.queue.push(msg) {
+                        disconnected = true;
+                        self.queue.clear();
+                    }
+                }
+                Event::Received(None) => self.closed = true,
+                Event::Ready(true) => {
+                    if let Some(msg) = self.queue.pop() {
+                        if let Err(err) = sender.try_send(msg) {
+                            self.queue.push_front(err.into_inner());
+                        }
+                    }
+                }
+                // Stream stopped receiving and is about to return
+                Event::Ready(false) => receiving = false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::plugin::{PluginError, PluginInfo, StreamablePlugin};
+    use crate::queue::{OverflowPolicy, QueueConfig};
+    use crate::supervisor::{RestartPolicy, Restarts, Supervisor, SupervisorEvent};
+    use crate::Message;
+    use futures::channel::mpsc::{channel, unbounded, Receiver, UnboundedSender};
+    use futures::channel::oneshot;
+    use futures::{SinkExt, StreamExt};
+    use std::fmt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    fn info(name: &str) -> PluginInfo {
+        PluginInfo {
+            name: name.to_string(),
+            version: "".to_string(),
+            authors: "".to_string(),
+            repo: None,
+            commands: vec![],
+            required_rights: Default::default(),
+        }
+    }
+
+    /// Echoes messages and crashes on the first message of its first `crashes` streams.
+    #[derive(Debug)]
+    struct CrashingPlugin {
+        crashes: usize,
+        streams: AtomicUsize,
+    }
+
+    #[derive(Debug)]
+    struct Crash;
+
+    impl fmt::Display for Crash {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "crash")
+        }
+    }
+
+    impl std::error::Error for Crash {}
+
+    #[async_trait]
+    impl StreamablePlugin for CrashingPlugin {
+        async fn stream(
+            &self,
+            mut input: Receiver<Message>,
+            mut output: UnboundedSender<Vec<Message>>,
+        ) -> Result<(), PluginError> {
+            let crash = self.streams.fetch_add(1, Ordering::SeqCst) < self.crashes;
+            while let Some(msg) = input.next().await {
+                if crash {
+                    return Err(Crash.into());
+                }
+                output.send(vec![msg]).await?;
+            }
+            Ok(())
+        }
+
+        fn info(&self) -> PluginInfo {
+            info("Crashing")
+        }
+    }
+
+    /// Echoes messages after `gate` was opened.
+    #[derive(Debug)]
+    struct GatedPlugin {
+        gate: Mutex<Option<oneshot::Receiver<()>>>,
+    }
+
+    #[async_trait]
+    impl StreamablePlugin for GatedPlugin {
+        async fn stream(
+            &self,
+            mut input: Receiver<Message>,
+            mut output: UnboundedSender<Vec<Message>>,
+        ) -> Result<(), PluginError> {
+            let gate = self.gate.lock().unwrap().take();
+            if let Some(gate) = gate {
+                let _ = gate.await;
+            }
+            while let Some(msg) = input.next().await {
+                output.send(vec![msg]).await?;
+            }
+            Ok(())
+        }
+
+        fn info(&self) -> PluginInfo {
+            info("Gated")
+        }
+    }
+
+    #[test]
+    fn test_restart_policies() {
+        let now = Instant::now();
+        let ran = Duration::from_millis(10);
+
+        assert_eq!(Restarts::new(RestartPolicy::Never).next(now, ran), None);
+        assert_eq!(
+            Restarts::new(RestartPolicy::Always).next(now, ran),
+            Some(Duration::from_secs(0))
+        );
+
+        let mut backoff = Restarts::new(RestartPolicy::ExponentialBackoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(3),
+        });
+        assert_eq!(backoff.next(now, ran), Some(Duration::from_secs(1)));
+        assert_eq!(backoff.next(now, ran), Some(Duration::from_secs(2)));
+        assert_eq!(backoff.next(now, ran), Some(Duration::from_secs(3)));
+        assert_eq!(backoff.next(now, ran), Some(Duration::from_secs(3)));
+        // Long running plugins reset the backoff
+        assert_eq!(
+            backoff.next(now, Duration::from_secs(5)),
+            Some(Duration::from_secs(1))
+        );
+
+        let mut max = Restarts::new(RestartPolicy::MaxRestarts {
+            max: 2,
+            window: Duration::from_secs(10),
+        });
+        assert!(max.next(now, ran).is_some());
+        assert!(max.next(now + Duration::from_secs(1), ran).is_some());
+        assert_eq!(max.next(now + Duration::from_secs(2), ran), None);
+        // First restart left the window
+        assert!(max.next(now + Duration::from_secs(10), ran).is_some());
+        assert_eq!(max.count, 3);
+    }
+
+    #[tokio::test]
+    async fn test_supervise() {
+        let mut supervisor = Supervisor::default();
+        supervisor.set_plugin_policy("Crashing".to_string(), RestartPolicy::Always);
+        let mut events = supervisor.subscribe();
+        let plugin = CrashingPlugin {
+            crashes: 2,
+            streams: AtomicUsize::new(0),
+        };
+        let info = plugin.info();
+        let (mut input_sender, input_receiver) = channel(0);
+        let (output_sender, mut output_receiver) = unbounded();
+        let message = Message::Irc(irc_rust::Message::from("PRIVMSG #channel :hello"));
+
+        let supervise = supervisor.supervise(plugin, input_receiver, output_sender);
+        let test = async {
+            for attempt in 1..=2 {
+                // Message crashing the plugin is lost
+                input_sender.send(message.clone()).await.unwrap();
+                assert_eq!(
+                    events.next().await,
+                    Some(SupervisorEvent::Crashed {
+                        plugin: info.clone(),
+                        error: Some("InvocationError: crash".to_string()),
+                    })
+                );
+                assert_eq!(
+                    events.next().await,
+                    Some(SupervisorEvent::Restarting {
+                        plugin: info.clone(),
+                        attempt,
+                        delay: Duration::from_secs(0),
+                    })
+                );
+            }
+            input_sender.send(message.clone()).await.unwrap();
+            assert_eq!(output_receiver.next().await, Some(vec![message.clone()]));
+            input_sender.close_channel();
+        };
+        futures::join!(supervise, test);
+    }
+
+    #[tokio::test]
+    async fn test_gave_up() {
+        let supervisor = Supervisor::default();
+        let mut events = supervisor.subscribe();
+        let plugin = CrashingPlugin {
+            crashes: 1,
+            streams: AtomicUsize::new(0),
+        };
+        let info = plugin.info();
+        let (mut input_sender, input_receiver) = channel(0);
+        let (output_sender, _output_receiver) = unbounded();
+
+        input_sender
+            .try_send(Message::Irc(irc_rust::Message::from("PING")))
+            .unwrap();
+        // Returns without closing the input as the plugin isn't restarted
+        supervisor
+            .supervise(plugin, input_receiver, output_sender)
+            .await;
+
+        assert!(matches!(
+            events.next().await,
+            Some(SupervisorEvent::Crashed { .. })
+        ));
+        assert_eq!(
+            events.next().await,
+            Some(SupervisorEvent::GaveUp {
+                plugin: info,
+                restarts: 0
+            })
+        );
+        assert!(input_sender.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_disconnect() {
+        let mut supervisor = Supervisor::default();
+        supervisor.set_queue(Some(QueueConfig::new(1, OverflowPolicy::Disconnect)));
+        let mut events = supervisor.subscribe();
+        let (gate, gate_receiver) = oneshot::channel();
+        let plugin = GatedPlugin {
+            gate: Mutex::new(Some(gate_receiver)),
+        };
+        let (mut input_sender, input_receiver) = channel(0);
+        let (output_sender, output_receiver) = unbounded();
+        let message = Message::Irc(irc_rust::Message::from("PRIVMSG #channel :hello"));
+
+        let supervise = supervisor.supervise(plugin, input_receiver, output_sender);
+        let test = async {
+            // One message sent to the plugin, one queued and the last one overflows the queue
+            for _ in 0..3 {
+                input_sender.send(message.clone()).await.unwrap();
+            }
+            while supervisor.dropped().get("Gated").cloned().unwrap_or(0) == 0 {
+                tokio::time::delay_for(Duration::from_millis(1)).await;
+            }
+            gate.send(()).unwrap();
+            assert_eq!(
+                events.next().await,
+                Some(SupervisorEvent::Disconnected {
+                    plugin: info("Gated")
+                })
+            );
+        };
+        futures::join!(supervise, test);
+
+        let delivered = output_receiver.collect::<Vec<_>>().await.len() as u64;
+        assert!(delivered <= 1);
+        assert!(supervisor.dropped()["Gated"] >= 2);
+    }
+
+    #[tokio::test]
+    async fn test_closed_before_restart() {
+        let mut supervisor = Supervisor::default();
+        supervisor.set_policy(RestartPolicy::ExponentialBackoff {
+            initial: Duration::from_secs(60),
+            max: Duration::from_secs(60),
+        });
+        let mut events = supervisor.subscribe();
+        let plugin = CrashingPlugin {
+            crashes: 1,
+            streams: AtomicUsize::new(0),
+        };
+        let (mut input_sender, input_receiver) = channel(0);
+        let (output_sender, _output_receiver) = unbounded();
+        let message = Message::Irc(irc_rust::Message::from("PRIVMSG #channel :hello"));
+
+        let supervise = supervisor.supervise(plugin, input_receiver, output_sender);
+        let test = async {
+            input_sender.send(message.clone()).await.unwrap();
+            while !matches!(
+                events.next().await,
+                Some(SupervisorEvent::Restarting { .. })
+            ) {}
+            // Queued until the restart which never happens
+            input_sender.send(message.clone()).await.unwrap();
+            input_sender.send(message.clone()).await.unwrap();
+            input_sender.close_channel();
+        };
+        futures::join!(supervise, test);
+
+        assert_eq!(supervisor.dropped()["Crashing"], 2);
+    }
+
+    /// Echoes messages received by a spawned task.
+    #[derive(Debug)]
+    struct SpawningPlugin;
+
+    #[async_trait]
+    impl StreamablePlugin for SpawningPlugin {
+        async fn stream(
+            &self,
+            mut input: Receiver<Message>,
+            mut output: UnboundedSender<Vec<Message>>,
+        ) -> Result<(), PluginError> {
+            tokio::spawn(async move {
+                while let Some(msg) = input.next().await {
+                    if output.send(vec![msg]).await.is_err() {
+                        break;
+                    }
+                }
+            })
+            .await
+            .unwrap();
+            Ok(())
+        }
+
+        fn info(&self) -> PluginInfo {
+            info("Spawning")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_receive_in_task() {
+        let supervisor = Supervisor::default();
+        let (mut input_sender, input_receiver) = channel(0);
+        let (output_sender, output_receiver) = unbounded();
+        let message = Message::Irc(irc_rust::Message::from("PRIVMSG #channel :hello"));
+
+        let supervise = supervisor.supervise(SpawningPlugin, input_receiver, output_sender);
+        let test = async {
+            for _ in 0..3 {
+                input_sender.send(message.clone()).await.unwrap();
+            }
+            input_sender.close_channel();
+        };
+        futures::join!(supervise, test);
+
+        assert_eq!(output_receiver.collect::<Vec<_>>().await.len(), 3);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::auth::{Authenticator, Credentials, UserInfo, ValidationError};
+use crate::profile::config::{self, Config};
 use crate::utils::rand_alphanumeric;
 use chrono::{Duration, Local};
 use core::fmt;
@@ -26,17 +27,8 @@ pub const ENV_TWITCH_USER_INFO: &str = "BRS_TWITCH_USERINFO";
 pub const ENV_TWITCH_SCOPES: &str = "BRS_TWITCH_SCOPES";
 const TWITCH_OAUTH_HANDLER_SCRIPT: &str = include_str!("twitch_oauth.html");
 
-static REDIRECT_URI: &str = "http://localhost:4334/";
-static DEFAULT_SCOPES: [&str; 8] = [
-    "channel:moderate",
-    "chat:edit",
-    "chat:read",
-    "user:edit:follows",
-    "user_follows_edit",
-    "user:edit",
-    "user_read",
-    "whispers:edit",
-];
+static REDIRECT_URI: &str = config::DEFAULT_TWITCH_REDIRECT_URI;
+static DEFAULT_SCOPES: [&str; 8] = config::DEFAULT_TWITCH_SCOPES;
 
 type AuthMutex = Arc<(Mutex<Option<Credentials>>, Condvar)>;
 
@@ -194,34 +186,50 @@ impl AuthRequest {
     /// Creates a new [AuthRequest] with given client information and by fetching auth information
     /// from the environment ([ENV_TWITCH_AUTH] defaults to `"token"`, [ENV_TWITCH_SCOPES] defaults to [DEFAULT_SCOPES]).
     pub fn new(client_id: String, client_secret: Option<String>) -> Self {
-        let auth_type = std::env::var(ENV_TWITCH_AUTH).unwrap_or_else(|arg| {
-            warn!("Error fetching envvar: {}", arg);
-            "token".to_string()
-        });
-        let scope = std::env::var(ENV_TWITCH_SCOPES)
-            .map(|val| {
-                let scopes: Vec<&str> = val.split(',').collect::<Vec<_>>();
-                scopes.iter().map(|scope| scope.to_string()).collect()
+        let mut config = Config::default();
+        config.add_env();
+        Self::with_config(client_id, client_secret, &config)
+    }
+
+    /// Creates a new [AuthRequest] with given client information and the auth information of
+    /// `config` (see [config::KEY_TWITCH_AUTH] and [config::KEY_TWITCH_SCOPES]).
+    pub fn with_config(client_id: String, client_secret: Option<String>, config: &Config) -> Self {
+        let auth_type = config
+            .get_as::<String>(config::KEY_TWITCH_AUTH)
+            .unwrap_or_else(|why| {
+                warn!("Invalid {}: {}", config::KEY_TWITCH_AUTH, why);
+                None
             })
-            .unwrap_or_else(|arg| {
-                warn!("Error fetchng envvar: {}", arg);
+            .unwrap_or_else(|| config::DEFAULT_TWITCH_AUTH.to_string());
+        let scope = config
+            .get_as::<Vec<String>>(config::KEY_TWITCH_SCOPES)
+            .unwrap_or_else(|why| {
+                warn!("Invalid {}: {}", config::KEY_TWITCH_SCOPES, why);
+                None
+            })
+            .unwrap_or_else(|| {
                 DEFAULT_SCOPES
                     .iter()
                     .map(|scope| scope.to_string())
                     .collect()
             });
+        let redirect_uri = config
+            .get_as::<String>(config::KEY_TWITCH_REDIRECT_URI)
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| REDIRECT_URI.to_string());
 
         match auth_type.as_str() {
             "token" => AuthRequest::ImplicitCode {
                 client_id,
-                redirect_uri: REDIRECT_URI.to_string(),
+                redirect_uri,
                 scope,
                 state: rand_alphanumeric(30),
                 force_verify: true,
             },
             "code" => AuthRequest::AuthorizationCode {
                 client_id,
-                redirect_uri: REDIRECT_URI.to_string(),
+                redirect_uri,
                 scope,
                 state: rand_alphanumeric(30),
                 force_verify: true,
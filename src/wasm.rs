@@ -0,0 +1,539 @@
+//! Plugins compiled to WebAssembly.
+//!
+//! WebAssembly plugins are platform independent and run sandboxed: the plugin-loader doesn't
+//! provide any imports to the module, so plugins have no access to the filesystem, network or
+//! environment of the host. Modules requiring imports (e.g. WASI) fail to load.
+//!
+//! Plugin files end with `.wasm` and have to export the following items:
+//!
+//! - `memory`: The linear memory used to exchange data.
+//! - `botrs_alloc(len: i32) -> i32`: Allocates `len` bytes and returns a pointer to them.
+//! - `botrs_dealloc(ptr: i32, len: i32)`: Frees memory allocated by `botrs_alloc` or returned
+//!   by `botrs_info` and `botrs_call`.
+//! - `botrs_info() -> i64`: Returns the [PluginInfo] of the plugin as JSON.
+//! - `botrs_call(ptr: i32, len: i32) -> i64`: Handles the [Message] serialized as JSON at
+//!   `ptr` and returns its results (`Vec<Message>`) as JSON. The plugin-loader frees the
+//!   message after the call returned.
+//!
+//! Plugins may also export `botrs_init(ptr: i32, len: i32)`. It's called once with the profile
+//! owning the plugin serialized as JSON at `ptr` if the plugin is loaded for a profile. Every
+//! profile instantiates the module separately.
+//!
+//! Returned data is passed as pointer in the upper and length in the lower 32 bits of the
+//! `i64`. The plugin-loader frees it with `botrs_dealloc` after reading it.
+//!
+//! Every call into the module may execute at most [DEFAULT_FUEL] instructions (see
+//! [WasmPlugin::with_fuel]). Calls exceeding it are aborted and fail with a [WasmError], so
+//! endless loops don't block the plugin-loader.
+
+use crate::context::Context;
+use crate::plugin::{PluginError, PluginInfo, StreamablePlugin};
+use crate::Message;
+use futures::channel::mpsc::{Receiver, UnboundedSender};
+use futures::{SinkExt, StreamExt};
+use loupe::{MemoryUsage, MemoryUsageTracker};
+use serde::de::DeserializeOwned;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::{fs, io};
+use wasmer::wasmparser::{Operator, Type as BlockType, TypeOrFuncType};
+use wasmer::{
+    imports, CompilerConfig, Cranelift, FunctionMiddleware, Global, Instance, Memory,
+    MiddlewareError, MiddlewareReaderState, Module, ModuleMiddleware, NativeFunc, Store, Universal,
+    Value,
+};
+use wasmer_types::{
+    ExportIndex, GlobalIndex, GlobalInit, GlobalType, LocalFunctionIndex, ModuleInfo, Mutability,
+    Type,
+};
+
+/// Extension of WebAssembly plugin files.
+pub const WASM_EXTENSION: &str = "wasm";
+
+/// Number of instructions a call into a module may execute by default.
+pub const DEFAULT_FUEL: u64 = 100_000_000;
+
+/// Exported global containing the fuel left for the current call.
+const FUEL_GLOBAL: &str = "botrs_fuel";
+/// Exported global set to `1` once the fuel of a call was exhausted.
+const EXHAUSTED_GLOBAL: &str = "botrs_fuel_exhausted";
+
+/// Returns if the file at `path` is a WebAssembly plugin based on its extension.
+pub fn is_wasm(path: &Path) -> bool {
+    path.extension()
+        .map(|extension| extension == WASM_EXTENSION)
+        .unwrap_or(false)
+}
+
+/// Error raised by a WebAssembly plugin or while exchanging data with it.
+#[derive(Debug, Clone)]
+pub struct WasmError(pub String);
+
+impl Display for WasmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "WasmError: {}", self.0)
+    }
+}
+
+impl Error for WasmError {}
+
+fn wasm_error<E: Display>(context: &'static str) -> impl Fn(E) -> WasmError {
+    move |why| WasmError(format!("{}: {}", context, why))
+}
+
+/// Middleware adding the [FUEL_GLOBAL] and [EXHAUSTED_GLOBAL] to a module and charging every
+/// instruction one unit of fuel. Can only be used for a single module.
+#[derive(Debug, Default)]
+struct Metering {
+    globals: Mutex<Option<(GlobalIndex, GlobalIndex)>>,
+}
+
+impl MemoryUsage for Metering {
+    fn size_of_val(&self, _: &mut dyn MemoryUsageTracker) -> usize {
+        std::mem::size_of_val(self)
+    }
+}
+
+impl ModuleMiddleware for Metering {
+    fn generate_function_middleware(&self, _: LocalFunctionIndex) -> Box<dyn FunctionMiddleware> {
+        let (fuel, exhausted) = self
+            .globals
+            .lock()
+            .expect("metering lock poisoned")
+            .expect("metering globals not added to the module");
+        Box::new(FunctionMetering {
+            fuel,
+            exhausted,
+            cost: 0,
+        })
+    }
+
+    fn transform_module_info(&self, module: &mut ModuleInfo) {
+        let mut add_global = |name: &str, ty: Type, init: GlobalInit| {
+            let index = module.globals.push(GlobalType::new(ty, Mutability::Var));
+            module.global_initializers.push(init);
+            module
+                .exports
+                .insert(name.to_string(), ExportIndex::Global(index));
+            index
+        };
+        let fuel = add_global(FUEL_GLOBAL, Type::I64, GlobalInit::I64Const(0));
+        let exhausted = add_global(EXHAUSTED_GLOBAL, Type::I32, GlobalInit::I32Const(0));
+        *self.globals.lock().expect("metering lock poisoned") = Some((fuel, exhausted));
+    }
+}
+
+/// Charges the instructions of a function before every instruction which may change the
+/// control flow.
+#[derive(Debug)]
+struct FunctionMetering {
+    fuel: GlobalIndex,
+    exhausted: GlobalIndex,
+    /// Instructions executed since the last charge.
+    cost: u64,
+}
+
+impl FunctionMiddleware for FunctionMetering {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        self.cost += 1;
+        let branches = matches!(
+            operator,
+            Operator::Block { .. }
+                | Operator::Loop { .. }
+                | Operator::If { .. }
+                | Operator::Else
+                | Operator::End
+                | Operator::Br { .. }
+                | Operator::BrIf { .. }
+                | Operator::BrTable { .. }
+                | Operator::Return
+                | Operator::Unreachable
+                | Operator::Call { .. }
+                | Operator::CallIndirect { .. }
+        );
+        if branches {
+            let fuel = self.fuel.as_u32();
+            let cost = self.cost as i64;
+            state.extend(&[
+                // Traps if less fuel than the cost is left
+                Operator::GlobalGet { global_index: fuel },
+                Operator::I64Const { value: cost },
+                Operator::I64LtU,
+                Operator::If {
+                    ty: TypeOrFuncType::Type(BlockType::EmptyBlockType),
+                },
+                Operator::I32Const { value: 1 },
+                Operator::GlobalSet {
+                    global_index: self.exhausted.as_u32(),
+                },
+                Operator::Unreachable,
+                Operator::End,
+                // Otherwise charges the cost
+                Operator::GlobalGet { global_index: fuel },
+                Operator::I64Const { value: cost },
+                Operator::I64Sub,
+                Operator::GlobalSet { global_index: fuel },
+            ]);
+            self.cost = 0;
+        }
+        state.push_operator(operator);
+        Ok(())
+    }
+}
+
+/// Instantiated module and its exports.
+struct WasmInstance {
+    memory: Memory,
+    alloc: NativeFunc<i32, i32>,
+    dealloc: NativeFunc<(i32, i32), ()>,
+    info: NativeFunc<(), i64>,
+    call: NativeFunc<(i32, i32), i64>,
+    init: Option<NativeFunc<(i32, i32), ()>>,
+    fuel: Global,
+    exhausted: Global,
+    /// Fuel of every call.
+    budget: u64,
+    // Keeps the exports alive
+    _instance: Instance,
+}
+
+impl WasmInstance {
+    fn new(bytes: &[u8]) -> Result<Self, WasmError> {
+        let mut compiler = Cranelift::default();
+        compiler.push_middleware(Arc::new(Metering::default()));
+        let store = Store::new(&Universal::new(compiler).engine());
+        let module = Module::new(&store, bytes).map_err(wasm_error("invalid module"))?;
+        // No imports to keep the plugin sandboxed
+        let instance =
+            Instance::new(&module, &imports! {}).map_err(wasm_error("failed to instantiate"))?;
+        let exports = &instance.exports;
+        let missing = wasm_error("missing export");
+        Ok(WasmInstance {
+            memory: exports.get_memory("memory").map_err(&missing)?.clone(),
+            alloc: exports
+                .get_native_function("botrs_alloc")
+                .map_err(&missing)?,
+            dealloc: exports
+                .get_native_function("botrs_dealloc")
+                .map_err(&missing)?,
+            info: exports
+                .get_native_function("botrs_info")
+                .map_err(&missing)?,
+            call: exports
+                .get_native_function("botrs_call")
+                .map_err(&missing)?,
+            init: exports.get_native_function("botrs_init").ok(),
+            fuel: exports.get_global(FUEL_GLOBAL).map_err(&missing)?.clone(),
+            exhausted: exports
+                .get_global(EXHAUSTED_GLOBAL)
+                .map_err(&missing)?
+                .clone(),
+            budget: DEFAULT_FUEL,
+            _instance: instance,
+        })
+    }
+
+    fn info(&self) -> Result<PluginInfo, WasmError> {
+        self.metered(|| {
+            let packed = self.info.call().map_err(wasm_error("botrs_info failed"))?;
+            self.read(packed)
+        })
+    }
+
+    fn call(&self, message: &[u8]) -> Result<Vec<Message>, WasmError> {
+        self.metered(|| {
+            let packed = self.pass(message, |ptr, len| {
+                self.call
+                    .call(ptr, len)
+                    .map_err(wasm_error("botrs_call failed"))
+            })?;
+            self.read(packed)
+        })
+    }
+
+    /// Calls `botrs_init` with `profile` if the module exports it.
+    fn init(&self, profile: &[u8]) -> Result<(), WasmError> {
+        match &self.init {
+            Some(init) => self.metered(|| {
+                self.pass(profile, |ptr, len| {
+                    init.call(ptr, len).map_err(wasm_error("botrs_init failed"))
+                })
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs `f` with the fuel of a single call.
+    fn metered<T, F>(&self, f: F) -> Result<T, WasmError>
+    where
+        F: FnOnce() -> Result<T, WasmError>,
+    {
+        let set = wasm_error("failed to set fuel");
+        // Compared unsigned by the module
+        self.fuel
+            .set(Value::I64(self.budget as i64))
+            .map_err(&set)?;
+        self.exhausted.set(Value::I32(0)).map_err(&set)?;
+        let result = f();
+        if self.exhausted.get().i32() == Some(1) {
+            return Err(WasmError(format!(
+                "fuel of {} instructions exhausted",
+                self.budget
+            )));
+        }
+        result
+    }
+
+    /// Copies `bytes` into memory allocated by the module and calls `f` with their pointer and
+    /// length. The memory is freed after `f` returned.
+    fn pass<T, F>(&self, bytes: &[u8], f: F) -> Result<T, WasmError>
+    where
+        F: FnOnce(i32, i32) -> Result<T, WasmError>,
+    {
+        let len = bytes.len() as i32;
+        let ptr = self
+            .alloc
+            .call(len)
+            .map_err(wasm_error("botrs_alloc failed"))?;
+        self.write(ptr, bytes)?;
+        let result = f(ptr, len);
+        self.dealloc
+            .call(ptr, len)
+            .map_err(wasm_error("botrs_dealloc failed"))?;
+        result
+    }
+
+    fn write(&self, ptr: i32, bytes: &[u8]) -> Result<(), WasmError> {
+        // Safe as the instance isn't running while its memory is accessed
+        let memory = unsafe { self.memory.data_unchecked_mut() };
+        memory
+            .get_mut(ptr as u32 as usize..ptr as u32 as usize + bytes.len())
+            .ok_or_else(|| WasmError("botrs_alloc returned invalid pointer".to_string()))?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Deserializes and frees the data referenced by `packed`.
+    fn read<T: DeserializeOwned>(&self, packed: i64) -> Result<T, WasmError> {
+        let ptr = (packed >> 32) as i32;
+        let len = packed as i32;
+        let start = ptr as u32 as usize;
+        let end = start + len as u32 as usize;
+        // Safe as the instance isn't running while its memory is accessed
+        let result = match unsafe { self.memory.data_unchecked() }.get(start..end) {
+            Some(bytes) => serde_json::from_slice(bytes).map_err(wasm_error("invalid response")),
+            None => Err(WasmError("returned invalid pointer".to_string())),
+        };
+        self.dealloc
+            .call(ptr, len)
+            .map_err(wasm_error("botrs_dealloc failed"))?;
+        result
+    }
+}
+
+/// [StreamablePlugin] calling a WebAssembly module. Messages are processed one at a time.
+pub struct WasmPlugin {
+    instance: Arc<Mutex<WasmInstance>>,
+    info: PluginInfo,
+}
+
+impl fmt::Debug for WasmPlugin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WasmPlugin")
+            .field("info", &self.info)
+            .finish()
+    }
+}
+
+impl WasmPlugin {
+    /// Compiles and instantiates the plugin file at `path`.
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        WasmPlugin::new(&bytes).map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+    }
+
+    /// Compiles and instantiates the module contained in `bytes`. The module may be in binary or
+    /// text format.
+    pub fn new(bytes: &[u8]) -> Result<Self, WasmError> {
+        let instance = WasmInstance::new(bytes)?;
+        let info = instance.info()?;
+        Ok(WasmPlugin {
+            instance: Arc::new(Mutex::new(instance)),
+            info,
+        })
+    }
+
+    /// Sets the number of instructions every call into the module may execute. Defaults to
+    /// [DEFAULT_FUEL].
+    pub fn with_fuel(self, fuel: u64) -> Self {
+        self.instance
+            .lock()
+            .expect("wasm instance lock poisoned")
+            .budget = fuel;
+        self
+    }
+
+    /// Passes the profile of `context` to `botrs_init` if the module exports it.
+    pub fn with_context(self, context: &Context) -> Result<Self, WasmError> {
+        let profile =
+            serde_json::to_vec(context.profile()).map_err(wasm_error("invalid profile"))?;
+        self.instance
+            .lock()
+            .expect("wasm instance lock poisoned")
+            .init(&profile)?;
+        Ok(self)
+    }
+}
+
+#[async_trait]
+impl StreamablePlugin for WasmPlugin {
+    async fn stream(
+        &self,
+        mut input: Receiver<Message>,
+        mut output: UnboundedSender<Vec<Message>>,
+    ) -> Result<(), PluginError> {
+        while let Some(msg) = input.next().await {
+            let message = serde_json::to_vec(&msg).map_err(wasm_error("invalid message"))?;
+            let instance = Arc::clone(&self.instance);
+            let results = tokio::task::spawn_blocking(move || {
+                let instance = instance.lock().expect("wasm instance lock poisoned");
+                instance.call(&message)
+            })
+            .await
+            .map_err(wasm_error("plugin call failed"))??;
+            output.send(results).await?;
+        }
+        Ok(())
+    }
+
+    fn info(&self) -> PluginInfo {
+        self.info.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::Context;
+    use crate::plugin::StreamablePlugin;
+    use crate::profile::Profile;
+    use crate::wasm::WasmPlugin;
+    use crate::Message;
+    use futures::channel::mpsc::{channel, unbounded};
+    use futures::StreamExt;
+
+    const INFO: &str =
+        r#"{"name":"Echo","version":"0.1.0","authors":"","repo":null,"commands":[]}"#;
+
+    /// Module returning every message wrapped in a JSON array.
+    fn echo_module() -> String {
+        format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (global $next (mut i32) (i32.const 1024))
+                (data (i32.const 0) "{info}")
+                (func $alloc (export "botrs_alloc") (param $len i32) (result i32)
+                    (local $ptr i32)
+                    (local.set $ptr (global.get $next))
+                    (global.set $next (i32.add (global.get $next) (local.get $len)))
+                    (local.get $ptr))
+                (func (export "botrs_dealloc") (param i32 i32))
+                (func (export "botrs_info") (result i64)
+                    (i64.const {len}))
+                (func (export "botrs_call") (param $ptr i32) (param $len i32) (result i64)
+                    (local $out i32)
+                    (local.set $out (call $alloc (i32.add (local.get $len) (i32.const 2))))
+                    (i32.store8 (local.get $out) (i32.const 91))
+                    (memory.copy
+                        (i32.add (local.get $out) (i32.const 1))
+                        (local.get $ptr)
+                        (local.get $len))
+                    (i32.store8
+                        (i32.add (i32.add (local.get $out) (local.get $len)) (i32.const 1))
+                        (i32.const 93))
+                    (i64.or
+                        (i64.shl (i64.extend_i32_u (local.get $out)) (i64.const 32))
+                        (i64.extend_i32_u (i32.add (local.get $len) (i32.const 2))))))"#,
+            info = INFO.replace('"', "\\\""),
+            len = INFO.len()
+        )
+    }
+
+    #[tokio::test]
+    async fn test_echo() {
+        let plugin = WasmPlugin::new(echo_module().as_bytes()).unwrap();
+        assert_eq!(plugin.info().name, "Echo");
+
+        let (mut input_sender, input_receiver) = channel(0);
+        let (output_sender, output_receiver) = unbounded();
+        let message = Message::Irc(irc_rust::Message::from("PRIVMSG #channel :hello"));
+
+        input_sender.try_send(message.clone()).unwrap();
+        input_sender.close_channel();
+        plugin.stream(input_receiver, output_sender).await.unwrap();
+
+        let results = output_receiver.collect::<Vec<_>>().await;
+        assert_eq!(results, vec![vec![message]]);
+    }
+
+    #[test]
+    fn test_init() {
+        let context = Context::new(Profile::new(
+            "foo".to_string(),
+            Vec::new(),
+            String::new(),
+            Default::default(),
+            None,
+        ));
+        // botrs_init is optional
+        let plugin = WasmPlugin::new(echo_module().as_bytes()).unwrap();
+        assert!(plugin.with_context(&context).is_ok());
+
+        let module = echo_module().replace(
+            "(func (export \"botrs_dealloc\")",
+            "(func (export \"botrs_init\") (param i32 i32) unreachable)\n(func (export \"botrs_dealloc\")",
+        );
+        let plugin = WasmPlugin::new(module.as_bytes()).unwrap();
+        assert!(plugin.with_context(&context).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fuel() {
+        let module = echo_module().replace(
+            "(func (export \"botrs_info\") (result i64)",
+            "(func (export \"botrs_info\") (result i64) (loop (br 0))",
+        );
+        let error = WasmPlugin::new(module.as_bytes()).unwrap_err();
+        assert!(error.0.contains("fuel"), "{}", error);
+
+        let plugin = WasmPlugin::new(echo_module().as_bytes())
+            .unwrap()
+            .with_fuel(10);
+        let (mut input_sender, input_receiver) = channel(0);
+        let (output_sender, _output_receiver) = unbounded();
+        input_sender
+            .try_send(Message::Irc(irc_rust::Message::from(
+                "PRIVMSG #channel :hello",
+            )))
+            .unwrap();
+        input_sender.close_channel();
+        let error = plugin
+            .stream(input_receiver, output_sender)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("fuel"), "{}", error);
+    }
+
+    #[test]
+    fn test_sandboxed() {
+        let module = r#"(module
+            (import "env" "exit" (func (param i32)))
+            (memory (export "memory") 1))"#;
+        assert!(WasmPlugin::new(module.as_bytes()).is_err());
+    }
+}